@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::os::unix::prelude::FileExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use mini_bitcask::storage::db_file::DBFile;
+use mini_bitcask::storage::entry::{Entry, ENTRY_HEADER_SIZE};
+use mini_bitcask::utils::hash_routine;
+
+const ENTRIES: u32 = 200_000;
+const RANDOM_READS: usize = 200_000;
+
+fn prepare(dir: &str) -> Vec<u64> {
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    File::create(Path::new(dir).join("1.data")).unwrap();
+    let mut file = DBFile::new(dir.to_string(), 1).unwrap();
+    let mut offsets = Vec::with_capacity(ENTRIES as usize);
+    for i in 0..ENTRIES {
+        let key = format!("key-{:08}", i).into_bytes();
+        let value = format!("value-{:08}-{}", i, "x".repeat((i % 64) as usize)).into_bytes();
+        offsets.push(file.offset);
+        assert!(file.write(Entry::new(key, value, 0, 0)));
+    }
+    file.close();
+    offsets
+}
+
+fn report(name: &str, ops: usize, elapsed: Duration) {
+    let per_op = elapsed.as_nanos() as f64 / ops as f64;
+    println!("{:<24} {:>10.2} ms {:>10.1} ns/op", name, elapsed.as_secs_f64() * 1000.0, per_op);
+}
+
+// the read path before mmap, kept as the baseline: every field gets a zero
+// filled buffer and a read_at, and every read stats the file for its bounds
+fn legacy_read_buf(file: &File, offset: u64, len: u64) -> Option<Vec<u8>> {
+    if offset >= file.metadata().unwrap().len() {
+        return None;
+    }
+    let mut buf = vec![0; len as usize];
+    file.read_at(&mut buf, offset).ok()?;
+    Some(buf)
+}
+
+fn legacy_read(file: &File, mut offset: u64) -> Option<Entry<'static>> {
+    let buf = legacy_read_buf(file, offset, ENTRY_HEADER_SIZE)?;
+    let mut entry = Entry::decode_header(&buf)?;
+    offset += ENTRY_HEADER_SIZE;
+    entry.key = Cow::Owned(legacy_read_buf(file, offset, entry.key_size as u64)?);
+    offset += entry.key_size as u64;
+    entry.value = Cow::Owned(legacy_read_buf(file, offset, entry.value_size as u64)?);
+    entry.valid = hash_routine::encode_vec_u8(&entry.value, 32) as u32 == entry.crc32;
+    Some(entry)
+}
+
+fn replay_legacy(dir: &str) -> Duration {
+    let file = File::open(Path::new(dir).join("1.data")).unwrap();
+    let start = Instant::now();
+    let mut offset = 0;
+    let mut count = 0;
+    while let Some(entry) = legacy_read(&file, offset) {
+        offset += entry.size();
+        count += 1;
+    }
+    assert_eq!(count, ENTRIES);
+    start.elapsed()
+}
+
+fn replay_pread(dir: &str) -> Duration {
+    let file = DBFile::new(dir.to_string(), 1).unwrap();
+    let start = Instant::now();
+    let mut offset = 0;
+    let mut count = 0;
+    while let Some(entry) = file.read(offset) {
+        offset += entry.size();
+        count += 1;
+    }
+    assert_eq!(count, ENTRIES);
+    start.elapsed()
+}
+
+fn replay_buffered(dir: &str) -> Duration {
+    let file = DBFile::new(dir.to_string(), 1).unwrap();
+    let start = Instant::now();
+    assert_eq!(file.iter().count(), ENTRIES as usize);
+    start.elapsed()
+}
+
+fn replay_mmap(dir: &str) -> Duration {
+    let mut file = DBFile::open_archived(dir.to_string(), 1).unwrap();
+    assert!(file.map());
+    let start = Instant::now();
+    assert_eq!(file.iter().count(), ENTRIES as usize);
+    start.elapsed()
+}
+
+fn random_offsets(offsets: &[u64]) -> Vec<u64> {
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..RANDOM_READS).map(|_| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        offsets[(seed % offsets.len() as u64) as usize]
+    }).collect()
+}
+
+fn get_legacy(dir: &str, offsets: &[u64]) -> Duration {
+    let file = File::open(Path::new(dir).join("1.data")).unwrap();
+    let start = Instant::now();
+    for offset in offsets {
+        assert!(legacy_read(&file, *offset).unwrap().valid);
+    }
+    start.elapsed()
+}
+
+fn get_pread(dir: &str, offsets: &[u64]) -> Duration {
+    let file = DBFile::new(dir.to_string(), 1).unwrap();
+    let start = Instant::now();
+    for offset in offsets {
+        assert!(file.read(*offset).unwrap().valid);
+    }
+    start.elapsed()
+}
+
+fn get_mmap(dir: &str, offsets: &[u64]) -> Duration {
+    let mut file = DBFile::open_archived(dir.to_string(), 1).unwrap();
+    assert!(file.map());
+    let start = Instant::now();
+    for offset in offsets {
+        assert!(file.read(*offset).unwrap().valid);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let dir = env::temp_dir().join(format!("mini-bitcask-bench-{}", std::process::id()));
+    let dir = dir.to_str().unwrap().to_string();
+    let offsets = prepare(&dir);
+    let random = random_offsets(&offsets);
+
+    report("replay legacy", ENTRIES as usize, replay_legacy(&dir));
+    report("replay pread", ENTRIES as usize, replay_pread(&dir));
+    report("replay buffered", ENTRIES as usize, replay_buffered(&dir));
+    report("replay mmap", ENTRIES as usize, replay_mmap(&dir));
+    report("random read legacy", RANDOM_READS, get_legacy(&dir, &random));
+    report("random read pread", RANDOM_READS, get_pread(&dir, &random));
+    report("random read mmap", RANDOM_READS, get_mmap(&dir, &random));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
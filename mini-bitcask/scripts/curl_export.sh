@@ -0,0 +1 @@
+curl 127.0.0.1:3010/export
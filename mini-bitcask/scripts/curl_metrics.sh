@@ -0,0 +1,2 @@
+curl 127.0.0.1:3010/metrics
+echo ""
\ No newline at end of file
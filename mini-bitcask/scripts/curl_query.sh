@@ -0,0 +1,2 @@
+curl -X POST -H "Content-Type: application/json" -d '{"index": "owner", "value": "alice"}' 127.0.0.1:3010/index/query
+echo ""
@@ -0,0 +1,7 @@
+txn=$(curl -s -X POST 127.0.0.1:3010/txn/begin | sed 's/.*"data":\([0-9]*\).*/\1/')
+curl -X POST -H "Content-Type: application/json" -d "{\"txn\": $txn, \"key\": \"test_key\"}" 127.0.0.1:3010/txn/get
+echo ""
+curl -X POST -H "Content-Type: application/json" -d "{\"txn\": $txn, \"key\": \"test_key\", \"value\": \"test_value\"}" 127.0.0.1:3010/txn/set
+echo ""
+curl -X POST -H "Content-Type: application/json" -d "{\"txn\": $txn}" 127.0.0.1:3010/txn/commit
+echo ""
@@ -1,9 +1,204 @@
 use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::str::FromStr;
 
-#[derive(Default)]
+use crate::ds::index;
+use crate::storage::entry;
+
+const ENV_PREFIX: &str = "BITCASK_";
+
+// every name `Config::set` takes
+pub const SETTINGS: [&str; 15] = [
+    "data_dir",
+    "bind",
+    "max_file_size",
+    "sync",
+    "max_key_size",
+    "max_value_size",
+    "queue_capacity",
+    "max_open_files",
+    "cache_capacity",
+    "max_data_size",
+    "client_requests_per_sec",
+    "client_bytes_per_sec",
+    "indexes",
+    "merge_dead_ratio",
+    "merge_min_dead_bytes",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    #[default]
+    Never,
+    Always,
+    Interval(u64),
+}
+
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(SyncPolicy::Never),
+            "always" => Ok(SyncPolicy::Always),
+            _ => {
+                let millis = s.strip_prefix("interval:").and_then(|ms| ms.parse::<u64>().ok());
+                match millis {
+                    Some(millis) if millis > 0 => Ok(SyncPolicy::Interval(millis)),
+                    _ => Err(format!("invalid sync policy `{}`, expected never, always or interval:<ms>", s)),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Config {
     pub dir_path: String,
-    pub max_file_size: u32,
+    pub bind_addr: SocketAddr,
+    pub max_file_size: u64,
+    pub sync_policy: SyncPolicy,
+    pub max_key_size: usize,
+    pub max_value_size: usize,
+    pub queue_capacity: usize,
+    pub max_open_files: usize,
+    // bytes of keys and values kept in the read cache, 0 disables it
+    pub cache_capacity: usize,
+    // total bytes of data files, 0 means unlimited
+    pub max_data_size: u64,
+    // per client address limits, 0 means unlimited
+    pub client_requests_per_sec: u64,
+    pub client_bytes_per_sec: u64,
+    // dot separated json paths into values that get a secondary index
+    pub indexes: Vec<String>,
+    // archived files are merged after a rotation once their dead bytes reach
+    // both the ratio and the absolute threshold
+    pub merge_dead_ratio: f64,
+    pub merge_min_dead_bytes: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dir_path: String::new(),
+            bind_addr: SocketAddr::from(([127, 0, 0, 1], 3010)),
+            max_file_size: 16 * 1024 * 1024,
+            sync_policy: SyncPolicy::Never,
+            max_key_size: 100,
+            max_value_size: 100,
+            queue_capacity: 32,
+            max_open_files: 256,
+            cache_capacity: 16 * 1024 * 1024,
+            indexes: vec![],
+            max_data_size: 0,
+            client_requests_per_sec: 0,
+            client_bytes_per_sec: 0,
+            merge_dead_ratio: 0.5,
+            merge_min_dead_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Config {
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "data_dir" => self.dir_path = value.to_string(),
+            "bind" => self.bind_addr = parse(name, value)?,
+            "max_file_size" => self.max_file_size = parse(name, value)?,
+            "sync" => self.sync_policy = value.parse()?,
+            "max_key_size" => self.max_key_size = parse(name, value)?,
+            "max_value_size" => self.max_value_size = parse(name, value)?,
+            "queue_capacity" => self.queue_capacity = parse(name, value)?,
+            "max_open_files" => self.max_open_files = parse(name, value)?,
+            "cache_capacity" => self.cache_capacity = parse(name, value)?,
+            "max_data_size" => self.max_data_size = parse(name, value)?,
+            "client_requests_per_sec" => self.client_requests_per_sec = parse(name, value)?,
+            "client_bytes_per_sec" => self.client_bytes_per_sec = parse(name, value)?,
+            "indexes" => {
+                self.indexes = value.split(',')
+                    .map(|path| path.trim().to_string())
+                    .filter(|path| !path.is_empty())
+                    .collect();
+            }
+            "merge_dead_ratio" => self.merge_dead_ratio = parse(name, value)?,
+            "merge_min_dead_bytes" => self.merge_min_dead_bytes = parse(name, value)?,
+            _ => return Err(format!("unknown setting `{}`", name)),
+        }
+        Ok(())
+    }
+
+    // only looks, never touches the disk; a data_dir that doesn't exist yet
+    // is created when the store opens
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dir_path.is_empty() {
+            return Err("data_dir must not be empty".to_string());
+        }
+        if let Ok(metadata) = fs::metadata(&self.dir_path) {
+            if !metadata.is_dir() || metadata.permissions().readonly() {
+                return Err(format!("data_dir `{}` is not a writable directory", self.dir_path));
+            }
+        }
+        if self.max_file_size == 0 {
+            return Err("max_file_size must be greater than 0".to_string());
+        }
+        if self.max_key_size == 0 {
+            return Err("max_key_size must be greater than 0".to_string());
+        }
+        if self.queue_capacity == 0 {
+            return Err("queue_capacity must be greater than 0".to_string());
+        }
+        if self.max_open_files == 0 {
+            return Err("max_open_files must be greater than 0".to_string());
+        }
+        // a single write has to fit in one second's worth of the budget
+        let largest_entry = entry::ENTRY_HEADER_SIZE + (self.max_key_size + self.max_value_size) as u64;
+        if self.client_bytes_per_sec != 0 && self.client_bytes_per_sec < largest_entry {
+            return Err(format!("client_bytes_per_sec must be 0 or at least {}", largest_entry));
+        }
+        for (i, path) in self.indexes.iter().enumerate() {
+            if !index::check_path(path) {
+                return Err(format!("invalid index path `{}`", path));
+            }
+            if self.indexes[..i].contains(path) {
+                return Err(format!("index `{}` is declared twice", path));
+            }
+        }
+        if !(self.merge_dead_ratio > 0.0 && self.merge_dead_ratio <= 1.0) {
+            return Err("merge_dead_ratio must be in (0, 1]".to_string());
+        }
+        Ok(())
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("can't read config file `{}`: {}", path, e))?;
+        let table = content.parse::<toml::Value>().map_err(|e| format!("invalid config file `{}`: {}", path, e))?;
+        let table = match table {
+            toml::Value::Table(table) => table,
+            _ => return Err(format!("invalid config file `{}`", path)),
+        };
+        for (name, value) in table {
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                toml::Value::Array(items) => {
+                    let items: Option<Vec<&str>> = items.iter().map(|item| item.as_str()).collect();
+                    match items {
+                        Some(items) => items.join(","),
+                        None => return Err(format!("invalid value for `{}` in `{}`", name, path)),
+                    }
+                }
+                _ => return Err(format!("invalid value for `{}` in `{}`", name, path)),
+            };
+            self.set(&name, &value).map_err(|e| format!("{} in `{}`", e, path))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("invalid value `{}` for `{}`", value, name))
 }
 
 pub fn default_config() -> Config {
@@ -11,6 +206,139 @@ pub fn default_config() -> Config {
     let temp_dir = current_path.join("data");
     Config {
         dir_path: temp_dir.to_str().unwrap().to_string(),
-        max_file_size: 16 * 1024 * 1024,
-    }  
-}
\ No newline at end of file
+        ..Default::default()
+    }
+}
+
+// settings are applied in order of precedence: defaults, then the toml file
+// given by --config or BITCASK_CONFIG, then BITCASK_* environment variables,
+// then command line flags; unknown BITCASK_* variables are only warned about
+// since the environment is shared with whatever else runs there
+pub fn load(args: &[String]) -> Result<Config, String> {
+    let mut flags = vec![];
+    let mut config_path = env::var(format!("{}CONFIG", ENV_PREFIX)).ok();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        if let Ok(port) = arg.parse::<u16>() {
+            flags.push(("bind".to_string(), format!("127.0.0.1:{}", port)));
+            i += 1;
+            continue;
+        }
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => return Err(format!("unexpected argument `{}`", arg)),
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.replace('-', "_"), value.to_string()),
+            None => {
+                i += 1;
+                match args.get(i) {
+                    Some(value) => (flag.replace('-', "_"), value.clone()),
+                    None => return Err(format!("missing value for `{}`", arg)),
+                }
+            }
+        };
+        if name == "config" {
+            config_path = Some(value);
+        } else {
+            flags.push((name, value));
+        }
+        i += 1;
+    }
+
+    let mut config = default_config();
+    if let Some(path) = config_path {
+        config.load_file(&path)?;
+    }
+    for (key, value) in env::vars() {
+        let name = match key.strip_prefix(ENV_PREFIX) {
+            Some(name) if name != "CONFIG" => name.to_lowercase(),
+            _ => continue,
+        };
+        if !SETTINGS.contains(&name.as_str()) {
+            eprintln!("ignoring unknown setting in {}", key);
+            continue;
+        }
+        config.set(&name, &value).map_err(|e| format!("{} in {}", e, key))?;
+    }
+    for (name, value) in flags {
+        config.set(&name, &value).map_err(|e| format!("{} in --{}", e, name.replace('_', "-")))?;
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::kv::test_util::temp_dir;
+
+    // the only test that touches the environment, so nothing else sees it
+    #[test]
+    fn flags_override_env_which_overrides_the_file() {
+        let dir = temp_dir("precedence");
+        fs::create_dir_all(&dir).unwrap();
+        let data_dir = Path::new(&dir).join("data").to_str().unwrap().to_string();
+        let file = Path::new(&dir).join("bitcask.toml").to_str().unwrap().to_string();
+        fs::write(&file, format!("data_dir = \"{}\"\nmax_key_size = 10\nmax_value_size = 20\nqueue_capacity = 5\nindexes = [\"owner\", \"meta.tag\"]\n", data_dir)).unwrap();
+        env::set_var("BITCASK_MAX_VALUE_SIZE", "30");
+        env::set_var("BITCASK_QUEUE_CAPACITY", "6");
+        env::set_var("BITCASK_NOT_A_SETTING", "1");
+        let args: Vec<String> = ["--config", &file, "--queue-capacity", "7", "--sync=interval:50", "4000"]
+            .iter().map(|arg| arg.to_string()).collect();
+        let loaded = load(&args);
+        let rejected = load(&["--config".to_string(), file.clone(), "--max-file-size=0".to_string()]);
+        env::remove_var("BITCASK_MAX_VALUE_SIZE");
+        env::remove_var("BITCASK_QUEUE_CAPACITY");
+        env::remove_var("BITCASK_NOT_A_SETTING");
+
+        let config = loaded.unwrap();
+        assert_eq!(config.dir_path, data_dir);
+        assert_eq!(config.max_key_size, 10);
+        assert_eq!(config.max_value_size, 30);
+        assert_eq!(config.queue_capacity, 7);
+        assert_eq!(config.sync_policy, SyncPolicy::Interval(50));
+        assert_eq!(config.bind_addr, SocketAddr::from(([127, 0, 0, 1], 4000)));
+        assert_eq!(config.indexes, vec!["owner".to_string(), "meta.tag".to_string()]);
+        assert_eq!(rejected.err().unwrap(), "max_file_size must be greater than 0");
+        // checking a config leaves the disk alone
+        assert!(!Path::new(&data_dir).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let mut config = Config::default();
+        assert_eq!(config.set("sync", "sometimes").err().unwrap(), "invalid sync policy `sometimes`, expected never, always or interval:<ms>");
+        assert!(config.set("sync", "interval:0").is_err());
+        assert_eq!(config.set("max_key_size", "ten").err().unwrap(), "invalid value `ten` for `max_key_size`");
+        assert_eq!(config.set("max_keys", "10").err().unwrap(), "unknown setting `max_keys`");
+        for name in SETTINGS {
+            assert_ne!(config.clone().set(name, "1").err(), Some(format!("unknown setting `{}`", name)));
+        }
+
+        let file = temp_dir("not-a-dir");
+        fs::write(&file, "").unwrap();
+        let valid = Config { dir_path: temp_dir("valid"), ..Default::default() };
+        let cases: Vec<(Config, String)> = vec![
+            (Config::default(), "data_dir must not be empty".to_string()),
+            (Config { dir_path: file.clone(), ..valid.clone() }, format!("data_dir `{}` is not a writable directory", file)),
+            (Config { max_file_size: 0, ..valid.clone() }, "max_file_size must be greater than 0".to_string()),
+            (Config { max_open_files: 0, ..valid.clone() }, "max_open_files must be greater than 0".to_string()),
+            (Config { client_bytes_per_sec: 100, ..valid.clone() }, "client_bytes_per_sec must be 0 or at least 222".to_string()),
+            (Config { indexes: vec!["a..b".to_string()], ..valid.clone() }, "invalid index path `a..b`".to_string()),
+            (Config { indexes: vec!["a".to_string(), "a".to_string()], ..valid.clone() }, "index `a` is declared twice".to_string()),
+            (Config { merge_dead_ratio: 0.0, ..valid.clone() }, "merge_dead_ratio must be in (0, 1]".to_string()),
+        ];
+        for (config, error) in cases {
+            assert_eq!(config.validate().err(), Some(error));
+        }
+        assert_eq!(valid.validate(), Ok(()));
+        fs::remove_file(&file).unwrap();
+    }
+}
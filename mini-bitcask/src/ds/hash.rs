@@ -1,34 +1,52 @@
 use std::collections::HashMap;
+use std::collections::hash_map;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub file_id: u32,
+    pub offset: u64,
+    pub size: u64,
+}
 
 #[derive(Default)]
 pub struct Hash {
-    index: HashMap<String, String>,
+    index: HashMap<String, Position>,
 }
 
 impl Hash {
-    pub fn get(&self, key: String) -> Option<String> {
-        if self.index.contains_key(&key) {
-            self.index.get(&key).cloned()
-        } else {
-            None
-        }
+    pub fn get(&self, key: &str) -> Option<Position> {
+        self.index.get(key).cloned()
     }
 
-    pub fn set(&mut self, key: String, value: String) {
-        if self.index.contains_key(&key) {
-            *self.index.get_mut(&key).unwrap() = value;
-        } else {
-            self.index.insert(key, value);
-        }
+    pub fn set(&mut self, key: String, position: Position) -> Option<Position> {
+        self.index.insert(key, position)
     }
 
-    pub fn delete(&mut self, key: String) {
-        if self.index.contains_key(&key) {
-            self.index.remove(&key);
-        }
+    pub fn delete(&mut self, key: &str) -> Option<Position> {
+        self.index.remove(key)
     }
 
     pub fn clear(&mut self) {
         self.index.clear();
     }
-}
\ No newline at end of file
+
+    pub fn drain(&mut self) -> hash_map::Drain<'_, String, Position> {
+        self.index.drain()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, String, Position> {
+        self.index.iter()
+    }
+
+    pub fn keys(&self) -> hash_map::Keys<'_, String, Position> {
+        self.index.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
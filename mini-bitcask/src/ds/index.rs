@@ -0,0 +1,130 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::Value;
+
+// secondary index over one dot separated json path, e.g. `owner` or
+// `meta.tags.0`; only scalar values are indexed and they are keyed by their
+// json text, so the string "1" and the number 1 stay distinct
+#[derive(Default)]
+pub struct Index {
+    pub path: String,
+    segments: Vec<String>,
+    entries: HashMap<String, BTreeSet<String>>,
+    terms: HashMap<String, String>,
+}
+
+pub fn check_path(path: &str) -> bool {
+    !path.is_empty() && path.split('.').all(|segment| !segment.is_empty())
+}
+
+pub fn term(value: &Value) -> Option<String> {
+    match value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+impl Index {
+    pub fn new(path: &str) -> Index {
+        Index {
+            path: path.to_string(),
+            segments: path.split('.').map(|segment| segment.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn extract(&self, document: &Value) -> Option<String> {
+        let mut current = document;
+        for segment in &self.segments {
+            current = match current {
+                Value::Object(map) => map.get(segment)?,
+                Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        term(current)
+    }
+
+    pub fn insert(&mut self, key: &str, document: Option<&Value>) {
+        self.remove(key);
+        let term = match document.and_then(|document| self.extract(document)) {
+            Some(term) => term,
+            None => return,
+        };
+        self.entries.entry(term.clone()).or_default().insert(key.to_string());
+        self.terms.insert(key.to_string(), term);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        let term = match self.terms.remove(key) {
+            Some(term) => term,
+            None => return,
+        };
+        if let Some(keys) = self.entries.get_mut(&term) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.entries.remove(&term);
+            }
+        }
+    }
+
+    pub fn find(&self, value: &Value) -> Vec<String> {
+        term(value)
+            .and_then(|term| self.entries.get(&term))
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.terms.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn scalars_at_the_path_are_indexed_by_json_text() {
+        let mut index = Index::new("meta.tags.0");
+        index.insert("a", Some(&json!({ "meta": { "tags": ["red", "blue"] } })));
+        index.insert("b", Some(&json!({ "meta": { "tags": [1] } })));
+        index.insert("c", Some(&json!({ "meta": { "tags": ["1"] } })));
+        index.insert("d", Some(&json!({ "meta": { "tags": [{ "nested": true }] } })));
+        index.insert("e", Some(&json!({ "meta": {} })));
+        index.insert("f", None);
+        assert_eq!(index.find(&json!("red")), vec!["a".to_string()]);
+        assert_eq!(index.find(&json!(1)), vec!["b".to_string()]);
+        assert_eq!(index.find(&json!("1")), vec!["c".to_string()]);
+        assert!(index.find(&json!({ "nested": true })).is_empty());
+        assert_eq!(index.len(), 3);
+        assert!(!check_path("a..b") && !check_path("") && check_path("a.0"));
+    }
+
+    #[test]
+    fn changing_or_removing_a_value_moves_the_key() {
+        let mut index = Index::new("owner");
+        index.insert("x", Some(&json!({ "owner": "ann" })));
+        index.insert("y", Some(&json!({ "owner": "ann" })));
+        index.insert("x", Some(&json!({ "owner": "bob" })));
+        assert_eq!(index.find(&json!("ann")), vec!["y".to_string()]);
+        assert_eq!(index.find(&json!("bob")), vec!["x".to_string()]);
+        // a value without the path drops the key altogether
+        index.insert("y", Some(&json!({ "name": "ann" })));
+        assert!(index.find(&json!("ann")).is_empty());
+        index.remove("x");
+        assert!(index.is_empty());
+        assert!(index.entries.is_empty());
+    }
+}
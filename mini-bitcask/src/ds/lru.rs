@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, HashMap};
+
+// size-aware lru of decoded values; a key and its value are charged together
+// against `capacity` bytes and the least recently read entry is evicted
+// first, a capacity of 0 disables the cache
+#[derive(Default)]
+pub struct Lru {
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    size: usize,
+    tick: u64,
+    entries: HashMap<String, (String, u64)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    pub fn new(capacity: usize) -> Lru {
+        Lru {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<String> {
+        let tick = self.tick + 1;
+        match self.entries.get_mut(key) {
+            Some((value, last)) => {
+                let key = self.order.remove(last).unwrap();
+                self.order.insert(tick, key);
+                *last = tick;
+                self.tick = tick;
+                self.hits += 1;
+                Some(value.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn set(&mut self, key: String, value: String) {
+        self.delete(&key);
+        let charge = key.len() + value.len();
+        if charge > self.capacity {
+            return;
+        }
+        while self.size + charge > self.capacity {
+            let (_, oldest) = self.order.pop_first().unwrap();
+            let (value, _) = self.entries.remove(&oldest).unwrap();
+            self.size -= oldest.len() + value.len();
+            self.evictions += 1;
+        }
+        self.tick += 1;
+        self.size += charge;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        if let Some((value, last)) = self.entries.remove(key) {
+            self.order.remove(&last);
+            self.size -= key.len() + value.len();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.size = 0;
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_within_capacity_evicting_the_least_recently_read() {
+        // every key and value below charges 2 bytes
+        let mut lru = Lru::new(6);
+        for key in ["a", "b", "c"] {
+            lru.set(key.to_string(), key.to_uppercase());
+        }
+        assert_eq!(lru.size(), 6);
+        assert_eq!(lru.get("a"), Some("A".to_string()));
+        lru.set("d".to_string(), "D".to_string());
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.evictions, 1);
+        lru.set("e".to_string(), "E".to_string());
+        assert_eq!(lru.get("c"), None);
+        assert_eq!(lru.get("a"), Some("A".to_string()));
+        assert_eq!(lru.len(), 3);
+        assert_eq!(lru.size(), 6);
+
+        // a bigger value makes room for itself, oldest first
+        lru.set("f".to_string(), "FFF".to_string());
+        assert_eq!(lru.size(), 6);
+        assert_eq!((lru.get("d"), lru.get("e")), (None, None));
+        assert_eq!(lru.get("f"), Some("FFF".to_string()));
+        // overwriting recharges, deleting refunds
+        lru.set("a".to_string(), "AAAAA".to_string());
+        assert_eq!(lru.size(), 6);
+        assert_eq!(lru.get("f"), None);
+        lru.delete("a");
+        assert_eq!(lru.size(), 0);
+        assert!(lru.is_empty());
+        assert_eq!((lru.hits, lru.misses), (3, 5));
+    }
+
+    #[test]
+    fn oversized_values_and_zero_capacity_are_never_cached() {
+        let mut lru = Lru::new(4);
+        lru.set("a".to_string(), "A".to_string());
+        lru.set("key".to_string(), "value".to_string());
+        assert_eq!(lru.get("key"), None);
+        assert_eq!(lru.get("a"), Some("A".to_string()));
+        let mut disabled = Lru::new(0);
+        disabled.set("a".to_string(), String::new());
+        assert!(disabled.is_empty());
+    }
+}
@@ -1 +1,3 @@
-pub mod hash;
\ No newline at end of file
+pub mod hash;
+pub mod lru;
+pub mod index;
\ No newline at end of file
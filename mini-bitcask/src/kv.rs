@@ -1,28 +1,49 @@
+use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::path::Path;
-use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
 
 use crate::config;
 use crate::ds::hash;
+use crate::ds::lru;
+use crate::ds::index;
 use crate::storage::entry;
 use crate::storage::db_file;
+#[cfg(feature = "fault-injection")]
+use crate::storage::fault;
+use crate::storage::file_pool;
+use crate::txn;
+use crate::limits;
 use crate::utils::time_routine;
+use serde_json::{json, Value};
+
+// entries written by a transaction carry this flag and only take effect
+// once the commit marker that closes their unit has been read back
+const TXN_FLAG: u16 = 1;
 
 enum EntryType {
     Set,
     SetWithExpire,
     Delete,
     Clear,
+    Commit,
 }
 
-impl Into<u16> for EntryType {
-    fn into(self) -> u16 {
-        match self {
+impl From<EntryType> for u16 {
+    fn from(kind: EntryType) -> u16 {
+        match kind {
             EntryType::Set => 0,
             EntryType::SetWithExpire => 1,
             EntryType::Delete => 2,
             EntryType::Clear => 3,
+            EntryType::Commit => 4,
         }
     }
 }
@@ -34,41 +55,196 @@ impl From<u16> for EntryType {
             1 => EntryType::SetWithExpire,
             2 => EntryType::Delete,
             3 => EntryType::Clear,
+            4 => EntryType::Commit,
             _ => panic!(),
         }
     }
 }
 
-pub fn check_key_value(key: &String, value: &String) -> bool {
-    if key.len() == 0 || key.len() > 100 {
+pub fn check_key_value(config: &config::Config, key: &str, value: &str) -> bool {
+    if key.is_empty() || key.len() > config.max_key_size {
         return false;
     }
-    value.len() <= 100
+    value.len() <= config.max_value_size
 }
 
-pub fn check_key_value_vec(key: &Vec<u8>, value: &Vec<u8>) -> bool {
-    if key.len() == 0 || key.len() > 100 {
+pub fn check_key_value_vec(config: &config::Config, key: &[u8], value: &[u8]) -> bool {
+    if key.is_empty() || key.len() > config.max_key_size {
         return false;
     }
-    value.len() <= 100
+    value.len() <= config.max_value_size
 }
 
-pub fn build(path: &String) -> Option<Vec<u32>> {
+// only `<id>.data` with a canonical decimal id names a data file, anything
+// else in the directory is left alone
+pub fn parse_file_id(file_name: &str) -> Option<u32> {
+    let id = file_name.strip_suffix(".data")?;
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) || (id.len() > 1 && id.starts_with('0')) {
+        return None;
+    }
+    id.parse::<u32>().ok()
+}
+
+pub fn build(path: &str) -> Option<Vec<u32>> {
     let dir =  match fs::read_dir(path) {
         Ok(dir) => dir,
         Err(_) => return None,
     };
     let mut ids = vec![];
     for entry in dir {
-        let file_name = entry.ok()?.file_name().to_str()?.to_string();
-        if file_name.contains(".data") {
-            let id = file_name.split(".").next()?.parse::<u32>().ok()?;
+        let entry = entry.ok()?;
+        if !entry.file_type().ok()?.is_file() {
+            continue;
+        }
+        if let Some(id) = entry.file_name().to_str().and_then(parse_file_id) {
             ids.push(id);
         }
     }
     Some(ids)
 }
 
+fn remove_staged(path: &str) -> Option<()> {
+    for entry in fs::read_dir(path).ok()? {
+        let entry = entry.ok()?;
+        if entry.file_name().to_str().is_some_and(|name| name.ends_with(".data.import")) {
+            fs::remove_file(entry.path()).ok()?;
+        }
+    }
+    Some(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WriteError {
+    Invalid,
+    Rejected(limits::Rejection),
+    Write,
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Invalid => write!(f, "key or value exceeds the configured limits"),
+            WriteError::Rejected(rejection) => write!(f, "{}", rejection),
+            WriteError::Write => write!(f, "entry can't be written"),
+        }
+    }
+}
+
+// where an import is staged; replay never picks these names up, and open
+// removes whatever a crash part way through an import left behind
+pub fn staging_path(dir: &str) -> PathBuf {
+    static STAGED: AtomicU64 = AtomicU64::new(0);
+    let n = STAGED.fetch_add(1, Ordering::Relaxed);
+    Path::new(dir).join(format!("{}-{}.data.import", std::process::id(), n))
+}
+
+// what an import may stage: `remaining` is the quota the store's files left
+// when it began, and `staged` the bytes every import in flight has written
+// so far, which data_size counts too
+#[derive(Clone)]
+pub struct ImportQuota {
+    pub remaining: u64,
+    pub staged: Arc<AtomicU64>,
+}
+
+// writes every record of an ndjson dump to `staged` as data file entries and
+// syncs it; this only needs the config, so it can run while the store keeps
+// serving, and removes `staged` again on failure. its bytes stay in
+// `quota.staged` until link_import takes the file over
+pub fn stage_import<R: BufRead>(config: &config::Config, input: R, staged: &Path, quota: &ImportQuota) -> Result<u64, String> {
+    let mut written = 0;
+    let result = write_import(config, input, staged, quota, &mut written);
+    if result.is_err() {
+        quota.staged.fetch_sub(written, Ordering::Relaxed);
+        let _ = fs::remove_file(staged);
+    }
+    result
+}
+
+fn write_import<R: BufRead>(config: &config::Config, input: R, staged: &Path, quota: &ImportQuota, written: &mut u64) -> Result<u64, String> {
+    let file = File::create(staged).map_err(|e| format!("import file can't create: {}", e))?;
+    let mut writer = BufWriter::new(file);
+    let now = time_routine::time_now();
+    let mut count = 0;
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(&line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        let key = record.get("key").and_then(Value::as_str);
+        let value = record.get("value").and_then(Value::as_str);
+        let (key, value) = match (key, value) {
+            (Some(key), Some(value)) => (key, value),
+            _ => return Err(format!("line {}: expected string key and value", i + 1)),
+        };
+        if !check_key_value(config, key, value) {
+            return Err(format!("line {}: key or value exceeds the configured limits", i + 1));
+        }
+        let entry = match record.get("deadline") {
+            None | Some(Value::Null) => entry::Entry::new(key.as_bytes().to_vec(), value.as_bytes().to_vec(), 0, EntryType::Set.into()),
+            Some(deadline) => {
+                let deadline = match deadline.as_u64() {
+                    Some(deadline) => deadline,
+                    None => return Err(format!("line {}: deadline must be an unsigned integer", i + 1)),
+                };
+                if deadline < now {
+                    continue;
+                }
+                entry::Entry::new_with_expire(key.as_bytes().to_vec(), value.as_bytes().to_vec(), deadline, 0, EntryType::SetWithExpire.into())
+            }
+        };
+        // checked before the write, so a dump over the quota never reaches disk
+        let size = entry.size();
+        let staged_bytes = quota.staged.fetch_add(size, Ordering::Relaxed) + size;
+        *written += size;
+        if config.max_data_size != 0 && staged_bytes > quota.remaining {
+            let rejection = limits::Rejection::DiskQuota {
+                used: config.max_data_size - quota.remaining + staged_bytes,
+                limit: config.max_data_size,
+            };
+            return Err(format!("line {}: {}", i + 1, rejection));
+        }
+        writer.write_all(&entry.encode().unwrap()).map_err(|e| format!("import file can't write: {}", e))?;
+        count += 1;
+    }
+    let file = writer.into_inner().map_err(|e| format!("import file can't write: {}", e))?;
+    file.sync_all().map_err(|e| format!("import file can't sync: {}", e))?;
+    Ok(count)
+}
+
+// every live pair at one point in time, taken inside the store and read
+// outside it: written entries never move, and the files they sit in are
+// opened up front so a merge removing them meanwhile doesn't matter
+pub struct Snapshot {
+    pub records: Vec<(String, hash::Position, Option<u64>)>,
+    files: HashMap<u32, File>,
+}
+
+impl Snapshot {
+    // newline-delimited json records: {"key", "value", "deadline"}; a record
+    // that can't be read back is an error, never a gap in the export
+    pub fn lines(&self) -> impl Iterator<Item = io::Result<String>> + '_ {
+        self.records.iter().map(|(key, position, deadline)| {
+            let file = self.files.get(&position.file_id).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("data file {} isn't open", position.file_id)))?;
+            let entry = db_file::read_at(file, position.offset, position.size)
+                .map_err(|e| io::Error::new(e.kind(), format!("`{}` can't be read: {}", key, e)))?;
+            let value = std::str::from_utf8(&entry.value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("`{}` isn't utf-8: {}", key, e)))?;
+            Ok(format!("{}\n", json!({ "key": key, "value": value, "deadline": deadline })))
+        })
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<u64> {
+        let mut count = 0;
+        for line in self.lines() {
+            out.write_all(line?.as_bytes())?;
+            count += 1;
+        }
+        out.flush()?;
+        Ok(count)
+    }
+}
+
 #[derive(Default)]
 #[allow(non_camel_case_types)]
 pub struct kv {
@@ -76,23 +252,46 @@ pub struct kv {
     pub hash_index: hash::Hash,
     pub expires: HashMap<String, u64>,
     pub active_file: db_file::DBFile,
-    pub arch_files: HashMap<u32, db_file::DBFile>,
+    pub arch_files: file_pool::FilePool,
+    pub cache: lru::Lru,
+    pub indexes: Vec<index::Index>,
+    pub dead: HashMap<u32, u64>,
+    pub rotations: u64,
+    pub merges: u64,
+    pub checksum_failures: u64,
+    // every write bumps `version`, and while a transaction is open stamps the
+    // key with it too; deleted keys keep their stamp so a transaction that
+    // read them still conflicts, until no open transaction is older
+    pub version: u64,
+    pub versions: HashMap<String, u64>,
+    // start versions of the open transactions, with how many began at each
+    pub open_txns: BTreeMap<u64, usize>,
+    pub cleared: u64,
+    pub commits: u64,
+    pub conflicts: u64,
+    pub quota_rejections: u64,
+    pub last_sync: Option<Instant>,
+    // bytes of imports still being staged, shared with their ImportQuota
+    pub staged: Arc<AtomicU64>,
 }
 
 impl kv {
     pub fn open(config: config::Config) -> Option<kv> {
-        fs::create_dir(&config.dir_path).ok()?;
+        fs::create_dir_all(&config.dir_path).ok()?;
+        remove_staged(&config.dir_path)?;
         let mut ids = build(&config.dir_path)?;
         ids.sort();
-        let mut arch_files = HashMap::default();
-        let active_file = if ids.len() == 0 {
+        let mut arch_files = file_pool::FilePool::new(config.max_open_files);
+        let config_cache_capacity = config.cache_capacity;
+        let indexes = config.indexes.iter().map(|path| index::Index::new(path)).collect();
+        let active_file = if ids.is_empty() {
             let active_id = 1;
             let active_path = Path::new(&config.dir_path).join(format!("{}.data", active_id));
             File::create(active_path).expect("active file can't create error");
             db_file::DBFile::new(config.dir_path.clone(), active_id).expect("active file can't create error")
         } else {
-            for i in 0..ids.len() - 1 {
-                arch_files.insert(ids[i], db_file::DBFile::new(config.dir_path.clone(), ids[i]).expect("archive file can't open error"));
+            for id in &ids[..ids.len() - 1] {
+                arch_files.insert(db_file::DBFile::open_archived(config.dir_path.clone(), *id).expect("archive file can't open error"));
             }
             db_file::DBFile::new(config.dir_path.clone(), ids[ids.len() - 1]).expect("active file can't open error")
         };
@@ -102,72 +301,217 @@ impl kv {
             expires: HashMap::default(),
             active_file,
             arch_files,
+            cache: lru::Lru::new(config_cache_capacity),
+            indexes,
+            ..Default::default()
         };
         db.build_index();
         Some(db)
     }
 
     pub fn get(&mut self, key: String) -> Option<String> {
-        if !check_key_value(&key, &"".to_string()) {
+        if !check_key_value(&self.config, &key, "") {
             return None;
         }
         if !self.check_expired(&key) {
-            self.hash_index.delete(key);
+            self.expires.remove(&key);
+            self.cache.delete(&key);
+            self.retire(&key);
             return None
         }
-        self.hash_index.get(key)
+        let position = self.hash_index.get(&key)?;
+        if let Some(value) = self.cache.get(&key) {
+            return Some(value);
+        }
+        let value = self.read_value(position)?;
+        self.cache.set(key, value.clone());
+        Some(value)
     }
 
-    pub fn set(&mut self, key: String, value: String) -> bool {
-        if !check_key_value(&key, &value) {
-            return false;
+    pub fn set(&mut self, key: String, value: String) -> Result<(), WriteError> {
+        if !check_key_value(&self.config, &key, &value) {
+            return Err(WriteError::Invalid);
         }
-        self.hash_index.set(key.clone(), value.clone());
-        let entry = entry::Entry::new(key.into_bytes(), value.into_bytes(), 0, EntryType::Set.into());
-        self.store_entry(entry)
+        let entry = entry::Entry::new(key.clone().into_bytes(), value.clone().into_bytes(), 0, EntryType::Set.into());
+        self.check_quota(entry.size()).map_err(WriteError::Rejected)?;
+        self.cache.delete(&key);
+        let position = self.store_entry(entry).ok_or(WriteError::Write)?;
+        self.bump(&key);
+        self.expires.remove(&key);
+        self.reindex(&key, &value);
+        self.place(key, position);
+        Ok(())
     }
 
-    pub fn set_with_expire(&mut self, key: String, value: String, deadline: u64) -> bool {
-        if !check_key_value(&key, &value) {
-            return false;
-        }
-        if self.expires.contains_key(&key) {
-            *self.expires.get_mut(&key).unwrap() = deadline;
-        } else {
-            self.expires.insert(key.clone(), deadline);
+    pub fn set_with_expire(&mut self, key: String, value: String, deadline: u64) -> Result<(), WriteError> {
+        if !check_key_value(&self.config, &key, &value) {
+            return Err(WriteError::Invalid);
         }
-        self.hash_index.set(key.clone(), value.clone());
-        let entry = entry::Entry::new_with_expire(key.into_bytes(), value.into_bytes(), deadline, 0, EntryType::SetWithExpire.into());
-        self.store_entry(entry)
+        let entry = entry::Entry::new_with_expire(key.clone().into_bytes(), value.clone().into_bytes(), deadline, 0, EntryType::SetWithExpire.into());
+        self.check_quota(entry.size()).map_err(WriteError::Rejected)?;
+        self.cache.delete(&key);
+        let position = self.store_entry(entry).ok_or(WriteError::Write)?;
+        self.expires.insert(key.clone(), deadline);
+        self.bump(&key);
+        self.reindex(&key, &value);
+        self.place(key, position);
+        Ok(())
     }
 
     pub fn delete(&mut self, key: String) -> bool {
-        if !check_key_value(&key, &"".to_string()) {
+        if !check_key_value(&self.config, &key, "") {
             return false;
         }
-        if self.expires.contains_key(&key) {
-            self.expires.remove(&key);
-        }
-        self.hash_index.delete(key.clone());
-        let entry = entry::Entry::new(key.into_bytes(), vec![], 0, EntryType::Delete.into());
-        self.store_entry(entry)
+        self.cache.delete(&key);
+        let entry = entry::Entry::new(key.clone().into_bytes(), vec![], 0, EntryType::Delete.into());
+        let position = match self.store_entry(entry) {
+            Some(position) => position,
+            None => return false,
+        };
+        self.expires.remove(&key);
+        self.retire(&key);
+        self.bump(&key);
+        *self.dead.entry(position.file_id).or_insert(0) += position.size;
+        true
     }
 
     pub fn clear(&mut self) -> bool {
-        self.expires.clear();
-        self.hash_index.clear();
         let entry = entry::Entry::new(vec![], vec![], 0, EntryType::Clear.into());
-        self.store_entry(entry)
+        let position = match self.store_entry(entry) {
+            Some(position) => position,
+            None => return false,
+        };
+        *self.dead.entry(position.file_id).or_insert(0) += position.size;
+        self.cache.clear();
+        self.expires.clear();
+        self.version += 1;
+        self.versions.clear();
+        self.cleared = self.version;
+        for index in &mut self.indexes {
+            index.clear();
+        }
+        for (_, position) in self.hash_index.drain() {
+            *self.dead.entry(position.file_id).or_insert(0) += position.size;
+        }
+        true
     }
 
-    pub fn close(&mut self) {
-        for (_, arch_file) in self.arch_files.iter_mut() {
-            arch_file.close();
+    pub fn begin(&mut self) -> txn::Transaction {
+        *self.open_txns.entry(self.version).or_insert(0) += 1;
+        txn::Transaction {
+            start: self.version,
+            ..Default::default()
         }
+    }
+
+    pub fn abort(&mut self, txn: txn::Transaction) {
+        self.end(txn.start);
+    }
+
+    // reads pin a version no older than their transaction's start, so a stamp
+    // at or below the oldest open start can't make any of them conflict
+    fn end(&mut self, start: u64) {
+        match self.open_txns.get_mut(&start) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                return;
+            }
+            Some(_) => {
+                self.open_txns.remove(&start);
+            }
+            None => return,
+        }
+        match self.open_txns.keys().next() {
+            None => self.versions.clear(),
+            Some(&oldest) if oldest > start => self.versions.retain(|_, version| *version > oldest),
+            Some(_) => {}
+        }
+    }
+
+    pub fn version_of(&self, key: &str) -> u64 {
+        self.versions.get(key).cloned().unwrap_or(self.cleared)
+    }
+
+    // the buffered writes go to disk as one unit closed by a commit marker,
+    // so after a crash replay applies either all of them or none
+    pub fn commit(&mut self, txn: txn::Transaction) -> Result<(), txn::TxnError> {
+        let start = txn.start;
+        let ret = self.apply(txn);
+        self.end(start);
+        ret
+    }
+
+    fn apply(&mut self, txn: txn::Transaction) -> Result<(), txn::TxnError> {
+        for (key, version) in &txn.reads {
+            if self.version_of(key) > *version {
+                self.conflicts += 1;
+                return Err(txn::TxnError::Conflict(key.clone()));
+            }
+        }
+        let mut entries = vec![];
+        for (key, write) in &txn.writes {
+            let entry = match write {
+                txn::Write::Set(value) => {
+                    if !check_key_value(&self.config, key, value) {
+                        return Err(txn::TxnError::Invalid(key.clone()));
+                    }
+                    entry::Entry::new(key.clone().into_bytes(), value.clone().into_bytes(), TXN_FLAG, EntryType::Set.into())
+                }
+                txn::Write::SetWithExpire(value, deadline) => {
+                    if !check_key_value(&self.config, key, value) {
+                        return Err(txn::TxnError::Invalid(key.clone()));
+                    }
+                    entry::Entry::new_with_expire(key.clone().into_bytes(), value.clone().into_bytes(), *deadline, TXN_FLAG, EntryType::SetWithExpire.into())
+                }
+                txn::Write::Delete => {
+                    if !check_key_value(&self.config, key, "") {
+                        return Err(txn::TxnError::Invalid(key.clone()));
+                    }
+                    entry::Entry::new(key.clone().into_bytes(), vec![], TXN_FLAG, EntryType::Delete.into())
+                }
+            };
+            entries.push(entry);
+        }
+        // deletes never count against the quota, they are how space comes back
+        let size = txn.writes.iter().zip(&entries)
+            .filter(|((_, write), _)| !matches!(write, txn::Write::Delete))
+            .map(|(_, entry)| entry.size())
+            .sum();
+        self.check_quota(size).map_err(txn::TxnError::Rejected)?;
+        if !entries.is_empty() {
+            let positions = self.store_unit(entries).ok_or(txn::TxnError::Write)?;
+            for ((key, write), position) in txn.writes.into_iter().zip(positions) {
+                self.cache.delete(&key);
+                self.bump(&key);
+                match write {
+                    txn::Write::Set(value) => {
+                        self.expires.remove(&key);
+                        self.reindex(&key, &value);
+                        self.place(key, position);
+                    }
+                    txn::Write::SetWithExpire(value, deadline) => {
+                        self.expires.insert(key.clone(), deadline);
+                        self.reindex(&key, &value);
+                        self.place(key, position);
+                    }
+                    txn::Write::Delete => {
+                        self.expires.remove(&key);
+                        self.retire(&key);
+                        *self.dead.entry(position.file_id).or_insert(0) += position.size;
+                    }
+                }
+            }
+        }
+        self.commits += 1;
+        Ok(())
+    }
+
+    pub fn close(&mut self) {
+        self.arch_files.close();
         self.active_file.close();
     }
 
-    pub fn check_expired(&self, key: &String) -> bool {
+    pub fn check_expired(&self, key: &str) -> bool {
         if !self.expires.contains_key(key) {
             return true;
         }
@@ -175,103 +519,752 @@ impl kv {
         time_routine::time_now() <= deadline
     }
 
-    pub fn store_entry(&mut self, entry: entry::Entry) -> bool {
+    pub fn live_bytes(&self, id: u32) -> u64 {
+        let size = if id == self.active_file.id {
+            self.active_file.offset
+        } else {
+            match self.arch_files.get(&id) {
+                Some(file) => file.offset,
+                None => return 0,
+            }
+        };
+        size.saturating_sub(self.dead_bytes(id))
+    }
+
+    // the store's files plus whatever imports in flight have staged so far
+    pub fn data_size(&self) -> u64 {
+        self.files_size() + self.staged.load(Ordering::Relaxed)
+    }
+
+    fn files_size(&self) -> u64 {
+        self.active_file.offset + self.arch_files.values().map(|arch_file| arch_file.offset).sum::<u64>()
+    }
+
+    pub fn import_quota(&self) -> ImportQuota {
+        ImportQuota {
+            remaining: self.config.max_data_size.saturating_sub(self.files_size()),
+            staged: self.staged.clone(),
+        }
+    }
+
+    // checked by every write before it reaches store_entry; deletes and
+    // clears skip it so a full store can still be emptied
+    pub fn check_quota(&mut self, bytes: u64) -> Result<(), limits::Rejection> {
+        if self.config.max_data_size == 0 {
+            return Ok(());
+        }
+        let used = self.data_size();
+        if used + bytes > self.config.max_data_size {
+            self.quota_rejections += 1;
+            return Err(limits::Rejection::DiskQuota {
+                used,
+                limit: self.config.max_data_size,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn dead_bytes(&self, id: u32) -> u64 {
+        self.dead.get(&id).cloned().unwrap_or(0)
+    }
+
+    // keys whose value has `value` at the indexed path, in key order; None
+    // when no index is declared on `path`
+    pub fn query(&mut self, path: &str, value: &Value) -> Option<Vec<String>> {
+        let keys = self.indexes.iter().find(|index| index.path == path)?.find(value);
+        let mut matched = vec![];
+        for key in keys {
+            if self.check_expired(&key) {
+                matched.push(key);
+            } else {
+                self.expires.remove(&key);
+                self.cache.delete(&key);
+                self.retire(&key);
+            }
+        }
+        Some(matched)
+    }
+
+    // live keys in a stable order, skipping anything already expired
+    pub fn export_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.hash_index.keys()
+            .filter(|key| self.check_expired(key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // every live pair as of now, to be read back outside the store
+    pub fn snapshot(&self) -> io::Result<Snapshot> {
+        let mut records = vec![];
+        let mut files = HashMap::new();
+        for key in self.export_keys() {
+            let position = self.hash_index.get(&key).unwrap();
+            if let hash_map::Entry::Vacant(file) = files.entry(position.file_id) {
+                let path = Path::new(&self.config.dir_path).join(format!("{}.data", position.file_id));
+                file.insert(File::open(path)?);
+            }
+            let deadline = self.expires.get(&key).cloned();
+            records.push((key, position, deadline));
+        }
+        Ok(Snapshot { records, files })
+    }
+
+    pub fn export<W: Write>(&mut self, out: &mut W) -> io::Result<u64> {
+        self.snapshot()?.write(out)
+    }
+
+    // stages `input` and links it in, see stage_import and link_import
+    pub fn import<R: BufRead>(&mut self, input: R) -> Result<u64, String> {
+        let staged = staging_path(&self.config.dir_path);
+        let count = stage_import(&self.config, input, &staged, &self.import_quota())?;
+        self.link_import(&staged)?;
+        Ok(count)
+    }
+
+    // the staged file becomes `<id>.data` between the old and the new active
+    // file, so later writes still win on replay
+    pub fn link_import(&mut self, staged: &Path) -> Result<(), String> {
+        let import_id = self.active_file.id + 1;
+        let size = fs::metadata(staged).map(|metadata| metadata.len()).unwrap_or(0);
+        // no longer in flight: from here on it counts as the file it becomes
+        self.staged.fetch_sub(size, Ordering::Relaxed);
+        if let Err(e) = self.check_quota(size) {
+            let _ = fs::remove_file(staged);
+            return Err(e.to_string());
+        }
+        if !self.rotate_to(import_id + 1) {
+            let _ = fs::remove_file(staged);
+            return Err("active file can't rotate".to_string());
+        }
+        let import_path = Path::new(&self.config.dir_path).join(format!("{}.data", import_id));
+        if let Err(e) = fs::rename(staged, import_path) {
+            let _ = fs::remove_file(staged);
+            return Err(format!("import file can't be renamed: {}", e));
+        }
+        let file = match db_file::DBFile::open_archived(self.config.dir_path.clone(), import_id) {
+            Some(file) => file,
+            None => return Err("import file can't open".to_string()),
+        };
+        for (offset, entry) in file.iter() {
+            if entry.valid {
+                // like any other write: the cache drops what it held and
+                // transactions that read the key conflict
+                if let Ok(key) = std::str::from_utf8(&entry.key) {
+                    self.cache.delete(key);
+                    self.bump(key);
+                }
+                self.build_entry(import_id, offset, entry);
+            }
+        }
+        self.arch_files.insert(file);
+        Ok(())
+    }
+
+    pub fn store_entry(&mut self, entry: entry::Entry) -> Option<hash::Position> {
+        if !self.prepare_write() {
+            return None;
+        }
+        self.append(entry)
+    }
+
+    // a unit never spans files, so it may push the active file past
+    // max_file_size
+    fn store_unit(&mut self, entries: Vec<entry::Entry>) -> Option<Vec<hash::Position>> {
+        if !self.prepare_write() {
+            return None;
+        }
+        let start = self.active_file.offset;
+        let marker = entry::Entry::new(vec![], entries.len().to_string().into_bytes(), TXN_FLAG, EntryType::Commit.into());
+        let mut positions = Vec::with_capacity(entries.len());
+        for entry in entries.into_iter().chain(Some(marker)) {
+            match self.write_entry(entry) {
+                Some(position) => positions.push(position),
+                None => {
+                    self.undo_write(start);
+                    return None;
+                }
+            }
+        }
+        if !self.sync_due() {
+            self.undo_write(start);
+            return None;
+        }
+        let marker = positions.pop().unwrap();
+        *self.dead.entry(marker.file_id).or_insert(0) += marker.size;
+        Some(positions)
+    }
+
+    fn prepare_write(&mut self) -> bool {
         if self.active_file.offset > self.config.max_file_size {
-            let active_id = self.active_file.id;
-            if !self.active_file.close() {
+            if !self.rotate() {
                 return false;
             }
-            let new_id = active_id + 1;
-            let new_path = Path::new(&self.config.dir_path).join(format!("{}.data", new_id));
-            if File::create(new_path).is_err() {
-                return false;
+            // a failed merge leaves every file in place, so the write can
+            // still go ahead
+            if self.should_merge() {
+                self.merge();
             }
-            self.active_file = db_file::DBFile::new(self.config.dir_path.clone(), new_id).expect("active file can't create error");
-            self.arch_files.insert(active_id, db_file::DBFile::new(self.config.dir_path.clone(), active_id).expect("active file can't archive error"));
         }
-        self.active_file.write(entry)
+        true
     }
 
-    fn build_index(&mut self) {
-        if self.arch_files.len() > 0 {
-            let mut ids = vec![];
-            for id in self.arch_files.keys() {
-                ids.push(*id);
+    pub fn merge(&mut self) -> bool {
+        let ids: HashSet<u32> = self.arch_files.ids().into_iter().collect();
+        let live: Vec<(String, hash::Position)> = self.hash_index.iter()
+            .filter(|(_, position)| ids.contains(&position.file_id))
+            .map(|(key, position)| (key.clone(), *position))
+            .collect();
+        for (key, position) in live {
+            if !self.check_expired(&key) {
+                self.expires.remove(&key);
+                self.cache.delete(&key);
+                self.retire(&key);
+                continue;
             }
-            ids.sort();
-            for id in ids {
-                let mut offset = 0;
-                loop {
-                    let entry = self.arch_files.get(&id).unwrap().read(offset);
-                    if entry.is_none() {
-                        break;
-                    }
-                    let entry = entry.unwrap();
-                    offset += entry.size();
-                    if check_key_value_vec(&entry.key, &entry.value) {
-                        self.build_entry(entry);
-                    }
+            let entry = self.arch_files.open(position.file_id).and_then(|file| file.read(position.offset)).map(entry::Entry::into_owned);
+            let mut entry = match entry {
+                Some(entry) if entry.valid => entry,
+                _ => return false,
+            };
+            // the unit it came from is already committed
+            entry.state = entry.get_mark();
+            let position = match self.append(entry) {
+                Some(position) => position,
+                None => return false,
+            };
+            self.place(key, position);
+        }
+        if !self.active_file.sync() {
+            return false;
+        }
+        // oldest first, so a crash part way through never leaves a file whose
+        // entries an already removed newer file used to shadow
+        let mut ids: Vec<u32> = ids.into_iter().collect();
+        ids.sort();
+        for id in ids {
+            if let Some(mut arch_file) = self.arch_files.remove(&id) {
+                arch_file.close();
+                if fs::remove_file(arch_file.file_path()).is_err() {
+                    return false;
                 }
             }
+            self.dead.remove(&id);
+        }
+        self.merges += 1;
+        true
+    }
+
+    fn should_merge(&self) -> bool {
+        let total: u64 = self.arch_files.values().map(|arch_file| arch_file.offset).sum();
+        let dead: u64 = self.arch_files.values().map(|arch_file| self.dead_bytes(arch_file.id)).sum();
+        if total == 0 || dead < self.config.merge_min_dead_bytes {
+            return false;
+        }
+        dead as f64 >= total as f64 * self.config.merge_dead_ratio
+    }
+
+    fn rotate(&mut self) -> bool {
+        self.rotate_to(self.active_file.id + 1)
+    }
+
+    // the new file exists before the old one is closed, so a failure at any
+    // step leaves the current active file usable
+    fn rotate_to(&mut self, new_id: u32) -> bool {
+        let active_id = self.active_file.id;
+        let new_path = Path::new(&self.config.dir_path).join(format!("{}.data", new_id));
+        #[cfg(feature = "fault-injection")]
+        if fault::rotation_fails() {
+            return false;
         }
-        let mut offset = 0;
-        loop {
-            let entry = self.active_file.read(offset);
-            if entry.is_none() {
-                break;
+        if File::create(&new_path).is_err() {
+            return false;
+        }
+        let new_file = match db_file::DBFile::new(self.config.dir_path.clone(), new_id) {
+            Some(new_file) => new_file,
+            None => {
+                let _ = fs::remove_file(&new_path);
+                return false;
+            }
+        };
+        #[cfg(feature = "fault-injection")]
+        if fault::rotation_fails() {
+            // after a kill nothing runs, the new file stays behind
+            if !fault::killed() {
+                let _ = fs::remove_file(&new_path);
             }
-            let entry = entry.unwrap();
-            offset += entry.size();
-            if check_key_value_vec(&entry.key, &entry.value) {
-                self.build_entry(entry);
+            return false;
+        }
+        let archived = match db_file::DBFile::open_archived(self.config.dir_path.clone(), active_id) {
+            Some(archived) => archived,
+            None => {
+                let _ = fs::remove_file(&new_path);
+                return false;
             }
+        };
+        if !self.active_file.close() {
+            let _ = fs::remove_file(&new_path);
+            return false;
         }
+        self.active_file = new_file;
+        self.arch_files.insert(archived);
+        self.rotations += 1;
+        true
     }
 
-    fn build_entry(&mut self, entry: entry::Entry) {
+    fn append(&mut self, entry: entry::Entry) -> Option<hash::Position> {
+        if self.active_file.offset > self.config.max_file_size && !self.rotate() {
+            return None;
+        }
+        let start = self.active_file.offset;
+        let position = self.write_entry(entry)?;
+        if !self.sync_due() {
+            self.undo_write(start);
+            return None;
+        }
+        Some(position)
+    }
+
+    // a write the caller is told failed must not come back on replay, so the
+    // active file goes back to `len`; should even that fail, the bytes stay
+    // behind as dead
+    fn undo_write(&mut self, len: u64) {
+        // after a kill nothing runs
+        #[cfg(feature = "fault-injection")]
+        if fault::killed() {
+            return;
+        }
+        let written = self.active_file.offset - len;
+        if !self.active_file.truncate(len) {
+            *self.dead.entry(self.active_file.id).or_insert(0) += written;
+        }
+    }
+
+    fn write_entry(&mut self, entry: entry::Entry) -> Option<hash::Position> {
+        let position = hash::Position {
+            file_id: self.active_file.id,
+            offset: self.active_file.offset,
+            size: entry.size(),
+        };
+        if !self.active_file.write(entry) {
+            return None;
+        }
+        Some(position)
+    }
+
+    fn sync_due(&mut self) -> bool {
+        let due = match self.config.sync_policy {
+            config::SyncPolicy::Never => false,
+            config::SyncPolicy::Always => true,
+            config::SyncPolicy::Interval(millis) => match self.last_sync {
+                Some(last_sync) => last_sync.elapsed() >= Duration::from_millis(millis),
+                None => true,
+            },
+        };
+        if due {
+            if !self.active_file.sync() {
+                return false;
+            }
+            self.last_sync = Some(Instant::now());
+        }
+        true
+    }
+
+    fn bump(&mut self, key: &str) {
+        self.version += 1;
+        if !self.open_txns.is_empty() {
+            self.versions.insert(key.to_string(), self.version);
+        }
+    }
+
+    fn read_value(&mut self, position: hash::Position) -> Option<String> {
+        let file = if position.file_id == self.active_file.id {
+            &self.active_file
+        } else {
+            self.arch_files.open(position.file_id)?
+        };
+        let entry = file.read(position.offset)?;
         if !entry.valid {
+            return None;
+        }
+        // the one copy a read makes, out of the mapping
+        std::str::from_utf8(&entry.value).ok().map(str::to_string)
+    }
+
+    fn place(&mut self, key: String, position: hash::Position) {
+        if let Some(old) = self.hash_index.set(key, position) {
+            *self.dead.entry(old.file_id).or_insert(0) += old.size;
+        }
+    }
+
+    fn retire(&mut self, key: &str) {
+        if let Some(old) = self.hash_index.delete(key) {
+            *self.dead.entry(old.file_id).or_insert(0) += old.size;
+        }
+        for index in &mut self.indexes {
+            index.remove(key);
+        }
+    }
+
+    // values that aren't json simply drop out of every index
+    fn reindex(&mut self, key: &str, value: &str) {
+        if self.indexes.is_empty() {
             return;
         }
-        match EntryType::from(entry.get_mark()) {
+        let document = serde_json::from_str::<Value>(value).ok();
+        for index in &mut self.indexes {
+            index.insert(key, document.as_ref());
+        }
+    }
+
+    fn build_index(&mut self) {
+        let mut ids = self.arch_files.ids();
+        let active_id = self.active_file.id;
+        ids.push(active_id);
+        for id in ids {
+            let file = if id == active_id {
+                mem::take(&mut self.active_file)
+            } else {
+                self.arch_files.remove(&id).unwrap()
+            };
+            let mut unit = vec![];
+            let mut entries = file.iter();
+            for (offset, entry) in &mut entries {
+                if !entry.valid {
+                    self.checksum_failures += 1;
+                    *self.dead.entry(id).or_insert(0) += entry.size();
+                    self.discard_unit(id, &mut unit);
+                    continue;
+                }
+                if (entry.state >> 8) & TXN_FLAG == 0 {
+                    self.discard_unit(id, &mut unit);
+                    self.replay_entry(id, offset, entry);
+                    continue;
+                }
+                if !matches!(EntryType::from(entry.get_mark()), EntryType::Commit) {
+                    unit.push((offset, entry));
+                    continue;
+                }
+                // anything before the last `count` entries belongs to a unit
+                // whose marker never made it to disk
+                *self.dead.entry(id).or_insert(0) += entry.size();
+                let count = std::str::from_utf8(&entry.value).ok().and_then(|count| count.parse::<usize>().ok());
+                match count {
+                    Some(count) if count <= unit.len() => {
+                        let mut committed = unit.split_off(unit.len() - count);
+                        self.discard_unit(id, &mut unit);
+                        for (offset, entry) in committed.drain(..) {
+                            self.replay_entry(id, offset, entry);
+                        }
+                    }
+                    _ => self.discard_unit(id, &mut unit),
+                }
+            }
+            self.discard_unit(id, &mut unit);
+            let end = entries.offset();
+            let mut file = file;
+            // a crash mid-write leaves a torn entry at the end of the active
+            // file, new appends must not land behind it
+            if id == active_id && end < file.offset {
+                file.truncate(end);
+            }
+            if id == active_id {
+                self.active_file = file;
+            } else {
+                self.arch_files.insert(file);
+            }
+        }
+    }
+
+    fn replay_entry(&mut self, id: u32, offset: u64, entry: entry::Entry) {
+        let clear = matches!(EntryType::from(entry.get_mark()), EntryType::Clear);
+        if clear || check_key_value_vec(&self.config, &entry.key, &entry.value) {
+            self.build_entry(id, offset, entry);
+        } else {
+            *self.dead.entry(id).or_insert(0) += entry.size();
+        }
+    }
+
+    fn discard_unit(&mut self, id: u32, unit: &mut Vec<(u64, entry::Entry)>) {
+        for (_, entry) in unit.drain(..) {
+            *self.dead.entry(id).or_insert(0) += entry.size();
+        }
+    }
+
+    fn build_entry(&mut self, id: u32, offset: u64, entry: entry::Entry) {
+        let position = hash::Position {
+            file_id: id,
+            offset,
+            size: entry.size(),
+        };
+        let mark = EntryType::from(entry.get_mark());
+        let key = match String::from_utf8(entry.key.into_owned()) {
+            Ok(key) => key,
+            Err(_) => {
+                *self.dead.entry(id).or_insert(0) += position.size;
+                return;
+            }
+        };
+        match mark {
             EntryType::Set => {
-                let key = match String::from_utf8(entry.key) {
-                    Ok(key) => key,
-                    Err(_) => return,
-                };
-                let value: String = match String::from_utf8(entry.value) {
+                let value = match std::str::from_utf8(&entry.value) {
                     Ok(value) => value,
-                    Err(_) => return,
+                    Err(_) => {
+                        *self.dead.entry(id).or_insert(0) += position.size;
+                        return;
+                    }
                 };
-                self.hash_index.set(key, value);
+                self.expires.remove(&key);
+                self.reindex(&key, value);
+                self.place(key, position);
             },
             EntryType::SetWithExpire => {
-                let key = match String::from_utf8(entry.key) {
-                    Ok(key) => key,
-                    Err(_) => return,
-                };
-                let value: String = match String::from_utf8(entry.value) {
+                let value = match std::str::from_utf8(&entry.value) {
                     Ok(value) => value,
-                    Err(_) => return,
-                };
-                if entry.time_stamp > time_routine::time_now() {
-                    if self.expires.contains_key(&key) {
-                        *self.expires.get_mut(&key).unwrap() = entry.time_stamp;
-                    } else {
-                        self.expires.insert(key.clone(), entry.time_stamp);
+                    Err(_) => {
+                        *self.dead.entry(id).or_insert(0) += position.size;
+                        return;
                     }
+                };
+                // already expired, it only hides whatever came before it
+                if entry.time_stamp < time_routine::time_now() {
+                    self.expires.remove(&key);
+                    self.retire(&key);
+                    *self.dead.entry(id).or_insert(0) += position.size;
+                    return;
                 }
-                self.hash_index.set(key, value);
+                self.expires.insert(key.clone(), entry.time_stamp);
+                self.reindex(&key, value);
+                self.place(key, position);
             },
             EntryType::Delete => {
-                let key = match String::from_utf8(entry.key) {
-                    Ok(key) => key,
-                    Err(_) => return,
-                };
-                self.hash_index.delete(key);
+                self.expires.remove(&key);
+                self.retire(&key);
+                *self.dead.entry(id).or_insert(0) += position.size;
+            },
+            EntryType::Commit => {
+                *self.dead.entry(id).or_insert(0) += position.size;
             },
             EntryType::Clear => {
-                self.hash_index.clear();
+                self.expires.clear();
+                for index in &mut self.indexes {
+                    index.clear();
+                }
+                for (_, old) in self.hash_index.drain() {
+                    *self.dead.entry(old.file_id).or_insert(0) += old.size;
+                }
+                *self.dead.entry(id).or_insert(0) += position.size;
             },
         }
     }
-}
\ No newline at end of file
+}
+
+// every test store lives in a directory of its own under the system temp
+// dir, named after the test
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util {
+    use std::env;
+    use std::fs;
+
+    use super::kv;
+    use crate::config;
+
+    // an empty directory for `name`, unique to this process
+    pub fn temp_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("mini-bitcask-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_str().unwrap().to_string()
+    }
+
+    // a store in a fresh directory for `name`, otherwise set up by `config`
+    pub fn open(name: &str, config: config::Config) -> kv {
+        kv::open(config::Config { dir_path: temp_dir(name), ..config }).unwrap()
+    }
+
+    pub fn remove(mut db: kv) {
+        db.close();
+        fs::remove_dir_all(&db.config.dir_path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_util::{open, remove};
+
+    #[test]
+    fn only_canonical_data_files_are_picked_up() {
+        assert_eq!(parse_file_id("1.data"), Some(1));
+        assert_eq!(parse_file_id("0.data"), Some(0));
+        assert_eq!(parse_file_id("4294967295.data"), Some(u32::MAX));
+        for name in ["4294967296.data", "01.data", ".data", "1.data.import", "1.DATA", "+1.data", "-1.data", "1 .data", "a.data", "1"] {
+            assert_eq!(parse_file_id(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn export_and_import_round_trip_with_deadlines() {
+        // a file per write, so the merge below has files to remove
+        let mut source = open("export", config::Config { max_file_size: 1, ..Default::default() });
+        let deadline = time_routine::time_now() + 3600;
+        source.set("plain".to_string(), "1".to_string()).unwrap();
+        source.set_with_expire("expiring".to_string(), "2".to_string(), deadline).unwrap();
+        source.set_with_expire("expired".to_string(), "3".to_string(), 1).unwrap();
+        source.set("deleted".to_string(), "4".to_string()).unwrap();
+        assert!(source.delete("deleted".to_string()));
+        let mut dump = vec![];
+        assert_eq!(source.export(&mut dump).unwrap(), 2);
+        let dump = String::from_utf8(dump).unwrap();
+        assert_eq!(dump, format!("{{\"deadline\":{},\"key\":\"expiring\",\"value\":\"2\"}}\n{{\"deadline\":null,\"key\":\"plain\",\"value\":\"1\"}}\n", deadline));
+
+        // a snapshot keeps what it saw while the store moves on
+        let snapshot = source.snapshot().unwrap();
+        source.set("plain".to_string(), "changed".to_string()).unwrap();
+        let merged = source.arch_files.ids();
+        assert!(source.merge());
+        assert!(merged.iter().all(|id| !Path::new(&source.config.dir_path).join(format!("{}.data", id)).exists()));
+        assert_eq!(snapshot.lines().collect::<io::Result<String>>().unwrap(), dump);
+
+        let mut target = open("import", config::Config::default());
+        assert_eq!(target.import(dump.as_bytes()).unwrap(), 2);
+        assert_eq!(target.get("plain".to_string()), Some("1".to_string()));
+        assert_eq!(target.get("expiring".to_string()), Some("2".to_string()));
+        assert_eq!(target.expires.get("expiring"), Some(&deadline));
+        let mut again = vec![];
+        target.export(&mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), dump);
+
+        // a bad record leaves nothing behind
+        assert_eq!(target.import("{\"key\":\"x\",\"value\":1}\n".as_bytes()).err().unwrap(), "line 1: expected string key and value");
+        let staged: Vec<_> = fs::read_dir(&target.config.dir_path).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_str().unwrap().ends_with(".import"))
+            .collect();
+        assert!(staged.is_empty());
+        // and a staged file a crash left is gone on the next open
+        let leftover = staging_path(&target.config.dir_path);
+        fs::write(&leftover, "partial").unwrap();
+        target.close();
+        let target = kv::open(target.config.clone()).unwrap();
+        assert!(!leftover.exists());
+        remove(source);
+        remove(target);
+    }
+
+    #[test]
+    fn unreadable_records_fail_the_export() {
+        let mut db = open("export-corrupt", config::Config::default());
+        db.set("a".to_string(), "1".to_string()).unwrap();
+        db.set("b".to_string(), "2".to_string()).unwrap();
+        // flip the last byte of `b`'s value
+        let path = db.active_file.file_path();
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, data).unwrap();
+        let lines: Vec<io::Result<String>> = db.snapshot().unwrap().lines().collect();
+        assert!(lines[0].is_ok());
+        assert_eq!(lines[1].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(db.export(&mut vec![]).is_err());
+        remove(db);
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn writes_whose_sync_fails_are_undone() {
+        use crate::storage::fault;
+
+        let mut db = open("sync", config::Config { sync_policy: config::SyncPolicy::Always, ..Default::default() });
+        db.set("a".to_string(), "1".to_string()).unwrap();
+        let offset = db.active_file.offset;
+        fault::fail_syncs();
+        assert_eq!(db.set("b".to_string(), "2".to_string()), Err(WriteError::Write));
+        let mut txn = db.begin();
+        txn.set("c".to_string(), "3".to_string());
+        assert_eq!(db.commit(txn), Err(txn::TxnError::Write));
+        assert!(!db.delete("a".to_string()));
+        fault::clear();
+        assert_eq!(db.active_file.offset, offset);
+        assert_eq!(db.dead_bytes(db.active_file.id), 0);
+
+        // nothing the caller was told failed comes back on replay
+        db.close();
+        let mut db = kv::open(db.config.clone()).unwrap();
+        assert_eq!(db.get("a".to_string()), Some("1".to_string()));
+        assert_eq!(db.get("b".to_string()), None);
+        assert_eq!(db.get("c".to_string()), None);
+        remove(db);
+    }
+
+    #[test]
+    fn imports_replace_cached_values() {
+        let mut db = open("import-cache", config::Config::default());
+        db.set("key".to_string(), "old".to_string()).unwrap();
+        assert_eq!(db.get("key".to_string()), Some("old".to_string()));
+        assert_eq!(db.cache.len(), 1);
+        db.import("{\"key\":\"key\",\"value\":\"new\"}\n".as_bytes()).unwrap();
+        assert_eq!(db.get("key".to_string()), Some("new".to_string()));
+        remove(db);
+    }
+
+    #[test]
+    fn staged_imports_count_against_the_quota() {
+        let mut db = open("import-quota", config::Config::default());
+        db.set("a".to_string(), "1".to_string()).unwrap();
+        let record = |key: &str| entry::Entry::new(key.as_bytes().to_vec(), b"1".to_vec(), 0, EntryType::Set.into()).size();
+        db.config.max_data_size = db.data_size() + 2 * record("k0");
+
+        // a dump past the quota stops staging as soon as it crosses it
+        let dump: String = (0..1000).map(|i| format!("{{\"key\":\"k{}\",\"value\":\"1\"}}\n", i)).collect();
+        let err = db.import(dump.as_bytes()).unwrap_err();
+        assert!(err.starts_with("line 3: data directory quota exceeded"), "{}", err);
+        assert_eq!(db.staged.load(Ordering::Relaxed), 0);
+        assert!(fs::read_dir(&db.config.dir_path).unwrap().all(|f| !f.unwrap().file_name().to_string_lossy().ends_with(".import")));
+
+        // bytes still in flight take up the quota until they are linked in
+        let used = db.data_size();
+        let staged = staging_path(&db.config.dir_path);
+        let dump = "{\"key\":\"k0\",\"value\":\"1\"}\n";
+        assert_eq!(stage_import(&db.config, dump.as_bytes(), &staged, &db.import_quota()), Ok(1));
+        assert_eq!(db.data_size(), used + record("k0"));
+        assert!(matches!(db.set("b".to_string(), "333".to_string()), Err(WriteError::Rejected(limits::Rejection::DiskQuota { .. }))));
+        // and a second import sees what the first one staged
+        let err = stage_import(&db.config, format!("{}{}", dump, dump).as_bytes(), &staging_path(&db.config.dir_path), &db.import_quota()).unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        db.link_import(&staged).unwrap();
+        assert_eq!(db.staged.load(Ordering::Relaxed), 0);
+        assert_eq!(db.data_size(), used + record("k0"));
+        assert_eq!(db.get("k0".to_string()), Some("1".to_string()));
+        remove(db);
+    }
+
+    #[test]
+    fn secondary_indexes_follow_sets_deletes_and_expiry() {
+        let config = config::Config {
+            indexes: vec!["owner".to_string()],
+            max_value_size: 1000,
+            ..Default::default()
+        };
+        let mut db = open("index", config);
+        let owner = |db: &mut kv, name: &str| db.query("owner", &json!(name)).unwrap();
+        db.set("a".to_string(), json!({ "owner": "ann" }).to_string()).unwrap();
+        db.set("b".to_string(), json!({ "owner": "ann" }).to_string()).unwrap();
+        db.set_with_expire("c".to_string(), json!({ "owner": "ann" }).to_string(), 1).unwrap();
+        assert_eq!(owner(&mut db, "ann"), vec!["a".to_string(), "b".to_string()]);
+        db.set("a".to_string(), json!({ "owner": "bob" }).to_string()).unwrap();
+        db.set("b".to_string(), "not json".to_string()).unwrap();
+        db.set("d".to_string(), json!({ "owner": "bob" }).to_string()).unwrap();
+        assert!(db.delete("d".to_string()));
+        assert!(owner(&mut db, "ann").is_empty());
+        assert_eq!(owner(&mut db, "bob"), vec!["a".to_string()]);
+        assert_eq!(db.query("missing", &json!("ann")), None);
+
+        // replay rebuilds the same index
+        db.close();
+        let mut db = kv::open(db.config.clone()).unwrap();
+        assert_eq!(owner(&mut db, "bob"), vec!["a".to_string()]);
+        assert_eq!(db.indexes[0].len(), 1);
+        assert!(db.clear());
+        assert!(owner(&mut db, "bob").is_empty());
+        remove(db);
+    }
+}
@@ -0,0 +1,8 @@
+pub mod ds;
+pub mod kv;
+pub mod utils;
+pub mod config;
+pub mod storage;
+pub mod metrics;
+pub mod txn;
+pub mod limits;
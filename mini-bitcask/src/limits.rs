@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config;
+
+// buckets of idle clients are refilled anyway, so they are dropped once the
+// table grows past this
+const MAX_TRACKED_CLIENTS: usize = 4096;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    RequestRate,
+    WriteRate,
+    DiskQuota { used: u64, limit: u64 },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::RequestRate => write!(f, "request rate limit exceeded"),
+            Rejection::WriteRate => write!(f, "write bandwidth limit exceeded"),
+            Rejection::DiskQuota { used, limit } => write!(f, "data directory quota exceeded ({} of {} bytes used)", used, limit),
+        }
+    }
+}
+
+struct Bucket {
+    requests: f64,
+    bytes: f64,
+    last: Instant,
+}
+
+// one token bucket pair per client address, each holding at most one
+// second's worth of its rate; a rate of 0 turns that limit off. The byte
+// bucket may go into debt: a charge bigger than it can ever hold goes
+// through once it is full and later charges wait for the debt to clear
+#[derive(Default)]
+pub struct Limiter {
+    requests_per_sec: u64,
+    bytes_per_sec: u64,
+    clients: Mutex<HashMap<IpAddr, Bucket>>,
+    pub throttled_requests: AtomicU64,
+    pub throttled_bytes: AtomicU64,
+}
+
+impl Limiter {
+    pub fn new(config: &config::Config) -> Limiter {
+        Limiter {
+            requests_per_sec: config.client_requests_per_sec,
+            bytes_per_sec: config.client_bytes_per_sec,
+            ..Default::default()
+        }
+    }
+
+    pub fn admit(&self, client: IpAddr) -> Result<(), Rejection> {
+        if self.requests_per_sec == 0 {
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().unwrap();
+        let bucket = self.refill(&mut clients, client);
+        if bucket.requests < 1.0 {
+            self.throttled_requests.fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection::RequestRate);
+        }
+        bucket.requests -= 1.0;
+        Ok(())
+    }
+
+    pub fn charge(&self, client: IpAddr, bytes: u64) -> Result<(), Rejection> {
+        if self.bytes_per_sec == 0 {
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().unwrap();
+        let bucket = self.refill(&mut clients, client);
+        if bucket.bytes < (bytes as f64).min(self.bytes_per_sec as f64) {
+            self.throttled_bytes.fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection::WriteRate);
+        }
+        bucket.bytes -= bytes as f64;
+        Ok(())
+    }
+
+    // charges `bytes` unconditionally and says how long to wait before
+    // sending more; streamed bodies pace themselves with it instead of
+    // being turned away part way through
+    pub fn reserve(&self, client: IpAddr, bytes: u64) -> Duration {
+        if self.bytes_per_sec == 0 {
+            return Duration::ZERO;
+        }
+        let mut clients = self.clients.lock().unwrap();
+        let bucket = self.refill(&mut clients, client);
+        bucket.bytes -= bytes as f64;
+        if bucket.bytes >= 0.0 {
+            return Duration::ZERO;
+        }
+        self.throttled_bytes.fetch_add(1, Ordering::Relaxed);
+        Duration::from_secs_f64(-bucket.bytes / self.bytes_per_sec as f64)
+    }
+
+    fn refill<'a>(&self, clients: &'a mut HashMap<IpAddr, Bucket>, client: IpAddr) -> &'a mut Bucket {
+        let now = Instant::now();
+        let requests = self.requests_per_sec as f64;
+        let bytes = self.bytes_per_sec as f64;
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&client) {
+            clients.retain(|_, bucket| {
+                let idle = now.duration_since(bucket.last).as_secs_f64();
+                bucket.requests + idle * requests < requests || bucket.bytes + idle * bytes < bytes
+            });
+        }
+        let bucket = clients.entry(client).or_insert(Bucket {
+            requests,
+            bytes,
+            last: now,
+        });
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.requests = (bucket.requests + elapsed * requests).min(requests);
+        bucket.bytes = (bucket.bytes + elapsed * bytes).min(bytes);
+        bucket.last = now;
+        bucket
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP bitcask_throttled_total Requests rejected by a per client limit.\n");
+        out.push_str("# TYPE bitcask_throttled_total counter\n");
+        let _ = writeln!(out, "bitcask_throttled_total{{reason=\"request_rate\"}} {}", self.throttled_requests.load(Ordering::Relaxed));
+        let _ = writeln!(out, "bitcask_throttled_total{{reason=\"write_rate\"}} {}", self.throttled_bytes.load(Ordering::Relaxed));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn with_rates(requests_per_sec: u64, bytes_per_sec: u64) -> Limiter {
+        Limiter::new(&config::Config { client_requests_per_sec: requests_per_sec, client_bytes_per_sec: bytes_per_sec, ..Default::default() })
+    }
+
+    #[test]
+    fn buckets_refill_at_their_rate_per_client() {
+        let limiter = with_rates(20, 1000);
+        let client: IpAddr = [10, 0, 0, 1].into();
+        for _ in 0..20 {
+            assert_eq!(limiter.admit(client), Ok(()));
+        }
+        assert_eq!(limiter.admit(client), Err(Rejection::RequestRate));
+        // another address has a bucket of its own
+        assert_eq!(limiter.admit([10, 0, 0, 2].into()), Ok(()));
+        assert_eq!(limiter.charge(client, 600), Ok(()));
+        assert_eq!(limiter.charge(client, 600), Err(Rejection::WriteRate));
+        thread::sleep(Duration::from_millis(250));
+        // a quarter second is 5 requests and 250 bytes, never more than a
+        // second's worth
+        for _ in 0..4 {
+            assert_eq!(limiter.admit(client), Ok(()));
+        }
+        assert_eq!(limiter.charge(client, 600), Ok(()));
+        assert_eq!(limiter.throttled_requests.load(Ordering::Relaxed), 1);
+        assert_eq!(limiter.throttled_bytes.load(Ordering::Relaxed), 1);
+        assert!(limiter.render().contains("bitcask_throttled_total{reason=\"write_rate\"} 1\n"));
+    }
+
+    #[test]
+    fn charges_beyond_one_second_go_into_debt() {
+        let limiter = with_rates(0, 1000);
+        let client: IpAddr = [10, 0, 0, 1].into();
+        assert_eq!(limiter.charge(client, 5000), Ok(()));
+        assert_eq!(limiter.charge(client, 1), Err(Rejection::WriteRate));
+        // reserving never refuses, it says how long the debt takes to clear
+        let wait = limiter.reserve(client, 1000);
+        assert!(wait > Duration::from_millis(4900) && wait <= Duration::from_secs(5), "{:?}", wait);
+        assert_eq!(with_rates(0, 0).reserve(client, u64::MAX), Duration::ZERO);
+        assert_eq!(limiter.admit(client), Ok(()));
+    }
+}
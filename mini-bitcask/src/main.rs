@@ -1,30 +1,55 @@
-mod ds;
-mod kv;
-mod utils;
-mod config;
-mod storage;
-
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::mem;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use serde_json::{Value, json};
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
-use axum::{routing::post, Router, Json, Extension};
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use axum::{routing::{get, post}, Router, Json, Extension};
+use axum::body::{Bytes, StreamBody};
+use axum::extract::{BodyStream, ConnectInfo};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use mini_bitcask::{config, kv, limits, metrics, txn};
+use mini_bitcask::storage::entry::ENTRY_HEADER_SIZE;
 
+#[derive(Default)]
 enum Operation {
+    #[default]
     Get,
     Set,
     SetWithExpire,
     Delete,
     Clear,
     Close,
+    Metrics,
+    Export,
+    StageImport,
+    Import,
+    Query,
+    TxnBegin,
+    TxnGet,
+    TxnSet,
+    TxnDelete,
+    TxnCommit,
+    TxnAbort,
 }
 
-impl Default for Operation {
-    fn default() -> Self {
-        Operation::Get
-    }
-}
+const EXPORT_CHUNK_KEYS: usize = 1024;
+// transactions untouched for this long are aborted by the next request to
+// the store
+const TXN_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Default)]
 struct Message {
@@ -32,85 +57,123 @@ struct Message {
     key: Option<String>,
     value: Option<String>,
     deadline: Option<u64>,
+    txn: Option<u64>,
     channel: Option<oneshot::Sender<(bool, String)>>,
+    snapshot: Option<oneshot::Sender<io::Result<kv::Snapshot>>>,
+    quota: Option<oneshot::Sender<kv::ImportQuota>>,
 }
 
 async fn kv_get (
     Json(payload): Json<serde_json::Value>,
     Extension(state): Extension<mpsc::Sender<Message>>,
+    Extension(metrics): Extension<Arc<metrics::Metrics>>,
 ) -> Json<Value>  {
+    let start = Instant::now();
     let key = payload.as_object().unwrap().get("key").unwrap().as_str().unwrap().to_string();
     let (tx, rx) = oneshot::channel();
-    let mut message = Message::default();
-    message.method = Operation::Get;
-    message.key = Some(key);
-    message.channel = Some(tx);
+    let message = Message {
+        method: Operation::Get,
+        key: Some(key),
+        channel: Some(tx),
+        ..Default::default()
+    };
     state.send(message).await.unwrap();
     let res = rx.await.unwrap();
+    metrics.observe("get", res.0, start.elapsed());
     Json(json!({ "status": res.0, "data": res.1 }))
 }
 
 async fn kv_set (
     Json(payload): Json<serde_json::Value>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Extension(state): Extension<mpsc::Sender<Message>>,
-) -> Json<Value> {
+    Extension(metrics): Extension<Arc<metrics::Metrics>>,
+    Extension(limiter): Extension<Arc<limits::Limiter>>,
+) -> (StatusCode, Json<Value>) {
+    let start = Instant::now();
     let key = payload.as_object().unwrap().get("key").unwrap().as_str().unwrap().to_string();
     let value = payload.as_object().unwrap().get("value").unwrap().as_str().unwrap().to_string();
+    if let Err(e) = limiter.charge(client.ip(), ENTRY_HEADER_SIZE + (key.len() + value.len()) as u64) {
+        return (StatusCode::TOO_MANY_REQUESTS, Json(json!({ "status": false, "error": e.to_string() })));
+    }
     let (tx, rx) = oneshot::channel();
-    let mut message = Message::default();
-    message.method = Operation::Set;
-    message.key = Some(key);
-    message.value = Some(value);
-    message.channel = Some(tx);
+    let message = Message {
+        method: Operation::Set,
+        key: Some(key),
+        value: Some(value),
+        channel: Some(tx),
+        ..Default::default()
+    };
     state.send(message).await.unwrap();
     let res = rx.await.unwrap();
-    Json(json!({ "status": res.0 }))
+    metrics.observe("set", res.0, start.elapsed());
+    write_reply(res)
 }
 
 async fn kv_set_with_expire (
     Json(payload): Json<serde_json::Value>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Extension(state): Extension<mpsc::Sender<Message>>,
-) -> Json<Value> {
+    Extension(metrics): Extension<Arc<metrics::Metrics>>,
+    Extension(limiter): Extension<Arc<limits::Limiter>>,
+) -> (StatusCode, Json<Value>) {
+    let start = Instant::now();
     let key = payload.as_object().unwrap().get("key").unwrap().as_str().unwrap().to_string();
     let value = payload.as_object().unwrap().get("value").unwrap().as_str().unwrap().to_string();
     let deadline = payload.as_object().unwrap().get("value").unwrap().as_u64().unwrap();
+    if let Err(e) = limiter.charge(client.ip(), ENTRY_HEADER_SIZE + (key.len() + value.len()) as u64) {
+        return (StatusCode::TOO_MANY_REQUESTS, Json(json!({ "status": false, "error": e.to_string() })));
+    }
     let (tx, rx) = oneshot::channel();
-    let mut message = Message::default();
-    message.method = Operation::SetWithExpire;
-    message.key = Some(key);
-    message.value = Some(value);
-    message.deadline = Some(deadline);
-    message.channel = Some(tx);
+    let message = Message {
+        method: Operation::SetWithExpire,
+        key: Some(key),
+        value: Some(value),
+        deadline: Some(deadline),
+        channel: Some(tx),
+        ..Default::default()
+    };
     state.send(message).await.unwrap();
     let res = rx.await.unwrap();
-    Json(json!({ "status": res.0 }))
+    metrics.observe("set_with_expire", res.0, start.elapsed());
+    write_reply(res)
 }
 
 
 async fn kv_delete (
     Json(payload): Json<serde_json::Value>,
     Extension(state): Extension<mpsc::Sender<Message>>,
+    Extension(metrics): Extension<Arc<metrics::Metrics>>,
 ) -> Json<Value> {
+    let start = Instant::now();
     let key = payload.as_object().unwrap().get("key").unwrap().as_str().unwrap().to_string();
     let (tx, rx) = oneshot::channel();
-    let mut message = Message::default();
-    message.method = Operation::Delete;
-    message.key = Some(key);
-    message.channel = Some(tx);
+    let message = Message {
+        method: Operation::Delete,
+        key: Some(key),
+        channel: Some(tx),
+        ..Default::default()
+    };
     state.send(message).await.unwrap();
     let res = rx.await.unwrap();
+    metrics.observe("delete", res.0, start.elapsed());
     Json(json!({ "status": res.0 }))
 }
 
 async fn kv_clear (
     Extension(state): Extension<mpsc::Sender<Message>>,
+    Extension(metrics): Extension<Arc<metrics::Metrics>>,
 ) -> Json<Value> {
+    let start = Instant::now();
     let (tx, rx) = oneshot::channel();
-    let mut message = Message::default();
-    message.method = Operation::Clear;
-    message.channel = Some(tx);
+    let message = Message {
+        method: Operation::Clear,
+        channel: Some(tx),
+        ..Default::default()
+    };
     state.send(message).await.unwrap();
     let res = rx.await.unwrap();
+    metrics.observe("clear", res.0, start.elapsed());
     Json(json!({ "status": res.0 }))
 }
 
@@ -118,23 +181,405 @@ async fn kv_close (
     Extension(state): Extension<mpsc::Sender<Message>>,
 ) -> Json<Value> {
     let (tx, rx) = oneshot::channel();
-    let mut message = Message::default();
-    message.method = Operation::Close;
-    message.channel = Some(tx);
+    let message = Message {
+        method: Operation::Close,
+        channel: Some(tx),
+        ..Default::default()
+    };
     state.send(message).await.unwrap();
     let res = rx.await.unwrap();
     Json(json!({ "status": res.0 }))
 }
 
-#[tokio::main]
-async fn main() {
-    let args: Vec<String> = env::args().collect();
-    let port = if args.len() > 1 && args[1].parse::<u16>().is_ok() {
-        args[1].parse::<u16>().unwrap()
+async fn kv_metrics (
+    Extension(state): Extension<mpsc::Sender<Message>>,
+    Extension(metrics): Extension<Arc<metrics::Metrics>>,
+    Extension(limiter): Extension<Arc<limits::Limiter>>,
+) -> impl IntoResponse {
+    let queue_depth = state.max_capacity() - state.capacity();
+    let (tx, rx) = oneshot::channel();
+    let message = Message {
+        method: Operation::Metrics,
+        channel: Some(tx),
+        ..Default::default()
+    };
+    state.send(message).await.unwrap();
+    let res = rx.await.unwrap();
+    let body = metrics.render(queue_depth, state.max_capacity()) + &limiter.render() + &res.1;
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+// the store only takes the snapshot; reading values and waiting on the
+// client happen on a blocking thread of their own
+async fn kv_export (
+    Extension(state): Extension<mpsc::Sender<Message>>,
+) -> Response {
+    let (tx, rx) = oneshot::channel();
+    let message = Message {
+        method: Operation::Export,
+        snapshot: Some(tx),
+        ..Default::default()
+    };
+    state.send(message).await.unwrap();
+    let snapshot = match rx.await.unwrap() {
+        Ok(snapshot) => snapshot,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": false, "error": e.to_string() }))).into_response(),
+    };
+    let (tx, rx) = mpsc::channel(16);
+    tokio::task::spawn_blocking(move || {
+        let mut chunk = String::new();
+        for (i, line) in snapshot.lines().enumerate() {
+            match line {
+                Ok(line) => chunk.push_str(&line),
+                // the body breaks off, so the client can't mistake what it
+                // got for the whole export
+                Err(e) => {
+                    eprintln!("export failed: {}", e);
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            }
+            if (i + 1) % EXPORT_CHUNK_KEYS == 0 && tx.blocking_send(Ok(mem::take(&mut chunk))).is_err() {
+                return;
+            }
+        }
+        if !chunk.is_empty() {
+            let _ = tx.blocking_send(Ok(chunk));
+        }
+    });
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], StreamBody::new(ReceiverStream::new(rx))).into_response()
+}
+
+// hands the chunks of a request body to blocking code as one reader; an
+// error in the body fails the read instead of looking like its end
+struct ChunkReader {
+    chunks: mpsc::Receiver<Result<Bytes, String>>,
+    current: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => self.current = chunk,
+                Some(Err(e)) => return Err(io::Error::other(e)),
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current[..len]);
+        self.current = self.current.slice(len..);
+        Ok(len)
+    }
+}
+
+// the body is staged to disk as it arrives, outside the store, which only
+// links the finished file in; a client over its byte rate is slowed down
+// rather than refused
+async fn kv_import (
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Extension(state): Extension<mpsc::Sender<Message>>,
+    Extension(limiter): Extension<Arc<limits::Limiter>>,
+    Extension(config): Extension<Arc<config::Config>>,
+    mut body: BodyStream,
+) -> Json<Value> {
+    let (tx, rx) = oneshot::channel();
+    let message = Message {
+        method: Operation::StageImport,
+        quota: Some(tx),
+        ..Default::default()
+    };
+    state.send(message).await.unwrap();
+    let quota = rx.await.unwrap();
+    let staged = kv::staging_path(&config.dir_path);
+    let (tx, rx) = mpsc::channel(16);
+    let stage = {
+        let staged = staged.clone();
+        let reader = BufReader::new(ChunkReader { chunks: rx, current: Bytes::new() });
+        tokio::task::spawn_blocking(move || kv::stage_import(&config, reader, &staged, &quota))
+    };
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| e.to_string());
+        if let Ok(chunk) = &chunk {
+            tokio::time::sleep(limiter.reserve(client.ip(), chunk.len() as u64)).await;
+        }
+        let failed = chunk.is_err();
+        // a send only fails once staging gave up on a bad record
+        if tx.send(chunk).await.is_err() || failed {
+            break;
+        }
+    }
+    drop(tx);
+    let count = match stage.await.unwrap() {
+        Ok(count) => count,
+        Err(e) => return Json(json!({ "status": false, "error": e })),
+    };
+    let (tx, rx) = oneshot::channel();
+    let message = Message {
+        method: Operation::Import,
+        value: Some(staged.to_string_lossy().into_owned()),
+        channel: Some(tx),
+        ..Default::default()
+    };
+    state.send(message).await.unwrap();
+    let res = rx.await.unwrap();
+    if res.0 {
+        Json(json!({ "status": true, "data": count }))
     } else {
-        3010
+        Json(json!({ "status": false, "error": res.1 }))
+    }
+}
+
+async fn kv_query (
+    Json(payload): Json<serde_json::Value>,
+    Extension(state): Extension<mpsc::Sender<Message>>,
+) -> Json<Value> {
+    let index = payload.as_object().unwrap().get("index").unwrap().as_str().unwrap().to_string();
+    let value = payload.as_object().unwrap().get("value").unwrap().to_string();
+    let (tx, rx) = oneshot::channel();
+    let message = Message {
+        method: Operation::Query,
+        key: Some(index),
+        value: Some(value),
+        channel: Some(tx),
+        ..Default::default()
     };
-    let (tx, mut rx) = mpsc::channel(32);
+    state.send(message).await.unwrap();
+    let res = rx.await.unwrap();
+    if res.0 {
+        Json(json!({ "status": true, "data": serde_json::from_str::<Value>(&res.1).unwrap() }))
+    } else {
+        Json(json!({ "status": false, "error": res.1 }))
+    }
+}
+
+// every /txn route takes optional `txn`, `key`, `value` and `deadline` fields
+// and the store answers with the response body itself
+async fn txn_call(state: &mpsc::Sender<Message>, method: Operation, payload: &Value) -> (bool, Json<Value>) {
+    let (tx, rx) = oneshot::channel();
+    let message = Message {
+        method,
+        key: payload.get("key").and_then(Value::as_str).map(|key| key.to_string()),
+        value: payload.get("value").and_then(Value::as_str).map(|value| value.to_string()),
+        deadline: payload.get("deadline").and_then(Value::as_u64),
+        txn: payload.get("txn").and_then(Value::as_u64),
+        channel: Some(tx),
+        ..Default::default()
+    };
+    state.send(message).await.unwrap();
+    let res = rx.await.unwrap();
+    (res.0, Json(serde_json::from_str(&res.1).unwrap()))
+}
+
+async fn kv_txn_begin (
+    Extension(state): Extension<mpsc::Sender<Message>>,
+) -> Json<Value> {
+    txn_call(&state, Operation::TxnBegin, &json!({})).await.1
+}
+
+async fn kv_txn_get (
+    Json(payload): Json<serde_json::Value>,
+    Extension(state): Extension<mpsc::Sender<Message>>,
+) -> Json<Value> {
+    txn_call(&state, Operation::TxnGet, &payload).await.1
+}
+
+async fn kv_txn_set (
+    Json(payload): Json<serde_json::Value>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Extension(state): Extension<mpsc::Sender<Message>>,
+    Extension(limiter): Extension<Arc<limits::Limiter>>,
+) -> Json<Value> {
+    let key = payload.get("key").and_then(Value::as_str).unwrap_or("");
+    let value = payload.get("value").and_then(Value::as_str).unwrap_or("");
+    if let Err(e) = limiter.charge(client.ip(), ENTRY_HEADER_SIZE + (key.len() + value.len()) as u64) {
+        return Json(json!({ "status": false, "error": e.to_string() }));
+    }
+    txn_call(&state, Operation::TxnSet, &payload).await.1
+}
+
+async fn kv_txn_delete (
+    Json(payload): Json<serde_json::Value>,
+    Extension(state): Extension<mpsc::Sender<Message>>,
+) -> Json<Value> {
+    txn_call(&state, Operation::TxnDelete, &payload).await.1
+}
+
+async fn kv_txn_commit (
+    Json(payload): Json<serde_json::Value>,
+    Extension(state): Extension<mpsc::Sender<Message>>,
+    Extension(metrics): Extension<Arc<metrics::Metrics>>,
+) -> Json<Value> {
+    let start = Instant::now();
+    let (status, body) = txn_call(&state, Operation::TxnCommit, &payload).await;
+    metrics.observe("commit", status, start.elapsed());
+    body
+}
+
+async fn kv_txn_abort (
+    Json(payload): Json<serde_json::Value>,
+    Extension(state): Extension<mpsc::Sender<Message>>,
+) -> Json<Value> {
+    txn_call(&state, Operation::TxnAbort, &payload).await.1
+}
+
+// only a write the quota turned away carries its reason back
+fn write_result(res: Result<(), kv::WriteError>) -> (bool, String) {
+    match res {
+        Ok(()) => (true, "".to_string()),
+        Err(kv::WriteError::Rejected(e)) => (false, e.to_string()),
+        Err(_) => (false, "".to_string()),
+    }
+}
+
+// a failed write that carries a message was turned away by the quota
+fn write_reply(res: (bool, String)) -> (StatusCode, Json<Value>) {
+    if res.0 || res.1.is_empty() {
+        (StatusCode::OK, Json(json!({ "status": res.0 })))
+    } else {
+        (StatusCode::INSUFFICIENT_STORAGE, Json(json!({ "status": false, "error": res.1 })))
+    }
+}
+
+// every route but /metrics counts against the client's request rate
+async fn throttle<B>(req: Request<B>, next: Next<B>) -> Response {
+    if req.uri().path() != "/metrics" {
+        let client = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip());
+        let limiter = req.extensions().get::<Arc<limits::Limiter>>();
+        if let (Some(client), Some(limiter)) = (client, limiter) {
+            if let Err(e) = limiter.admit(client) {
+                return (StatusCode::TOO_MANY_REQUESTS, Json(json!({ "status": false, "error": e.to_string() }))).into_response();
+            }
+        }
+    }
+    next.run(req).await
+}
+
+fn expire_transactions(db: &mut kv::kv, transactions: &mut HashMap<u64, (Instant, txn::Transaction)>) {
+    let idle: Vec<u64> = transactions.iter()
+        .filter(|(_, (last_used, _))| last_used.elapsed() >= TXN_IDLE_TIMEOUT)
+        .map(|(id, _)| *id)
+        .collect();
+    for id in idle {
+        let (_, transaction) = transactions.remove(&id).unwrap();
+        db.abort(transaction);
+    }
+}
+
+fn run_txn(db: &mut kv::kv, transactions: &mut HashMap<u64, (Instant, txn::Transaction)>, message: &Message) -> Value {
+    let id = match message.txn {
+        Some(id) => id,
+        None => return json!({ "status": false, "error": "missing txn" }),
+    };
+    if let Operation::TxnCommit | Operation::TxnAbort = message.method {
+        let (_, transaction) = match transactions.remove(&id) {
+            Some(transaction) => transaction,
+            None => return json!({ "status": false, "error": "unknown transaction" }),
+        };
+        if let Operation::TxnAbort = message.method {
+            db.abort(transaction);
+            return json!({ "status": true });
+        }
+        return match db.commit(transaction) {
+            Ok(()) => json!({ "status": true }),
+            Err(e) => json!({ "status": false, "error": e.to_string(), "conflict": matches!(e, txn::TxnError::Conflict(_)) }),
+        };
+    }
+    let (last_used, transaction) = match transactions.get_mut(&id) {
+        Some(transaction) => transaction,
+        None => return json!({ "status": false, "error": "unknown transaction" }),
+    };
+    *last_used = Instant::now();
+    let key = match message.key.clone() {
+        Some(key) => key,
+        None => return json!({ "status": false, "error": "missing key" }),
+    };
+    match message.method {
+        Operation::TxnGet => match transaction.get(db, key) {
+            Some(value) => json!({ "status": true, "data": value }),
+            None => json!({ "status": false, "data": "" }),
+        },
+        Operation::TxnSet => {
+            match (message.value.clone(), message.deadline) {
+                (Some(value), Some(deadline)) => transaction.set_with_expire(key, value, deadline),
+                (Some(value), None) => transaction.set(key, value),
+                (None, _) => return json!({ "status": false, "error": "missing value" }),
+            }
+            json!({ "status": true })
+        }
+        _ => {
+            transaction.delete(key);
+            json!({ "status": true })
+        }
+    }
+}
+
+fn load_config(args: &[String]) -> config::Config {
+    let config = match config::load(args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = fs::create_dir_all(&config.dir_path) {
+        eprintln!("data_dir `{}` can't be created: {}", config.dir_path, e);
+        process::exit(1);
+    }
+    config
+}
+
+// `export [flags]` writes every live pair to stdout and `import <file> [flags]`
+// bulk loads a dump; both open the data dir directly, so the server must not
+// be running on it at the same time
+fn run_offline(args: &[String]) {
+    let export = args[0] == "export";
+    let input = if export {
+        None
+    } else {
+        match args.get(1) {
+            Some(path) => Some(path.clone()),
+            None => {
+                eprintln!("usage: mini-bitcask import <file> [flags]");
+                process::exit(1);
+            }
+        }
+    };
+    let config = load_config(&args[if export { 1 } else { 2 }..]);
+    let mut db = kv::kv::open(config).expect("kv open internal error");
+    let result = match input {
+        None => {
+            let stdout = io::stdout();
+            let mut out = io::BufWriter::new(stdout.lock());
+            db.export(&mut out).map_err(|e| e.to_string())
+        }
+        Some(path) => match File::open(&path) {
+            Ok(file) => db.import(BufReader::new(file)),
+            Err(e) => Err(format!("can't open `{}`: {}", path, e)),
+        },
+    };
+    db.close();
+    match result {
+        Ok(count) => eprintln!("{} {} records", args[0], count),
+        Err(e) => {
+            eprintln!("{} failed: {}", args[0], e);
+            process::exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|arg| arg == "export" || arg == "import").unwrap_or(false) {
+        run_offline(&args);
+        return;
+    }
+    let config = load_config(&args);
+    let addr = config.bind_addr;
+    let limiter = Arc::new(limits::Limiter::new(&config));
+    let shared_config = Arc::new(config.clone());
+    let (tx, mut rx) = mpsc::channel(config.queue_capacity);
     tx.send(Message::default()).await.unwrap();
     rx.recv().await.unwrap();
     tokio::spawn(async move {
@@ -145,18 +590,37 @@ async fn main() {
             .route("/key/delete", post(kv_delete))
             .route("/key/clear", post(kv_clear))
             .route("/close", post(kv_close))
-            .layer(Extension(tx));
-        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            .route("/metrics", get(kv_metrics))
+            .route("/export", get(kv_export))
+            .route("/import", post(kv_import))
+            .route("/index/query", post(kv_query))
+            .route("/txn/begin", post(kv_txn_begin))
+            .route("/txn/get", post(kv_txn_get))
+            .route("/txn/set", post(kv_txn_set))
+            .route("/txn/delete", post(kv_txn_delete))
+            .route("/txn/commit", post(kv_txn_commit))
+            .route("/txn/abort", post(kv_txn_abort))
+            .layer(middleware::from_fn(throttle))
+            .layer(Extension(tx))
+            .layer(Extension(Arc::new(metrics::Metrics::default())))
+            .layer(Extension(limiter))
+            .layer(Extension(shared_config));
         println!("listening on {}", addr);
         axum::Server::bind(&addr)
-            .serve(app.into_make_service())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
             .await
             .unwrap();
     });
 
-    let config = config::default_config();
     let mut db = kv::kv::open(config).expect("kv open internal error");
+    let mut transactions = HashMap::new();
+    let mut next_txn = 0;
     while let Some(message) = rx.recv().await {
+        // an abandoned transaction would otherwise keep every write stamping
+        // its key
+        if !transactions.is_empty() {
+            expire_transactions(&mut db, &mut transactions);
+        }
         match message.method {
             Operation::Get => {
                 if let Some(value) = db.get(message.key.unwrap()) {
@@ -166,18 +630,12 @@ async fn main() {
                 }
             }
             Operation::Set => {
-                if db.set(message.key.unwrap(), message.value.unwrap()) {
-                    message.channel.unwrap().send((true, "".to_string())).unwrap();
-                } else {
-                    message.channel.unwrap().send((false, "".to_string())).unwrap();
-                }
+                let res = db.set(message.key.unwrap(), message.value.unwrap());
+                message.channel.unwrap().send(write_result(res)).unwrap();
             }
             Operation::SetWithExpire => {
-                if db.set_with_expire(message.key.unwrap(), message.value.unwrap(), message.deadline.unwrap()) {
-                    message.channel.unwrap().send((true, "".to_string())).unwrap();
-                } else {
-                    message.channel.unwrap().send((false, "".to_string())).unwrap();
-                }
+                let res = db.set_with_expire(message.key.unwrap(), message.value.unwrap(), message.deadline.unwrap());
+                message.channel.unwrap().send(write_result(res)).unwrap();
             }
             Operation::Delete => {
                 if db.delete(message.key.unwrap()) {
@@ -197,6 +655,39 @@ async fn main() {
                 db.close();
                 panic!()
             }
+            Operation::Metrics => {
+                message.channel.unwrap().send((true, metrics::render_store(&db))).unwrap();
+            }
+            Operation::Export => {
+                let _ = message.snapshot.unwrap().send(db.snapshot());
+            }
+            Operation::StageImport => {
+                let _ = message.quota.unwrap().send(db.import_quota());
+            }
+            Operation::Import => {
+                match db.link_import(Path::new(&message.value.unwrap())) {
+                    Ok(()) => message.channel.unwrap().send((true, "".to_string())).unwrap(),
+                    Err(e) => message.channel.unwrap().send((false, e)).unwrap(),
+                }
+            }
+            Operation::Query => {
+                let index = message.key.unwrap();
+                let value: Value = serde_json::from_str(&message.value.unwrap()).unwrap();
+                match db.query(&index, &value) {
+                    Some(keys) => message.channel.unwrap().send((true, json!(keys).to_string())).unwrap(),
+                    None => message.channel.unwrap().send((false, format!("no index on `{}`", index))).unwrap(),
+                }
+            }
+            Operation::TxnBegin => {
+                next_txn += 1;
+                transactions.insert(next_txn, (Instant::now(), db.begin()));
+                message.channel.unwrap().send((true, json!({ "status": true, "data": next_txn }).to_string())).unwrap();
+            }
+            Operation::TxnGet | Operation::TxnSet | Operation::TxnDelete | Operation::TxnCommit | Operation::TxnAbort => {
+                let reply = run_txn(&mut db, &mut transactions, &message);
+                let status = reply["status"].as_bool().unwrap_or(false);
+                message.channel.unwrap().send((status, reply.to_string())).unwrap();
+            }
         }
     }
 }
@@ -0,0 +1,224 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::kv;
+
+pub const OPERATIONS: [&str; 6] = ["get", "set", "set_with_expire", "delete", "clear", "commit"];
+
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                self.buckets[i].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    latencies: [Histogram; OPERATIONS.len()],
+    failures: [AtomicU64; OPERATIONS.len()],
+}
+
+impl Metrics {
+    pub fn observe(&self, op: &str, status: bool, elapsed: Duration) {
+        let i = match OPERATIONS.iter().position(|name| *name == op) {
+            Some(i) => i,
+            None => return,
+        };
+        self.latencies[i].observe(elapsed);
+        if !status {
+            self.failures[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn render(&self, queue_depth: usize, queue_capacity: usize) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP bitcask_requests_total Requests handled per operation.\n");
+        out.push_str("# TYPE bitcask_requests_total counter\n");
+        for (i, op) in OPERATIONS.iter().enumerate() {
+            let count = self.latencies[i].count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "bitcask_requests_total{{op=\"{}\"}} {}", op, count);
+        }
+        out.push_str("# HELP bitcask_request_failures_total Requests answered with a false status per operation.\n");
+        out.push_str("# TYPE bitcask_request_failures_total counter\n");
+        for (i, op) in OPERATIONS.iter().enumerate() {
+            let count = self.failures[i].load(Ordering::Relaxed);
+            let _ = writeln!(out, "bitcask_request_failures_total{{op=\"{}\"}} {}", op, count);
+        }
+        out.push_str("# HELP bitcask_request_duration_seconds Request latency per operation, including time queued for the store.\n");
+        out.push_str("# TYPE bitcask_request_duration_seconds histogram\n");
+        for (i, op) in OPERATIONS.iter().enumerate() {
+            let histogram = &self.latencies[i];
+            for (j, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                let count = histogram.buckets[j].load(Ordering::Relaxed);
+                let _ = writeln!(out, "bitcask_request_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}", op, bound, count);
+            }
+            let count = histogram.count.load(Ordering::Relaxed);
+            let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            let _ = writeln!(out, "bitcask_request_duration_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}", op, count);
+            let _ = writeln!(out, "bitcask_request_duration_seconds_sum{{op=\"{}\"}} {}", op, sum);
+            let _ = writeln!(out, "bitcask_request_duration_seconds_count{{op=\"{}\"}} {}", op, count);
+        }
+        out.push_str("# HELP bitcask_queue_depth Messages waiting in the store channel.\n");
+        out.push_str("# TYPE bitcask_queue_depth gauge\n");
+        let _ = writeln!(out, "bitcask_queue_depth {}", queue_depth);
+        out.push_str("# HELP bitcask_queue_capacity Capacity of the store channel.\n");
+        out.push_str("# TYPE bitcask_queue_capacity gauge\n");
+        let _ = writeln!(out, "bitcask_queue_capacity {}", queue_capacity);
+        out
+    }
+}
+
+pub fn render_store(db: &kv::kv) -> String {
+    let mut out = String::new();
+    out.push_str("# HELP bitcask_keys Keys currently held in the index.\n");
+    out.push_str("# TYPE bitcask_keys gauge\n");
+    let _ = writeln!(out, "bitcask_keys {}", db.hash_index.len());
+    out.push_str("# HELP bitcask_index_keys Keys with an indexed value per secondary index.\n");
+    out.push_str("# TYPE bitcask_index_keys gauge\n");
+    for index in &db.indexes {
+        let _ = writeln!(out, "bitcask_index_keys{{path=\"{}\"}} {}", index.path, index.len());
+    }
+    let mut ids = db.arch_files.ids();
+    ids.push(db.active_file.id);
+    out.push_str("# HELP bitcask_file_live_bytes Bytes of entries still referenced by the index per data file.\n");
+    out.push_str("# TYPE bitcask_file_live_bytes gauge\n");
+    for id in ids.iter() {
+        let _ = writeln!(out, "bitcask_file_live_bytes{{file=\"{}\"}} {}", id, db.live_bytes(*id));
+    }
+    out.push_str("# HELP bitcask_file_dead_bytes Bytes of overwritten, deleted or corrupt entries per data file.\n");
+    out.push_str("# TYPE bitcask_file_dead_bytes gauge\n");
+    for id in ids.iter() {
+        let _ = writeln!(out, "bitcask_file_dead_bytes{{file=\"{}\"}} {}", id, db.dead_bytes(*id));
+    }
+    out.push_str("# HELP bitcask_files Data files in the data directory.\n");
+    out.push_str("# TYPE bitcask_files gauge\n");
+    let _ = writeln!(out, "bitcask_files {}", db.arch_files.len() + 1);
+    out.push_str("# HELP bitcask_mapped_files Archived files currently memory-mapped.\n");
+    out.push_str("# TYPE bitcask_mapped_files gauge\n");
+    let _ = writeln!(out, "bitcask_mapped_files {}", db.arch_files.mapped());
+    out.push_str("# HELP bitcask_active_file_bytes Size of the active data file.\n");
+    out.push_str("# TYPE bitcask_active_file_bytes gauge\n");
+    let _ = writeln!(out, "bitcask_active_file_bytes {}", db.active_file.offset);
+    out.push_str("# HELP bitcask_max_file_bytes Size at which the active data file is rotated.\n");
+    out.push_str("# TYPE bitcask_max_file_bytes gauge\n");
+    let _ = writeln!(out, "bitcask_max_file_bytes {}", db.config.max_file_size);
+    out.push_str("# HELP bitcask_active_file_fill_ratio Active file size relative to the rotation size.\n");
+    out.push_str("# TYPE bitcask_active_file_fill_ratio gauge\n");
+    let ratio = db.active_file.offset as f64 / db.config.max_file_size.max(1) as f64;
+    let _ = writeln!(out, "bitcask_active_file_fill_ratio {}", ratio);
+    out.push_str("# HELP bitcask_rotations_total Active file rotations since the store was opened.\n");
+    out.push_str("# TYPE bitcask_rotations_total counter\n");
+    let _ = writeln!(out, "bitcask_rotations_total {}", db.rotations);
+    out.push_str("# HELP bitcask_merges_total Merges of archived files since the store was opened.\n");
+    out.push_str("# TYPE bitcask_merges_total counter\n");
+    let _ = writeln!(out, "bitcask_merges_total {}", db.merges);
+    out.push_str("# HELP bitcask_cache_hits_total Reads answered from the value cache.\n");
+    out.push_str("# TYPE bitcask_cache_hits_total counter\n");
+    let _ = writeln!(out, "bitcask_cache_hits_total {}", db.cache.hits);
+    out.push_str("# HELP bitcask_cache_misses_total Reads of indexed keys that had to go to disk.\n");
+    out.push_str("# TYPE bitcask_cache_misses_total counter\n");
+    let _ = writeln!(out, "bitcask_cache_misses_total {}", db.cache.misses);
+    out.push_str("# HELP bitcask_cache_evictions_total Values evicted from the cache to stay within capacity.\n");
+    out.push_str("# TYPE bitcask_cache_evictions_total counter\n");
+    let _ = writeln!(out, "bitcask_cache_evictions_total {}", db.cache.evictions);
+    out.push_str("# HELP bitcask_cache_bytes Bytes of keys and values held in the cache.\n");
+    out.push_str("# TYPE bitcask_cache_bytes gauge\n");
+    let _ = writeln!(out, "bitcask_cache_bytes {}", db.cache.size());
+    out.push_str("# HELP bitcask_cache_capacity_bytes Configured cache capacity.\n");
+    out.push_str("# TYPE bitcask_cache_capacity_bytes gauge\n");
+    let _ = writeln!(out, "bitcask_cache_capacity_bytes {}", db.cache.capacity);
+    out.push_str("# HELP bitcask_txn_commits_total Transactions committed.\n");
+    out.push_str("# TYPE bitcask_txn_commits_total counter\n");
+    let _ = writeln!(out, "bitcask_txn_commits_total {}", db.commits);
+    out.push_str("# HELP bitcask_txn_conflicts_total Transactions rejected because a key they read was written since.\n");
+    out.push_str("# TYPE bitcask_txn_conflicts_total counter\n");
+    let _ = writeln!(out, "bitcask_txn_conflicts_total {}", db.conflicts);
+    out.push_str("# HELP bitcask_quota_rejections_total Writes rejected because the data directory reached max_data_size.\n");
+    out.push_str("# TYPE bitcask_quota_rejections_total counter\n");
+    let _ = writeln!(out, "bitcask_quota_rejections_total {}", db.quota_rejections);
+    out.push_str("# HELP bitcask_checksum_failures_total Entries skipped by index rebuild because of a checksum mismatch.\n");
+    out.push_str("# TYPE bitcask_checksum_failures_total counter\n");
+    let _ = writeln!(out, "bitcask_checksum_failures_total {}", db.checksum_failures);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::kv::test_util;
+    use crate::storage::entry;
+
+    #[test]
+    fn requests_render_as_counters_and_histograms() {
+        let metrics = Metrics::default();
+        metrics.observe("get", true, Duration::from_micros(50));
+        metrics.observe("get", false, Duration::from_millis(20));
+        metrics.observe("scan", true, Duration::from_millis(1));
+        let out = metrics.render(3, 32);
+        for line in [
+            "bitcask_requests_total{op=\"get\"} 2",
+            "bitcask_requests_total{op=\"set\"} 0",
+            "bitcask_request_failures_total{op=\"get\"} 1",
+            "bitcask_request_duration_seconds_bucket{op=\"get\",le=\"0.0001\"} 1",
+            "bitcask_request_duration_seconds_bucket{op=\"get\",le=\"0.01\"} 1",
+            "bitcask_request_duration_seconds_bucket{op=\"get\",le=\"0.05\"} 2",
+            "bitcask_request_duration_seconds_bucket{op=\"get\",le=\"+Inf\"} 2",
+            "bitcask_request_duration_seconds_sum{op=\"get\"} 0.02005",
+            "bitcask_request_duration_seconds_count{op=\"get\"} 2",
+            "bitcask_queue_depth 3",
+            "bitcask_queue_capacity 32",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing `{}` in\n{}", line, out);
+        }
+        assert!(!out.contains("scan"));
+        // every sample line belongs to a declared family
+        for line in out.lines().filter(|l| !l.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = name.trim_end_matches("_bucket").trim_end_matches("_sum").trim_end_matches("_count");
+            assert!(out.contains(&format!("# TYPE {} ", family)), "undeclared `{}`", name);
+        }
+    }
+
+    #[test]
+    fn store_gauges_follow_the_data_files() {
+        let mut db = test_util::open("metrics", config::Config::default());
+        db.set("a".to_string(), "1".to_string()).unwrap();
+        db.set("b".to_string(), "2".to_string()).unwrap();
+        assert!(db.delete("b".to_string()));
+        assert_eq!(db.get("a".to_string()), Some("1".to_string()));
+        assert_eq!(db.get("a".to_string()), Some("1".to_string()));
+        let out = render_store(&db);
+        test_util::remove(db);
+        // `b` and its delete marker are dead, only `a` is live
+        let live = entry::ENTRY_HEADER_SIZE + 2;
+        for line in [
+            "bitcask_keys 1".to_string(),
+            "bitcask_files 1".to_string(),
+            format!("bitcask_file_live_bytes{{file=\"1\"}} {}", live),
+            format!("bitcask_file_dead_bytes{{file=\"1\"}} {}", 2 * entry::ENTRY_HEADER_SIZE + 2 + 1),
+            format!("bitcask_active_file_bytes {}", 3 * entry::ENTRY_HEADER_SIZE + 5),
+            "bitcask_cache_hits_total 1".to_string(),
+            "bitcask_cache_misses_total 1".to_string(),
+            "bitcask_cache_bytes 2".to_string(),
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing `{}` in\n{}", line, out);
+        }
+    }
+}
@@ -1,62 +1,122 @@
+use std::borrow::Cow;
+use std::fs;
 use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::io::{self, BufReader, Read, Write};
 use std::os::unix::prelude::FileExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
 
 use crate::storage::entry;
+#[cfg(feature = "fault-injection")]
+use crate::storage::fault;
 use crate::utils::hash_routine;
 
+const REPLAY_BUFFER_SIZE: usize = 256 * 1024;
+
 #[derive(Default)]
 pub struct DBFile {
     pub id: u32,
     pub path: String,
-    pub offset: u32,
-    pub file: Option<File>
+    pub offset: u64,
+    pub file: Option<File>,
+    pub mmap: Option<Mmap>,
 }
 
 impl DBFile {
     pub fn new(path: String, file_id: u32) -> Option<DBFile> {
         let file_path = Path::new(&path).join(format!("{}.data", file_id));
-        let ret = OpenOptions::new().read(true).write(true).open(file_path);
+        let ret = OpenOptions::new().read(true).append(true).open(file_path);
         if ret.is_err() {
             return None;
         }
         let f = ret.ok().unwrap();
         Some(DBFile {
             id: file_id,
-            offset: f.metadata().unwrap().len() as u32,
+            offset: f.metadata().unwrap().len(),
             file: Some(f),
+            mmap: None,
             path,
         })
     }
 
-    pub fn read(&self, mut offset: u32) -> Option<entry::Entry> {
-        let buf = self.read_buf(offset, entry::ENTRY_HEADER_SIZE);
-        if buf.is_none() {
-            return None;
+    // archived files are only stat'ed here; FilePool maps them on first read
+    pub fn open_archived(path: String, file_id: u32) -> Option<DBFile> {
+        let file_path = Path::new(&path).join(format!("{}.data", file_id));
+        let len = fs::metadata(file_path).ok()?.len();
+        Some(DBFile {
+            id: file_id,
+            offset: len,
+            file: None,
+            mmap: None,
+            path,
+        })
+    }
+
+    pub fn map(&mut self) -> bool {
+        if self.mmap.is_some() || self.file.is_some() {
+            return true;
         }
-        let buf = buf.unwrap();
-        let mut entry = entry::Entry::decode_header(buf).unwrap();
-        offset += entry::ENTRY_HEADER_SIZE;
-        let key = self.read_buf(offset, entry.key_size);
-        if key.is_none() {
-            return None;
+        // archived files are never written again, so the mapping stays valid;
+        // an empty file can't be mapped and simply has nothing to read
+        if self.offset == 0 {
+            return true;
         }
-        entry.key = key.unwrap();
-        offset += entry.key_size;
-        let value = self.read_buf(offset, entry.value_size);
-        if value.is_none() {
-            return None;
+        let f = match File::open(self.file_path()) {
+            Ok(f) => f,
+            Err(_) => return false,
+        };
+        match unsafe { Mmap::map(&f) } {
+            Ok(mmap) => {
+                self.mmap = Some(mmap);
+                true
+            }
+            Err(_) => false,
         }
-        entry.value = value.unwrap();
-        let check_sum = hash_routine::encode_vec_u8(&entry.value, 32) as u32;
-        if check_sum != entry.crc32 {
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.mmap.is_some()
+    }
+
+    // borrows key and value from the mapping of an archived file; only the
+    // active file, read with pread, hands out owned buffers
+    pub fn read(&self, mut offset: u64) -> Option<entry::Entry<'_>> {
+        let buf = self.read_buf(offset, entry::ENTRY_HEADER_SIZE)?;
+        let mut entry = entry::Entry::decode_header(&buf)?;
+        if offset + entry.size() > self.offset {
             return None;
         }
-        entry.valid = true;
+        offset += entry::ENTRY_HEADER_SIZE;
+        entry.key = self.read_buf(offset, entry.key_size as u64)?;
+        offset += entry.key_size as u64;
+        entry.value = self.read_buf(offset, entry.value_size as u64)?;
+        let check_sum = hash_routine::encode_vec_u8(&entry.value, 32) as u32;
+        entry.valid = check_sum == entry.crc32;
         Some(entry)
     }
 
+    // borrows straight from the mapping, so only archived files support it
+    pub fn slice(&self, offset: u64, len: u64) -> Option<&[u8]> {
+        let mmap = self.mmap.as_ref()?;
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        mmap.get(start..end)
+    }
+
+    pub fn iter(&self) -> EntryIter<'_> {
+        let reader = if self.mmap.is_none() {
+            File::open(self.file_path()).ok().map(|f| BufReader::with_capacity(REPLAY_BUFFER_SIZE, f))
+        } else {
+            None
+        };
+        EntryIter {
+            file: self,
+            reader,
+            offset: 0,
+        }
+    }
+
     pub fn write(&mut self, entry: entry::Entry) -> bool {
         if self.file.is_none() {
             return false;
@@ -69,15 +129,62 @@ impl DBFile {
             return false;
         }
         let buf = buf.unwrap();
-        let ret = self.file.as_ref().unwrap().write_all(&buf);
+        let mut file = self.file.as_ref().unwrap();
+        #[cfg(feature = "fault-injection")]
+        let ret = match fault::write_allowance(buf.len()) {
+            None => file.write_all(&buf),
+            Some(len) => file.write_all(&buf[..len]).and(Err(std::io::ErrorKind::Other.into())),
+        };
+        #[cfg(not(feature = "fault-injection"))]
+        let ret = file.write_all(&buf);
         if ret.is_err() {
+            // after a kill nothing runs
+            #[cfg(feature = "fault-injection")]
+            if fault::killed() {
+                return false;
+            }
+            // drop whatever part of the entry made it out, otherwise the next
+            // append lands behind a torn entry
+            let _ = file.set_len(self.offset);
             return false;
         }
         self.offset += entry.size();
         true
     }
 
+    // cuts a torn tail left by a crash off the active file
+    pub fn truncate(&mut self, len: u64) -> bool {
+        let file = match self.file.as_ref() {
+            Some(file) => file,
+            None => return false,
+        };
+        if file.set_len(len).is_err() {
+            return false;
+        }
+        self.offset = len;
+        true
+    }
+
+    pub fn sync(&self) -> bool {
+        #[cfg(feature = "fault-injection")]
+        if fault::sync_fails() {
+            return false;
+        }
+        match self.file.as_ref() {
+            Some(file) => file.sync_data().is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn file_path(&self) -> PathBuf {
+        Path::new(&self.path).join(format!("{}.data", self.id))
+    }
+
     pub fn close(&mut self) -> bool {
+        if self.mmap.is_some() {
+            self.mmap = None;
+            return true;
+        }
         if self.file.is_none() {
             return false;
         }
@@ -88,21 +195,85 @@ impl DBFile {
         true
     }
 
-    fn read_buf(&self, offset: u32, len: u32) -> Option<Vec<u8>> {
-        if self.file.is_none() {
+    fn read_buf(&self, offset: u64, len: u64) -> Option<Cow<'_, [u8]>> {
+        if offset + len > self.offset {
             return None;
         }
-        if offset as u64 >= self.file.as_ref().unwrap().metadata().unwrap().len() {
-            return None;
+        if self.mmap.is_some() {
+            return self.slice(offset, len).map(Cow::Borrowed);
         }
-        let mut buf = Vec::with_capacity(len as usize);
-        for _ in 0..len {
-            buf.push(0);
+        let file = self.file.as_ref()?;
+        let mut buf = vec![0; len as usize];
+        if file.read_exact_at(&mut buf, offset).is_err() {
+            return None;
         }
-        let ret = self.file.as_ref().unwrap().read_at(&mut buf, offset as u64);
-        if ret.is_err() {
+        Some(Cow::Owned(buf))
+    }
+}
+
+// one whole entry with a single pread, for readers outside the store that
+// hold the file open themselves; an entry that doesn't check out is an error
+pub fn read_at(file: &File, offset: u64, size: u64) -> io::Result<entry::Entry<'static>> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, format!("entry at offset {} is corrupt", offset));
+    let mut buf = vec![0; usize::try_from(size).map_err(|_| corrupt())?];
+    file.read_exact_at(&mut buf, offset)?;
+    let mut entry = entry::Entry::decode_header(&buf).ok_or_else(corrupt)?;
+    if entry.size() != size {
+        return Err(corrupt());
+    }
+    let value = buf.split_off((entry::ENTRY_HEADER_SIZE + entry.key_size as u64) as usize);
+    entry.key = Cow::Owned(buf.split_off(entry::ENTRY_HEADER_SIZE as usize));
+    entry.value = Cow::Owned(value);
+    let check_sum = hash_routine::encode_vec_u8(&entry.value, 32) as u32;
+    entry.valid = check_sum == entry.crc32;
+    if !entry.valid {
+        return Err(corrupt());
+    }
+    Ok(entry)
+}
+
+pub struct EntryIter<'a> {
+    file: &'a DBFile,
+    reader: Option<BufReader<File>>,
+    offset: u64,
+}
+
+impl<'a> EntryIter<'a> {
+    // end of the last complete entry once the iterator is exhausted
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn read_next(&mut self) -> Option<entry::Entry<'a>> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return self.file.read(self.offset),
+        };
+        let mut header = [0; entry::ENTRY_HEADER_SIZE as usize];
+        reader.read_exact(&mut header).ok()?;
+        let mut entry = entry::Entry::decode_header(&header)?;
+        if self.offset + entry.size() > self.file.offset {
             return None;
         }
-        Some(buf)
+        let mut key = vec![0; entry.key_size as usize];
+        reader.read_exact(&mut key).ok()?;
+        let mut value = vec![0; entry.value_size as usize];
+        reader.read_exact(&mut value).ok()?;
+        entry.key = Cow::Owned(key);
+        entry.value = Cow::Owned(value);
+        let check_sum = hash_routine::encode_vec_u8(&entry.value, 32) as u32;
+        entry.valid = check_sum == entry.crc32;
+        Some(entry)
     }
-}
\ No newline at end of file
+}
+
+impl<'a> Iterator for EntryIter<'a> {
+    type Item = (u64, entry::Entry<'a>);
+
+    fn next(&mut self) -> Option<(u64, entry::Entry<'a>)> {
+        let entry = self.read_next()?;
+        let offset = self.offset;
+        self.offset += entry.size();
+        Some((offset, entry))
+    }
+}
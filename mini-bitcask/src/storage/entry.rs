@@ -1,24 +1,26 @@
+use std::borrow::Cow;
+
 use crate::utils::hash_routine;
 use crate::utils::time_routine;
 
-pub const ENTRY_HEADER_SIZE: u32 = 22;
+pub const ENTRY_HEADER_SIZE: u64 = 22;
 
-pub struct Entry {
+// key and value borrow from the mapping when read from an archived file and
+// are only copied once they leave the store
+pub struct Entry<'a> {
     pub valid: bool,
     pub crc32: u32,
     pub key_size: u32,
     pub value_size: u32,
     pub state: u16,
     pub time_stamp: u64,
-    pub key: Vec<u8>,
-    pub value: Vec<u8>,
+    pub key: Cow<'a, [u8]>,
+    pub value: Cow<'a, [u8]>,
 }
 
-impl Entry {
-    pub fn new(key: Vec<u8>, value: Vec<u8>, t: u16, mark: u16) -> Entry {
-        let mut state = 0;
-        state = state | (t << 8);
-        state = state | mark;
+impl<'a> Entry<'a> {
+    pub fn new(key: Vec<u8>, value: Vec<u8>, t: u16, mark: u16) -> Entry<'a> {
+        let state = (t << 8) | mark;
         Entry {
             valid: true,
             crc32: 0,
@@ -26,15 +28,13 @@ impl Entry {
             value_size: value.len() as u32,
             time_stamp: time_routine::time_now(),
             state,
-            key,
-            value,
+            key: Cow::Owned(key),
+            value: Cow::Owned(value),
         }
     }
 
-    pub fn new_with_expire(key: Vec<u8>, value: Vec<u8>, ddl: u64, t: u16, mark: u16) -> Entry {
-        let mut state = 0;
-        state = state | (t << 8);
-        state = state | mark;
+    pub fn new_with_expire(key: Vec<u8>, value: Vec<u8>, ddl: u64, t: u16, mark: u16) -> Entry<'a> {
+        let state = (t << 8) | mark;
         Entry {
             valid: true,
             crc32: 0,
@@ -42,12 +42,12 @@ impl Entry {
             value_size: value.len() as u32,
             time_stamp: ddl,
             state,
-            key,
-            value,
+            key: Cow::Owned(key),
+            value: Cow::Owned(value),
         }
     }
 
-    pub fn decode_header(buf: Vec<u8>) -> Option<Entry> {
+    pub fn decode_header(buf: &[u8]) -> Option<Entry<'a>> {
         if buf.len() < ENTRY_HEADER_SIZE as usize {
             return None;
         }
@@ -64,8 +64,8 @@ impl Entry {
             value_size,
             state,
             time_stamp,
-            key: vec![],
-            value: vec![],
+            key: Cow::Borrowed(&[]),
+            value: Cow::Borrowed(&[]),
         })
     }
 
@@ -77,10 +77,7 @@ impl Entry {
         let vs = self.value_size;
         let state = self.state;
         let time_stamp = self.time_stamp;
-        let mut buf = Vec::with_capacity(ENTRY_HEADER_SIZE as usize);
-        for _ in 0..ENTRY_HEADER_SIZE {
-            buf.push(0);
-        }
+        let mut buf = vec![0; ENTRY_HEADER_SIZE as usize];
         buf[4] = (ks >> 24) as u8;
         buf[5] = (ks >> 16) as u8;
         buf[6] = (ks >> 8) as u8;
@@ -109,11 +106,19 @@ impl Entry {
         Some(buf)
     }
 
+    pub fn into_owned(self) -> Entry<'static> {
+        Entry {
+            key: Cow::Owned(self.key.into_owned()),
+            value: Cow::Owned(self.value.into_owned()),
+            ..self
+        }
+    }
+
     pub fn get_mark(&self) -> u16 {
         self.state & ((1<<8) - 1)
     }
 
-    pub fn size(&self) -> u32 {
-        ENTRY_HEADER_SIZE + self.key_size + self.value_size
+    pub fn size(&self) -> u64 {
+        ENTRY_HEADER_SIZE + self.key_size as u64 + self.value_size as u64
     }
 }
\ No newline at end of file
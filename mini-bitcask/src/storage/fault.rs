@@ -0,0 +1,110 @@
+use std::cell::{Cell, RefCell};
+
+// fault injection for the crash tests, only built with the fault-injection
+// feature; the plan is per thread so tests running side by side don't see
+// each other's
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    // the write or rotation step fails once and the store carries on
+    Fail,
+    // the process is gone: the write is torn and every later write and
+    // rotation fails until the plan is cleared
+    Kill,
+}
+
+#[derive(Default)]
+struct Plan {
+    mode: Option<Mode>,
+    bytes_left: Option<u64>,
+    steps_left: Option<u64>,
+    killed: bool,
+}
+
+thread_local! {
+    static PLAN: RefCell<Plan> = RefCell::new(Plan::default());
+    static SYNCS_FAIL: Cell<bool> = const { Cell::new(false) };
+}
+
+// fires once `bytes` more bytes have gone through DBFile::write, or at the
+// `steps`-th rotation step from now, whichever comes first
+pub fn arm(mode: Mode, bytes: Option<u64>, steps: Option<u64>) {
+    PLAN.with(|plan| {
+        *plan.borrow_mut() = Plan {
+            mode: Some(mode),
+            bytes_left: bytes,
+            steps_left: steps,
+            killed: false,
+        }
+    });
+}
+
+pub fn clear() {
+    PLAN.with(|plan| *plan.borrow_mut() = Plan::default());
+    SYNCS_FAIL.with(|fail| fail.set(false));
+}
+
+// every DBFile::sync fails until cleared, while writes still go through
+pub fn fail_syncs() {
+    SYNCS_FAIL.with(|fail| fail.set(true));
+}
+
+pub fn killed() -> bool {
+    PLAN.with(|plan| plan.borrow().killed)
+}
+
+pub fn fired() -> bool {
+    PLAN.with(|plan| {
+        let plan = plan.borrow();
+        plan.killed || (plan.mode.is_some() && plan.bytes_left.is_none() && plan.steps_left.is_none())
+    })
+}
+
+// how many bytes of a `len` byte write reach the file before it fails, or
+// None when the write goes through
+pub(crate) fn write_allowance(len: usize) -> Option<usize> {
+    PLAN.with(|plan| {
+        let mut plan = plan.borrow_mut();
+        if plan.killed {
+            return Some(0);
+        }
+        let left = plan.bytes_left?;
+        if left >= len as u64 {
+            plan.bytes_left = Some(left - len as u64);
+            return None;
+        }
+        plan.fire();
+        Some(left as usize)
+    })
+}
+
+pub(crate) fn sync_fails() -> bool {
+    SYNCS_FAIL.with(|fail| fail.get())
+}
+
+pub(crate) fn rotation_fails() -> bool {
+    PLAN.with(|plan| {
+        let mut plan = plan.borrow_mut();
+        if plan.killed {
+            return true;
+        }
+        match plan.steps_left {
+            Some(0) => {
+                plan.fire();
+                true
+            }
+            Some(left) => {
+                plan.steps_left = Some(left - 1);
+                false
+            }
+            None => false,
+        }
+    })
+}
+
+impl Plan {
+    fn fire(&mut self) {
+        self.bytes_left = None;
+        self.steps_left = None;
+        self.killed = self.mode == Some(Mode::Kill);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::storage::db_file;
+
+// keeps every archived file known but only `capacity` of them mapped, so
+// directories with tens of thousands of files don't exhaust descriptors or
+// mappings; the least recently read file is unmapped first
+#[derive(Default)]
+pub struct FilePool {
+    pub capacity: usize,
+    files: HashMap<u32, db_file::DBFile>,
+    mapped: VecDeque<u32>,
+}
+
+impl FilePool {
+    pub fn new(capacity: usize) -> FilePool {
+        FilePool {
+            capacity: capacity.max(1),
+            ..Default::default()
+        }
+    }
+
+    pub fn insert(&mut self, mut file: db_file::DBFile) {
+        file.close();
+        if let Some(mut old) = self.files.insert(file.id, file) {
+            old.close();
+        }
+    }
+
+    pub fn remove(&mut self, id: &u32) -> Option<db_file::DBFile> {
+        self.mapped.retain(|mapped| mapped != id);
+        self.files.remove(id)
+    }
+
+    pub fn get(&self, id: &u32) -> Option<&db_file::DBFile> {
+        self.files.get(id)
+    }
+
+    pub fn open(&mut self, id: u32) -> Option<&db_file::DBFile> {
+        if !self.files.contains_key(&id) {
+            return None;
+        }
+        if let Some(pos) = self.mapped.iter().position(|mapped| *mapped == id) {
+            self.mapped.remove(pos);
+        } else {
+            while self.mapped.len() >= self.capacity {
+                let evicted = self.mapped.pop_front().unwrap();
+                if let Some(file) = self.files.get_mut(&evicted) {
+                    file.close();
+                }
+            }
+            if !self.files.get_mut(&id).unwrap().map() {
+                return None;
+            }
+        }
+        self.mapped.push_back(id);
+        self.files.get(&id)
+    }
+
+    pub fn contains(&self, id: &u32) -> bool {
+        self.files.contains_key(id)
+    }
+
+    pub fn ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.files.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &db_file::DBFile> {
+        self.files.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn mapped(&self) -> usize {
+        self.mapped.len()
+    }
+
+    pub fn close(&mut self) {
+        for (_, file) in self.files.iter_mut() {
+            file.close();
+        }
+        self.mapped.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::path::Path;
+
+    use super::*;
+    use crate::kv::test_util;
+    use crate::storage::entry;
+
+    fn archive(dir: &str, id: u32) -> db_file::DBFile {
+        File::create(Path::new(dir).join(format!("{}.data", id))).unwrap();
+        let mut file = db_file::DBFile::new(dir.to_string(), id).unwrap();
+        assert!(file.write(entry::Entry::new(b"key".to_vec(), id.to_string().into_bytes(), 0, 0)));
+        file.close();
+        db_file::DBFile::open_archived(dir.to_string(), id).unwrap()
+    }
+
+    fn mapped(pool: &FilePool) -> Vec<u32> {
+        pool.ids().into_iter().filter(|id| pool.get(id).unwrap().is_mapped()).collect()
+    }
+
+    #[test]
+    fn least_recently_read_file_is_unmapped_first() {
+        let dir = test_util::temp_dir("pool");
+        fs::create_dir_all(&dir).unwrap();
+        let mut pool = FilePool::new(2);
+        for id in 1..=3 {
+            pool.insert(archive(&dir, id));
+        }
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.mapped(), 0);
+
+        assert_eq!(&pool.open(1).unwrap().read(0).unwrap().value[..], b"1");
+        pool.open(2).unwrap();
+        assert_eq!(mapped(&pool), vec![1, 2]);
+        // reading 1 again makes 2 the oldest
+        pool.open(1).unwrap();
+        assert_eq!(&pool.open(3).unwrap().read(0).unwrap().value[..], b"3");
+        assert_eq!(mapped(&pool), vec![1, 3]);
+        // an unmapped file is still known and maps again on demand
+        assert!(pool.contains(&2));
+        assert_eq!(&pool.open(2).unwrap().read(0).unwrap().value[..], b"2");
+        assert_eq!(mapped(&pool), vec![2, 3]);
+
+        assert!(pool.remove(&3).is_some());
+        assert_eq!(pool.mapped(), 1);
+        assert!(pool.open(3).is_none());
+        pool.close();
+        assert_eq!(mapped(&pool), Vec::<u32>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,2 +1,5 @@
 pub mod entry;
-pub mod db_file;
\ No newline at end of file
+pub mod db_file;
+pub mod file_pool;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
\ No newline at end of file
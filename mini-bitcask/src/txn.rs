@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::kv;
+use crate::limits;
+
+pub enum Write {
+    Set(String),
+    SetWithExpire(String, u64),
+    Delete,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TxnError {
+    // another writer changed a key this transaction read
+    Conflict(String),
+    Invalid(String),
+    Rejected(limits::Rejection),
+    Write,
+}
+
+impl fmt::Display for TxnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxnError::Conflict(key) => write!(f, "conflict on key `{}`", key),
+            TxnError::Invalid(key) => write!(f, "key or value of `{}` exceeds the configured limits", key),
+            TxnError::Rejected(rejection) => write!(f, "{}", rejection),
+            TxnError::Write => write!(f, "transaction can't be written"),
+        }
+    }
+}
+
+// writes are buffered until `kv::commit`; every key read through the
+// transaction pins the store's version at its first read, and the commit
+// fails if any of them was written after that
+#[derive(Default)]
+pub struct Transaction {
+    pub start: u64,
+    pub reads: HashMap<String, u64>,
+    pub writes: BTreeMap<String, Write>,
+}
+
+impl Transaction {
+    pub fn get(&mut self, db: &mut kv::kv, key: String) -> Option<String> {
+        match self.writes.get(&key) {
+            Some(Write::Set(value)) | Some(Write::SetWithExpire(value, _)) => return Some(value.clone()),
+            Some(Write::Delete) => return None,
+            None => {}
+        }
+        self.reads.entry(key.clone()).or_insert(db.version);
+        db.get(key)
+    }
+
+    pub fn set(&mut self, key: String, value: String) {
+        self.writes.insert(key, Write::Set(value));
+    }
+
+    pub fn set_with_expire(&mut self, key: String, value: String, deadline: u64) {
+        self.writes.insert(key, Write::SetWithExpire(value, deadline));
+    }
+
+    pub fn delete(&mut self, key: String) {
+        self.writes.insert(key, Write::Delete);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::test_util::{open, remove};
+
+    type Writer = fn(&mut kv::kv);
+
+    #[test]
+    fn commit_applies_every_write_at_once() {
+        let mut db = open("commit", Default::default());
+        db.set("a".to_string(), "1".to_string()).unwrap();
+        db.set("b".to_string(), "2".to_string()).unwrap();
+        let mut txn = db.begin();
+        assert_eq!(txn.get(&mut db, "a".to_string()), Some("1".to_string()));
+        txn.set("a".to_string(), "10".to_string());
+        txn.delete("b".to_string());
+        txn.set_with_expire("c".to_string(), "3".to_string(), u64::MAX);
+        // reads see the transaction's own writes, the store doesn't yet
+        assert_eq!(txn.get(&mut db, "a".to_string()), Some("10".to_string()));
+        assert_eq!(txn.get(&mut db, "b".to_string()), None);
+        assert_eq!(db.get("a".to_string()), Some("1".to_string()));
+        assert_eq!(db.commit(txn), Ok(()));
+        assert_eq!(db.get("a".to_string()), Some("10".to_string()));
+        assert_eq!(db.get("b".to_string()), None);
+        assert_eq!(db.get("c".to_string()), Some("3".to_string()));
+        assert_eq!(db.commits, 1);
+
+        // and replay after a reopen agrees
+        db.close();
+        let mut db = kv::kv::open(db.config.clone()).unwrap();
+        assert_eq!(db.get("a".to_string()), Some("10".to_string()));
+        assert_eq!(db.get("b".to_string()), None);
+        assert_eq!(db.expires.get("c"), Some(&u64::MAX));
+        remove(db);
+    }
+
+    #[test]
+    fn any_write_to_a_key_read_is_a_conflict() {
+        let mut db = open("conflict", Default::default());
+        let writes: [(&str, Writer); 4] = [
+            ("set", |db| db.set("key".to_string(), "set".to_string()).unwrap()),
+            ("delete", |db| assert!(db.delete("key".to_string()))),
+            ("import", |db| assert_eq!(db.import("{\"key\":\"key\",\"value\":\"imported\"}\n".as_bytes()), Ok(1))),
+            ("commit", |db| {
+                let mut other = db.begin();
+                other.set("key".to_string(), "committed".to_string());
+                assert_eq!(db.commit(other), Ok(()));
+            }),
+        ];
+        for (name, write) in writes {
+            db.set("key".to_string(), "start".to_string()).unwrap();
+            let mut txn = db.begin();
+            txn.get(&mut db, "key".to_string());
+            txn.set("other".to_string(), name.to_string());
+            write(&mut db);
+            assert_eq!(db.commit(txn), Err(TxnError::Conflict("key".to_string())), "{}", name);
+            assert_eq!(db.get("other".to_string()), None, "{}", name);
+        }
+        assert_eq!(db.conflicts, 4);
+
+        // a key that was absent when read conflicts once it shows up
+        let mut txn = db.begin();
+        assert_eq!(txn.get(&mut db, "new".to_string()), None);
+        db.set("new".to_string(), "1".to_string()).unwrap();
+        assert!(matches!(db.commit(txn), Err(TxnError::Conflict(_))));
+        // writes to keys never read don't
+        let mut txn = db.begin();
+        txn.get(&mut db, "key".to_string());
+        db.set("unrelated".to_string(), "1".to_string()).unwrap();
+        txn.set("key".to_string(), "mine".to_string());
+        assert_eq!(db.commit(txn), Ok(()));
+        assert_eq!(db.get("key".to_string()), Some("mine".to_string()));
+        remove(db);
+    }
+
+    #[test]
+    fn deleted_keys_are_dropped_once_no_transaction_can_see_them() {
+        let mut db = open("versions", Default::default());
+        // nothing is stamped while no transaction is open
+        db.set("a".to_string(), "1".to_string()).unwrap();
+        assert!(db.delete("a".to_string()));
+        assert!(db.versions.is_empty());
+
+        let mut old = db.begin();
+        old.get(&mut db, "b".to_string());
+        db.set("b".to_string(), "1".to_string()).unwrap();
+        assert!(db.delete("b".to_string()));
+        let mut young = db.begin();
+        young.get(&mut db, "b".to_string());
+        db.set("c".to_string(), "1".to_string()).unwrap();
+        assert!(db.delete("c".to_string()));
+        let mut stamped: Vec<_> = db.versions.keys().cloned().collect();
+        stamped.sort();
+        assert_eq!(stamped, ["b", "c"]);
+
+        // `b` changed before `young` began, so ending `old` drops it
+        db.abort(old);
+        assert_eq!(db.versions.keys().collect::<Vec<_>>(), ["c"]);
+        young.set("d".to_string(), "1".to_string());
+        assert_eq!(db.commit(young), Ok(()));
+        assert!(db.versions.is_empty());
+        assert!(db.open_txns.is_empty());
+        remove(db);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::env;
+
+use mini_bitcask::config;
+use mini_bitcask::kv;
+use mini_bitcask::kv::test_util;
+use mini_bitcask::storage::fault;
+use mini_bitcask::txn::TxnError;
+use mini_bitcask::utils::time_routine;
+
+// CRASH_SEEDS=<n> runs a longer search
+const SEEDS: u64 = 12;
+const STEPS: usize = 300;
+const KEYS: u64 = 12;
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+// what the store should hold: key -> (value, deadline)
+type Model = BTreeMap<String, (String, Option<u64>)>;
+
+fn open(dir: &str) -> kv::kv {
+    let config = config::Config {
+        dir_path: dir.to_string(),
+        max_file_size: 1024,
+        cache_capacity: 64,
+        merge_min_dead_bytes: 64,
+        ..Default::default()
+    };
+    kv::kv::open(config).expect("store can't open")
+}
+
+fn live(model: &Model) -> BTreeMap<String, String> {
+    let now = time_routine::time_now();
+    model.iter()
+        .filter(|(_, (_, deadline))| deadline.map(|deadline| now <= deadline).unwrap_or(true))
+        .map(|(key, (value, _))| (key.clone(), value.clone()))
+        .collect()
+}
+
+fn check(db: &mut kv::kv, model: &Model, context: &str) {
+    let expected = live(model);
+    for i in 0..KEYS {
+        let key = format!("k{}", i);
+        assert_eq!(db.get(key.clone()), expected.get(&key).cloned(), "{}: value of {}", context, key);
+    }
+    let keys: Vec<String> = expected.keys().cloned().collect();
+    assert_eq!(db.export_keys(), keys, "{}: live keys", context);
+}
+
+// a failed operation is only acceptable when the armed fault fired during it
+fn expect(ok: bool, context: &str) -> bool {
+    assert!(ok || fault::fired(), "{}: failed without a fault", context);
+    ok
+}
+
+fn step(db: &mut kv::kv, model: &mut Model, rng: &mut Rng, context: &str) {
+    let key = format!("k{}", rng.below(KEYS));
+    let value = format!("v{}", rng.next() % 1_000_000);
+    let now = time_routine::time_now();
+    match rng.below(20) {
+        0..=7 => {
+            if expect(db.set(key.clone(), value.clone()).is_ok(), context) {
+                model.insert(key, (value, None));
+            }
+        }
+        8..=10 => {
+            // either long gone or far out, so the clock never decides a check
+            let deadline = if rng.below(2) == 0 { now - 1000 } else { now + 100_000 };
+            if expect(db.set_with_expire(key.clone(), value.clone(), deadline).is_ok(), context) {
+                model.insert(key, (value, Some(deadline)));
+            }
+        }
+        11..=14 => {
+            if expect(db.delete(key.clone()), context) {
+                model.remove(&key);
+            }
+        }
+        15 => {
+            if expect(db.clear(), context) {
+                model.clear();
+            }
+        }
+        _ => {
+            let mut txn = db.begin();
+            let read = key.clone();
+            txn.get(db, read.clone());
+            // somebody else writes the key the transaction read
+            let interfered = rng.below(4) == 0 && expect(db.set(read.clone(), value.clone()).is_ok(), context);
+            if interfered {
+                model.insert(read.clone(), (value.clone(), None));
+            }
+            let mut writes = vec![];
+            for _ in 0..1 + rng.below(3) {
+                let key = format!("k{}", rng.below(KEYS));
+                if rng.below(3) == 0 {
+                    txn.delete(key.clone());
+                    writes.push((key, None));
+                } else {
+                    let value = format!("t{}", rng.next() % 1_000_000);
+                    txn.set(key.clone(), value.clone());
+                    writes.push((key, Some(value)));
+                }
+            }
+            match db.commit(txn) {
+                Ok(()) => {
+                    assert!(!interfered, "{}: commit after a conflicting write", context);
+                    for (key, value) in writes {
+                        match value {
+                            Some(value) => model.insert(key, (value, None)),
+                            None => model.remove(&key),
+                        };
+                    }
+                }
+                Err(TxnError::Conflict(key)) => {
+                    assert!(interfered, "{}: conflict on {} without a conflicting write", context, key);
+                }
+                Err(e) => {
+                    expect(false, &format!("{}: {}", context, e));
+                }
+            }
+        }
+    }
+}
+
+fn arm(rng: &mut Rng) {
+    let mode = if rng.below(2) == 0 { fault::Mode::Fail } else { fault::Mode::Kill };
+    if rng.below(4) == 0 {
+        fault::arm(mode, None, Some(rng.below(3)));
+    } else {
+        fault::arm(mode, Some(rng.below(400)), None);
+    }
+}
+
+#[test]
+fn random_operations_survive_faults() {
+    let seeds = env::var("CRASH_SEEDS").ok().and_then(|seeds| seeds.parse().ok()).unwrap_or(SEEDS);
+    for seed in 1..=seeds {
+        let dir = test_util::temp_dir(&format!("crash-{}", seed));
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15 ^ seed);
+        let mut db = open(&dir);
+        let mut model = Model::new();
+        let mut armed = false;
+        for i in 0..STEPS {
+            let context = format!("seed {} step {}", seed, i);
+            if !armed && rng.below(10) == 0 {
+                arm(&mut rng);
+                armed = true;
+            }
+            step(&mut db, &mut model, &mut rng, &context);
+            if fault::killed() {
+                // nothing is closed or synced, the process just goes away
+                drop(db);
+                fault::clear();
+                armed = false;
+                db = open(&dir);
+                check(&mut db, &model, &format!("{} after kill", context));
+                continue;
+            }
+            if fault::fired() {
+                fault::clear();
+                armed = false;
+            }
+            check(&mut db, &model, &context);
+            if i % 100 == 99 {
+                fault::clear();
+                armed = false;
+                db.close();
+                db = open(&dir);
+                check(&mut db, &model, &format!("{} after reopen", context));
+            }
+        }
+        fault::clear();
+        db.close();
+        let mut db = open(&dir);
+        check(&mut db, &model, &format!("seed {} final reopen", seed));
+        test_util::remove(db);
+    }
+}
+
+#[test]
+fn commit_is_all_or_nothing_at_every_kill_offset() {
+    let keys = ["a", "b", "c"];
+    let mut offset = 0;
+    loop {
+        let dir = test_util::temp_dir(&format!("commit-{}", offset));
+        let mut db = open(&dir);
+        db.set("a".to_string(), "old".to_string()).unwrap();
+        let mut txn = db.begin();
+        for key in keys {
+            txn.set(key.to_string(), "new".to_string());
+        }
+        fault::arm(fault::Mode::Kill, Some(offset), None);
+        let committed = db.commit(txn).is_ok();
+        fault::clear();
+        drop(db);
+        let mut db = open(&dir);
+        let values: Vec<Option<String>> = keys.iter().map(|key| db.get(key.to_string())).collect();
+        if committed {
+            assert!(values.iter().all(|value| value.as_deref() == Some("new")), "offset {}: {:?}", offset, values);
+        } else {
+            assert_eq!(values, vec![Some("old".to_string()), None, None], "offset {}", offset);
+        }
+        // the torn tail is gone, so later writes replay too
+        db.set("d".to_string(), "after".to_string()).unwrap();
+        drop(db);
+        let mut db = open(&dir);
+        assert_eq!(db.get("d".to_string()), Some("after".to_string()), "offset {}", offset);
+        test_util::remove(db);
+        if committed {
+            break;
+        }
+        offset += 1;
+    }
+    assert!(offset > 0);
+}
+
+#[test]
+fn failed_rotation_keeps_the_active_file() {
+    for steps in 0..2 {
+        let dir = test_util::temp_dir(&format!("rotation-{}", steps));
+        let mut db = open(&dir);
+        let mut i = 0;
+        while db.active_file.offset <= 1024 {
+            db.set(format!("k{}", i % KEYS), format!("{:040}", i)).unwrap();
+            i += 1;
+        }
+        let active_id = db.active_file.id;
+        fault::arm(fault::Mode::Fail, None, Some(steps));
+        assert!(db.set("k0".to_string(), "lost".to_string()).is_err());
+        fault::clear();
+        assert_eq!(db.active_file.id, active_id);
+        db.set("k0".to_string(), "kept".to_string()).unwrap();
+        assert_ne!(db.active_file.id, active_id);
+        db.close();
+        let mut db = open(&dir);
+        assert_eq!(db.get("k0".to_string()), Some("kept".to_string()));
+        test_util::remove(db);
+    }
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+// cargo fuzz run packet: whatever arrives off the wire may fail to parse,
+// but must never panic, and what does parse must be writable without panics
+use libfuzzer_sys::fuzz_target;
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::packet::Packet;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buffer = BytePacketBuffer::with_size(data.len());
+    buffer.buf.copy_from_slice(data);
+    if let Ok(mut packet) = Packet::from_buffer(&mut buffer) {
+        let _ = packet.write(&mut BytePacketBuffer::new());
+    }
+    let _ = Packet::format_error(&mut buffer);
+});
@@ -1,15 +1,47 @@
-use crate::utils::Result;
+use std::collections::HashMap;
+
+use crate::utils::{Error, Result};
+
+// no DNS message can be longer than its TCP length prefix allows
+pub const MAX_SIZE: usize = 65535;
+// compressed names chain a pointer per level of the hierarchy
+pub const MAX_JUMPS: usize = 127;
 
 pub struct BytePacketBuffer {
-    pub buf: [u8; 512],
+    pub buf: Vec<u8>,
     pub pos: usize,
+    // where each name suffix written so far starts, for compression pointers
+    names: HashMap<String, usize>,
+    compress: bool,
+}
+
+impl Default for BytePacketBuffer {
+    fn default() -> BytePacketBuffer {
+        BytePacketBuffer::new()
+    }
 }
 
 impl BytePacketBuffer {
     pub fn new() -> BytePacketBuffer {
+        BytePacketBuffer::with_size(512)
+    }
+
+    // reads stop at the end of `buf`, writes grow it up to MAX_SIZE
+    pub fn with_size(size: usize) -> BytePacketBuffer {
         BytePacketBuffer {
-            buf: [0; 512],
+            buf: vec![0; size],
             pos: 0,
+            names: HashMap::new(),
+            compress: true,
+        }
+    }
+
+    // writes every name in full, the canonical form that DNSSEC signatures
+    // and digests are computed over (RFC 4034 6.2)
+    pub fn uncompressed() -> BytePacketBuffer {
+        BytePacketBuffer {
+            compress: false,
+            ..BytePacketBuffer::with_size(0)
         }
     }
 
@@ -18,18 +50,21 @@ impl BytePacketBuffer {
     }
 
     pub fn step(&mut self, steps: usize) -> Result<()> {
-        self.pos += steps;
-        Ok(())
+        self.seek(self.pos + steps)
     }
 
+    // the end of the buffer is as far as one can go
     pub fn seek(&mut self, pos: usize) -> Result<()> {
+        if pos > self.buf.len() {
+            return Err(Error::EndOfBuffer);
+        }
         self.pos = pos;
         Ok(())
     }
 
     pub fn read(&mut self) -> Result<u8> {
-        if self.pos >= 512 {
-            return Err("End of buffer".into());
+        if self.pos >= self.buf.len() {
+            return Err(Error::EndOfBuffer);
         }
         let res = self.buf[self.pos];
         self.pos += 1;
@@ -37,15 +72,15 @@ impl BytePacketBuffer {
     }
 
     pub fn get(&mut self, pos: usize) -> Result<u8> {
-        if pos >= 512 {
-            return Err("End of buffer".into());
+        if pos >= self.buf.len() {
+            return Err(Error::EndOfBuffer);
         }
         Ok(self.buf[pos])
     }
 
     pub fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8]> {
-        if start + len >= 512 {
-            return Err("End of buffer".into());
+        if start.checked_add(len).is_none_or(|end| end > self.buf.len()) {
+            return Err(Error::EndOfBuffer);
         }
         Ok(&self.buf[start..start + len])
     }
@@ -56,7 +91,7 @@ impl BytePacketBuffer {
     }
 
     pub fn read_u32(&mut self) -> Result<u32> {
-        let res = ((self.read()? as u32) << 24) | ((self.read()? as u32) << 16) | ((self.read()? as u32) << 8) | ((self.read()? as u32) << 0);
+        let res = ((self.read()? as u32) << 24) | ((self.read()? as u32) << 16) | ((self.read()? as u32) << 8) | (self.read()? as u32);
         Ok(res)
     }
 
@@ -64,11 +99,11 @@ impl BytePacketBuffer {
         let mut pos = self.pos();
         let mut jumped = false;
         let mut delim = "";
-        let max_jumps = 5;
         let mut jumps_performed = 0;
+        let mut name_len = 0;
         loop {
-            if jumps_performed > max_jumps {
-                return Err(format!("Limit of {} jumps exceeded", max_jumps).into());
+            if jumps_performed > MAX_JUMPS {
+                return Err(Error::TooManyJumps);
             }
             let len = self.get(pos)?;
             if (len & 0xC0) == 0xC0 {
@@ -77,15 +112,28 @@ impl BytePacketBuffer {
                 }
                 let b2 = self.get(pos + 1)? as u16;
                 let offset = (((len as u16) ^ 0xC0) << 8) | b2;
+                // only pointing backwards keeps a name from looping
+                if offset as usize >= pos {
+                    return Err(Error::BadPointer { at: pos, to: offset as usize });
+                }
                 pos = offset as usize;
                 jumped = true;
                 jumps_performed += 1;
                 continue;
             }
+            // 0x40 and 0x80 introduce label types that never caught on
+            if len > 63 {
+                return Err(Error::LabelTooLong(len as usize));
+            }
             pos += 1;
             if len == 0 {
                 break;
             }
+            // 255 octets on the wire, less the first length and the root
+            name_len += delim.len() + len as usize;
+            if name_len > 253 {
+                return Err(Error::NameTooLong(name_len));
+            }
             outstr.push_str(delim);
             let str_buffer = self.get_range(pos, len as usize)?;
             outstr.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
@@ -99,8 +147,11 @@ impl BytePacketBuffer {
     }
 
     pub fn write(&mut self, val: u8) -> Result<()> {
-        if self.pos >= 512 {
-            return Err("End of buffer".into());
+        if self.pos >= MAX_SIZE {
+            return Err(Error::MessageTooLong);
+        }
+        if self.pos >= self.buf.len() {
+            self.buf.resize(self.pos + 1, 0);
         }
         self.buf[self.pos] = val;
         self.pos += 1;
@@ -122,15 +173,45 @@ impl BytePacketBuffer {
         self.write(((val >> 24) & 0xFF) as u8)?;
         self.write(((val >> 16) & 0xFF) as u8)?;
         self.write(((val >> 8) & 0xFF) as u8)?;
-        self.write(((val >> 0) & 0xFF) as u8)?;
+        self.write((val & 0xFF) as u8)?;
         Ok(())
     }
 
+    // writes `qname`, pointing back at an earlier copy of its longest
+    // already written suffix instead of repeating it (RFC 1035 4.1.4)
     pub fn write_qname(&mut self, qname: &str) -> Result<()> {
-        for label in qname.split('.') {
+        self.write_name(qname, self.compress)
+    }
+
+    // for names in the rdata of types that came after RFC 1035, which
+    // must not be compressed (RFC 3597 4)
+    pub fn write_plain_qname(&mut self, qname: &str) -> Result<()> {
+        self.write_name(qname, false)
+    }
+
+    fn write_name(&mut self, qname: &str, compress: bool) -> Result<()> {
+        if qname.len() > 253 {
+            return Err(Error::NameTooLong(qname.len()));
+        }
+        // the root name is just the terminating zero
+        let labels: Vec<&str> = qname.split('.').filter(|_| !qname.is_empty()).collect();
+        for (i, label) in labels.iter().enumerate() {
             let len = label.len();
-            if len > 0x34 {
-                return Err("Single label exceeds 63 characters of length".into());
+            if len == 0 {
+                return Err(Error::EmptyLabel);
+            }
+            if len > 63 {
+                return Err(Error::LabelTooLong(len));
+            }
+            if compress {
+                let suffix = labels[i..].join(".").to_lowercase();
+                if let Some(&offset) = self.names.get(&suffix) {
+                    return self.write_u16(0xC000 | offset as u16);
+                }
+                // pointers only have 14 bits of offset
+                if self.pos < 0x4000 {
+                    self.names.insert(suffix, self.pos);
+                }
             }
             self.write_u8(len as u8)?;
             for b in label.as_bytes() {
@@ -142,7 +223,7 @@ impl BytePacketBuffer {
     }
 
     pub fn set(&mut self, pos: usize, val: u8) -> Result<()> {
-        self.buf[pos] = val;
+        *self.buf.get_mut(pos).ok_or(Error::EndOfBuffer)? = val;
         Ok(())
     }
 
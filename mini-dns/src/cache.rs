@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::utils::ResultCode;
+use crate::record::Record;
+use crate::query_type::QueryType;
+use crate::packet::Packet;
+
+// nothing is kept longer than a day, whatever its ttl says
+const MAX_TTL: u32 = 86400;
+// the upper bound RFC 2308 suggests for negative answers
+const MAX_NEGATIVE_TTL: u32 = 10800;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub name: String,
+    pub qtype: QueryType,
+    pub class: u16,
+}
+
+impl Key {
+    pub fn new(name: &str, qtype: QueryType, class: u16) -> Key {
+        Key {
+            name: name.to_lowercase(),
+            qtype,
+            class,
+        }
+    }
+}
+
+struct Entry {
+    rescode: ResultCode,
+    // whether the answer passed DNSSEC validation
+    authed_data: bool,
+    answers: Vec<Record>,
+    authorities: Vec<Record>,
+    stored: Instant,
+    expires: Instant,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    // least recently used first
+    recency: BTreeMap<u64, Key>,
+    tick: u64,
+}
+
+impl Inner {
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.tick);
+        }
+    }
+}
+
+// responses by question, shared by every query the server handles; a
+// capacity of 0 turns caching off
+pub struct Cache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    // the cached response with every ttl counted down to `now`
+    pub fn get(&self, key: &Key, now: Instant) -> Option<Packet> {
+        let mut inner = self.inner.lock().unwrap();
+        let expired = now >= inner.entries.get(key)?.expires;
+        if expired {
+            inner.remove(key);
+            return None;
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        let entry = inner.entries.get_mut(key).unwrap();
+        let old_tick = entry.tick;
+        entry.tick = tick;
+        let elapsed = now.duration_since(entry.stored).as_secs() as u32;
+        let mut packet = Packet::new();
+        packet.header.rescode = entry.rescode;
+        packet.header.authed_data = entry.authed_data;
+        packet.answers = entry.answers.clone();
+        packet.authorities = entry.authorities.clone();
+        for record in packet.answers.iter_mut().chain(packet.authorities.iter_mut()) {
+            record.set_ttl(record.ttl().saturating_sub(elapsed));
+        }
+        inner.recency.remove(&old_tick);
+        inner.recency.insert(tick, key.clone());
+        Some(packet)
+    }
+
+    pub fn insert(&self, key: Key, response: &Packet, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let ttl = match lifetime(response) {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return,
+        };
+        let mut answers = response.answers.clone();
+        // a negative answer is only worth its SOA and the records proving it
+        let mut authorities: Vec<Record> = response.authorities.iter()
+            .filter(|record| !answers.is_empty() || matches!(record, Record::SOA { .. } | Record::NSEC { .. } | Record::NSEC3 { .. } | Record::RRSIG { .. }))
+            .cloned()
+            .collect();
+        for record in answers.iter_mut().chain(authorities.iter_mut()) {
+            record.set_ttl(record.ttl().min(ttl));
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(&key);
+        if inner.entries.len() >= self.capacity {
+            let expired: Vec<Key> = inner.entries.iter()
+                .filter(|(_, entry)| now >= entry.expires)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for stale in &expired {
+                inner.remove(stale);
+            }
+        }
+        while inner.entries.len() >= self.capacity {
+            let oldest = match inner.recency.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            let evicted = inner.recency.remove(&oldest).unwrap();
+            inner.entries.remove(&evicted);
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.recency.insert(tick, key.clone());
+        inner.entries.insert(key, Entry {
+            rescode: response.header.rescode,
+            authed_data: response.header.authed_data,
+            answers,
+            authorities,
+            stored: now,
+            expires: now + Duration::from_secs(ttl as u64),
+            tick,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// how long a response may be cached: the smallest ttl among its answers, and
+// for NXDOMAIN and NODATA the SOA's negative ttl (RFC 2308); responses without
+// anything to go by aren't cached at all
+fn lifetime(response: &Packet) -> Option<u32> {
+    let negative = match response.header.rescode {
+        ResultCode::NXDOMAIN => true,
+        ResultCode::NOERROR => response.answers.is_empty(),
+        _ => return None,
+    };
+    let mut ttl = response.answers.iter().map(|record| record.ttl()).min().unwrap_or(MAX_TTL).min(MAX_TTL);
+    if negative {
+        let soa = response.authorities.iter().find_map(|record| match *record {
+            Record::SOA { ttl, minimum, .. } => Some(ttl.min(minimum)),
+            _ => None,
+        })?;
+        ttl = ttl.min(soa).min(MAX_NEGATIVE_TTL);
+    }
+    Some(ttl)
+}
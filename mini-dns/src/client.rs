@@ -0,0 +1,207 @@
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::utils::{Result, ResultCode};
+use crate::record::{self, Record};
+use crate::query_type::QueryType;
+use crate::packet::Packet;
+use crate::question::Question;
+use crate::transport;
+use crate::edns;
+use crate::zone;
+
+// referrals a trace follows before giving up, deeper than any real tree
+const MAX_REFERRALS: usize = 16;
+
+// a one-shot stub client: asks `server` a single question the way dig does
+// and hands the reply back as it came
+pub struct Client {
+    pub server: SocketAddr,
+    pub tcp: bool,
+    pub recursion_desired: bool,
+    pub dnssec_ok: bool,
+    // whether to send an OPT record at all, and the payload size it carries;
+    // DO needs one, so `dnssec_ok` sends it either way
+    pub edns: bool,
+    pub bufsize: u16,
+    pub timeout: Duration,
+    // port nameservers are asked on while tracing, only ever changed by tests
+    pub port: u16,
+}
+
+impl Client {
+    pub fn new(server: SocketAddr) -> Client {
+        Client {
+            server,
+            tcp: false,
+            recursion_desired: true,
+            dnssec_ok: false,
+            edns: true,
+            bufsize: edns::UDP_PAYLOAD_SIZE,
+            timeout: Duration::from_secs(5),
+            port: 53,
+        }
+    }
+
+    pub fn request(&self, qname: &str, qtype: QueryType) -> Packet {
+        self.request_with(qname, qtype, self.recursion_desired)
+    }
+
+    fn request_with(&self, qname: &str, qtype: QueryType, recursion_desired: bool) -> Packet {
+        let mut request = Packet::new();
+        request.header.id = rand::random();
+        request.header.recursion_desired = recursion_desired;
+        request.questions.push(Question::new(qname.trim_end_matches('.').to_string(), qtype));
+        if self.edns || self.dnssec_ok {
+            request.resources.push(transport::opt(self.bufsize, self.dnssec_ok));
+        }
+        request
+    }
+
+    // the reply and how long it took to come
+    pub fn query(&self, qname: &str, qtype: QueryType) -> Result<(Packet, Duration)> {
+        self.send(self.server, self.request(qname, qtype))
+    }
+
+    fn send(&self, server: SocketAddr, mut request: Packet) -> Result<(Packet, Duration)> {
+        let start = Instant::now();
+        let response = transport::send_request(server, &mut request, self.tcp, self.timeout)?;
+        Ok((response, start.elapsed()))
+    }
+
+    // iterative resolution done in the open, like `dig +trace`: the root
+    // servers come from `server`, then each referral is followed without RD
+    // and every reply on the way goes to `show`
+    pub fn trace(&self, qname: &str, qtype: QueryType, mut show: impl FnMut(&Packet, SocketAddr, Duration)) -> Result<Packet> {
+        let qname = qname.trim_end_matches('.').to_lowercase();
+        let (mut referral, elapsed) = self.send(self.server, self.request_with("", QueryType::NS, true))?;
+        show(&referral, self.server, elapsed);
+        let mut zone = String::new();
+        for _ in 0..MAX_REFERRALS {
+            let servers = self.nameservers(&referral, &zone);
+            if servers.is_empty() {
+                return Err(format!("no address for any nameserver of `{}.`", zone).into());
+            }
+            let mut failure = None;
+            let mut reply = None;
+            for server in servers {
+                match self.send(server, self.request_with(&qname, qtype, false)) {
+                    Ok((response, elapsed)) => {
+                        reply = Some((response, server, elapsed));
+                        break;
+                    }
+                    Err(e) => failure = Some(format!("{}: {}", server, e)),
+                }
+            }
+            let (response, server, elapsed) = reply.ok_or_else(|| failure.unwrap_or_default())?;
+            show(&response, server, elapsed);
+            if !response.answers.is_empty() || response.header.rescode != ResultCode::NOERROR {
+                return Ok(response);
+            }
+            let cut = response.authorities.iter().find_map(|record| match record {
+                Record::NS { domain, .. } => Some(domain.clone()),
+                _ => None,
+            });
+            match cut {
+                // a referral has to move closer to `qname` or it goes in circles
+                Some(cut) if cut != zone && zone::in_zone(&cut, &zone) && zone::in_zone(&qname, &cut) => zone = cut,
+                _ => return Ok(response),
+            }
+            referral = response;
+        }
+        Err(format!("tracing {} took more than {} referrals", qname, MAX_REFERRALS).into())
+    }
+
+    // the servers for `zone` named in `referral`, from its glue where there
+    // is some and otherwise looked up through `server`, IPv4 first
+    fn nameservers(&self, referral: &Packet, zone: &str) -> Vec<SocketAddr> {
+        let hosts: Vec<&str> = referral.answers.iter()
+            .chain(&referral.authorities)
+            .filter_map(|record| match record {
+                Record::NS { domain, host, .. } if domain == zone => Some(host.as_str()),
+                _ => None,
+            })
+            .collect();
+        let mut addrs: Vec<IpAddr> = referral.resources.iter()
+            .filter(|record| hosts.contains(&record.domain()))
+            .filter_map(|record| match *record {
+                Record::A { addr, .. } => Some(IpAddr::V4(addr)),
+                Record::AAAA { addr, .. } => Some(IpAddr::V6(addr)),
+                _ => None,
+            })
+            .collect();
+        if addrs.is_empty() {
+            for host in hosts {
+                if let Ok((found, _)) = self.send(self.server, self.request_with(host, QueryType::A, true)) {
+                    addrs.extend(found.answers.iter().filter_map(|record| match *record {
+                        Record::A { addr, .. } => Some(IpAddr::V4(addr)),
+                        _ => None,
+                    }));
+                }
+            }
+        }
+        addrs.sort_by_key(|addr| addr.is_ipv6());
+        addrs.dedup();
+        addrs.into_iter().map(|addr| SocketAddr::new(addr, self.port)).collect()
+    }
+}
+
+fn opcode_name(opcode: u8) -> String {
+    match opcode {
+        0 => "QUERY".to_string(),
+        1 => "IQUERY".to_string(),
+        2 => "STATUS".to_string(),
+        4 => "NOTIFY".to_string(),
+        5 => "UPDATE".to_string(),
+        _ => opcode.to_string(),
+    }
+}
+
+// a whole message the way dig lays it out: header, flags and counts, the
+// EDNS pseudo-section, then each section that has anything in it
+pub fn format(packet: &Packet) -> String {
+    let header = &packet.header;
+    let mut out = String::new();
+    let _ = writeln!(out, ";; ->>HEADER<<- opcode: {}, status: {}, id: {}", opcode_name(header.opcode), header.rescode, header.id);
+    let flags: Vec<&str> = [
+        (header.response, "qr"),
+        (header.authoritative_answer, "aa"),
+        (header.truncated_message, "tc"),
+        (header.recursion_desired, "rd"),
+        (header.recursion_available, "ra"),
+        (header.authed_data, "ad"),
+        (header.checking_disabled, "cd"),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, name)| *name)
+    .collect();
+    let _ = writeln!(
+        out,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags.join(" "), packet.questions.len(), packet.answers.len(), packet.authorities.len(), packet.resources.len()
+    );
+    if let Some(opt) = packet.edns() {
+        let _ = write!(out, "\n;; OPT PSEUDOSECTION:\n{}\n", opt);
+    }
+    out.push_str("\n;; QUESTION SECTION:\n");
+    for question in &packet.questions {
+        let _ = writeln!(out, ";{}.\t\t{}\t{}", question.name, record::class_name(question.class), question.qtype);
+    }
+    let additional: Vec<&Record> = packet.resources.iter().filter(|record| !matches!(record, Record::OPT { .. })).collect();
+    for (title, records) in [
+        ("ANSWER", packet.answers.iter().collect::<Vec<&Record>>()),
+        ("AUTHORITY", packet.authorities.iter().collect()),
+        ("ADDITIONAL", additional),
+    ] {
+        if records.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "\n;; {} SECTION:\n", title);
+        for record in records {
+            let _ = writeln!(out, "{}", record);
+        }
+    }
+    out
+}
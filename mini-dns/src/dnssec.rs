@@ -0,0 +1,746 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ring::{digest, signature};
+
+use crate::utils::{Result, ResultCode};
+use crate::record::Record;
+use crate::query_type::QueryType;
+use crate::packet::Packet;
+use crate::byte_bucket_buffer::BytePacketBuffer;
+use crate::zone;
+
+// verified keys and delegations are trusted again without a lookup for at
+// most this long
+const MAX_KEY_TTL: u32 = 3600;
+// RFC 9276 lets validators treat costlier NSEC3 chains as unsigned
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+const MAX_CNAME_CHAIN: usize = 8;
+const FLAG_ZONE_KEY: u16 = 0x0100;
+const FLAG_OPT_OUT: u8 = 0x01;
+const QTYPE_ANY: u16 = 255;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    // every RRset checks out back to the trust anchor
+    Secure,
+    // some of the answer comes from below a delegation that is provably
+    // unsigned, or from outside every trust anchor
+    Insecure,
+    Bogus(String),
+}
+
+#[derive(Clone)]
+enum Keys {
+    Secure(Vec<Record>),
+    Insecure,
+}
+
+// a name as the chain of trust sees it: the apex of a zone, or just a name
+// inside its parent's
+#[derive(Clone)]
+enum Cut {
+    Zone(Keys),
+    Inside,
+}
+
+// how the validator asks for the DS and DNSKEY sets it needs
+pub type Fetch<'a> = dyn Fn(&str, QueryType) -> Result<Packet> + 'a;
+
+// checks answers against a chain of trust built down from the configured
+// anchors (RFC 4035 5)
+pub struct Validator {
+    // DS or DNSKEY records for the zones trusted without proof
+    anchors: Vec<Record>,
+    cuts: Mutex<HashMap<String, (Cut, Instant)>>,
+}
+
+impl Validator {
+    pub fn new(anchors: Vec<Record>) -> Result<Validator> {
+        let anchors: Vec<Record> = anchors.into_iter()
+            .filter(|record| matches!(record, Record::DS { .. } | Record::DNSKEY { .. }))
+            .collect();
+        if anchors.is_empty() {
+            return Err("no DS or DNSKEY trust anchors".into());
+        }
+        Ok(Validator {
+            anchors,
+            cuts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // trust anchors out of a master file, e.g. the root's DS record
+    pub fn from_file(path: &Path) -> Result<Validator> {
+        Validator::new(zone::parse(path)?).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn validate(&self, qname: &str, qtype: QueryType, response: &Packet, fetch: &Fetch) -> Status {
+        match self.check(&qname.to_lowercase(), qtype, response, fetch) {
+            Ok(true) => Status::Secure,
+            Ok(false) => Status::Insecure,
+            Err(e) => Status::Bogus(e.to_string()),
+        }
+    }
+
+    // Ok(false) when part of the answer is provably insecure
+    fn check(&self, qname: &str, qtype: QueryType, response: &Packet, fetch: &Fetch) -> Result<bool> {
+        if self.anchor_for(qname).is_none() {
+            return Ok(false);
+        }
+        match response.header.rescode {
+            ResultCode::NOERROR | ResultCode::NXDOMAIN => {}
+            rescode => return Err(format!("upstream answered {}", rescode).into()),
+        }
+        let now = unix_time();
+        let mut secure = true;
+        // names answered through a wildcard, with the labels it stood for
+        let mut wildcards = Vec::new();
+        let mut records: Vec<Record> = response.answers.clone();
+        records.extend(response.authorities.iter()
+            .filter(|record| matches!(record, Record::SOA { .. } | Record::NSEC { .. } | Record::NSEC3 { .. } | Record::RRSIG { .. }))
+            .cloned());
+        for (owner, rtype, rrset) in rrsets(&records) {
+            let sigs = signatures(&records, &owner, rtype);
+            let signer = sigs.iter().find_map(|sig| match sig {
+                Record::RRSIG { signer_name, .. } if zone::in_zone(&owner, signer_name) => Some(signer_name.clone()),
+                _ => None,
+            });
+            let signer = match signer {
+                Some(signer) => signer,
+                None => match self.closest_zone(&owner, fetch)? {
+                    (_, Keys::Insecure) => {
+                        secure = false;
+                        continue;
+                    }
+                    (zone, Keys::Secure(_)) => return Err(format!("{} {} from signed zone `{}` has no signature", owner, rtype, zone).into()),
+                },
+            };
+            let keys = match self.zone_keys(&signer, fetch)? {
+                Keys::Secure(keys) => keys,
+                Keys::Insecure => {
+                    secure = false;
+                    continue;
+                }
+            };
+            if let Record::RRSIG { labels, .. } = verify_rrset(&rrset, &sigs, &keys, &signer, now)? {
+                if (*labels as usize) < label_count(&owner) {
+                    wildcards.push((owner.clone(), *labels as usize, signer.clone()));
+                }
+            }
+        }
+        let denials: Vec<&Record> = response.authorities.iter().filter(|record| matches!(record, Record::NSEC { .. } | Record::NSEC3 { .. })).collect();
+        for (owner, labels, zone) in wildcards {
+            // the name itself must not exist for a wildcard to answer it
+            let closest = suffix(&owner, labels);
+            let next_closer = suffix(&owner, labels + 1);
+            match prove_covered(&denials, &zone, &owner, &next_closer)? {
+                Proof::Denied => {}
+                Proof::OptOut => secure = false,
+                Proof::Missing => return Err(format!("no proof that {} is not {} under a wildcard", owner, closest).into()),
+            }
+        }
+        let target = chase(&response.answers, qname, qtype);
+        let answered = response.answers.iter()
+            .any(|record| record.domain() == target && (record.qtype() == qtype || qtype.to_num() == QTYPE_ANY));
+        if answered {
+            return Ok(secure);
+        }
+        let (zone, keys) = self.closest_zone(&target, fetch)?;
+        if let Keys::Insecure = keys {
+            return Ok(false);
+        }
+        let proof = if response.header.rescode == ResultCode::NXDOMAIN {
+            prove_nxdomain(&denials, &zone, &target)?
+        } else {
+            prove_nodata(&denials, &zone, &target, qtype)?
+        };
+        match proof {
+            Proof::Denied => Ok(secure),
+            Proof::OptOut => Ok(false),
+            Proof::Missing => Err(format!("no proof that {} {} doesn't exist", target, qtype.to_num()).into()),
+        }
+    }
+
+    fn anchor_for(&self, name: &str) -> Option<&str> {
+        self.anchors.iter()
+            .map(|anchor| anchor.domain())
+            .filter(|anchor| zone::in_zone(name, anchor))
+            .max_by_key(|anchor| anchor.len())
+    }
+
+    // the keys of `zone`, which has to be a zone apex below a trust anchor
+    fn zone_keys(&self, zone: &str, fetch: &Fetch) -> Result<Keys> {
+        match self.closest_zone(zone, fetch)? {
+            (_, Keys::Insecure) => Ok(Keys::Insecure),
+            (apex, keys) if apex == zone => Ok(keys),
+            (apex, _) => Err(format!("`{}` signs as a zone but is part of `{}`", zone, apex).into()),
+        }
+    }
+
+    // walks down from the trust anchor, one DS lookup per label, to the
+    // deepest zone holding `name`; stops at the first unsigned delegation
+    fn closest_zone(&self, name: &str, fetch: &Fetch) -> Result<(String, Keys)> {
+        let anchor = match self.anchor_for(name) {
+            Some(anchor) => anchor.to_string(),
+            None => return Ok((String::new(), Keys::Insecure)),
+        };
+        let mut keys = match self.cached(&anchor) {
+            Some(Cut::Zone(keys)) => keys,
+            _ => {
+                let keys = self.anchor_keys(&anchor, fetch)?;
+                self.remember(&anchor, Cut::Zone(keys.clone()), MAX_KEY_TTL);
+                keys
+            }
+        };
+        let mut zone = anchor.clone();
+        for depth in (0..label_count(name) - label_count(&anchor)).rev() {
+            let parent_keys = match keys {
+                Keys::Secure(ref parent_keys) => parent_keys,
+                Keys::Insecure => break,
+            };
+            let candidate = suffix(name, label_count(name) - depth);
+            let cut = match self.cached(&candidate) {
+                Some(cut) => cut,
+                None => {
+                    let (cut, ttl) = self.delegation(&candidate, &zone, parent_keys, fetch)?;
+                    self.remember(&candidate, cut.clone(), ttl);
+                    cut
+                }
+            };
+            if let Cut::Zone(child) = cut {
+                zone = candidate;
+                keys = child;
+            }
+        }
+        Ok((zone, keys))
+    }
+
+    // whether `name` is a delegation out of `parent`, going by the DS set
+    // there or the parent's signed proof that there is none
+    fn delegation(&self, name: &str, parent: &str, parent_keys: &[Record], fetch: &Fetch) -> Result<(Cut, u32)> {
+        let response = fetch(name, QueryType::DS)?;
+        let now = unix_time();
+        let ds: Vec<Record> = response.answers.iter()
+            .filter(|record| matches!(record, Record::DS { .. }) && record.domain() == name)
+            .cloned()
+            .collect();
+        if !ds.is_empty() {
+            let sigs = signatures(&response.answers, name, QueryType::DS.to_num());
+            verify_rrset(&ds, &sigs, parent_keys, parent, now)?;
+            let ttl = ds.iter().map(Record::ttl).min().unwrap_or(0);
+            return Ok((Cut::Zone(self.trusted_keys(name, |key| ds.iter().any(|ds| ds_matches(ds, key)), &ds, fetch)?), ttl));
+        }
+        // an alias can't sit at a zone cut
+        if response.answers.iter().any(|record| matches!(record, Record::CNAME { domain, .. } if domain == name)) {
+            return Ok((Cut::Inside, MAX_KEY_TTL));
+        }
+        let mut denials = Vec::new();
+        for (owner, qtype, rrset) in rrsets(&response.authorities) {
+            if qtype != QueryType::NSEC.to_num() && qtype != QueryType::NSEC3.to_num() {
+                continue;
+            }
+            let sigs = signatures(&response.authorities, &owner, qtype);
+            verify_rrset(&rrset, &sigs, parent_keys, parent, now)?;
+            denials.extend(rrset);
+        }
+        let ttl = denials.iter().map(Record::ttl).min().unwrap_or(0);
+        let denials: Vec<&Record> = denials.iter().collect();
+        match types_at(&denials, parent, name)? {
+            Some(types) if types.contains(&QueryType::DS.to_num()) => Err(format!("DS of {} is both denied and present", name).into()),
+            Some(types) if types.contains(&QueryType::NS.to_num()) && !types.contains(&QueryType::SOA.to_num()) => Ok((Cut::Zone(Keys::Insecure), ttl)),
+            Some(_) => Ok((Cut::Inside, ttl)),
+            None => match prove_covered(&denials, parent, name, name)? {
+                Proof::Denied => Ok((Cut::Inside, ttl)),
+                Proof::OptOut => Ok((Cut::Zone(Keys::Insecure), ttl)),
+                Proof::Missing => Err(format!("no proof that {} has no DS", name).into()),
+            },
+        }
+    }
+
+    fn anchor_keys(&self, anchor: &str, fetch: &Fetch) -> Result<Keys> {
+        let anchors: Vec<Record> = self.anchors.iter().filter(|record| record.domain() == anchor).cloned().collect();
+        let ds: Vec<Record> = anchors.iter().filter(|record| matches!(record, Record::DS { .. })).cloned().collect();
+        self.trusted_keys(anchor, |key| {
+            anchors.iter().any(|anchor| match anchor {
+                Record::DS { .. } => ds_matches(anchor, key),
+                _ => rdata(anchor).ok() == rdata(key).ok(),
+            })
+        }, &ds, fetch)
+    }
+
+    // the DNSKEY set of `zone`, once one of the keys `vouched` for has
+    // signed it; a zone whose DS records all use algorithms this validator
+    // doesn't know is treated as unsigned (RFC 4035 5.2)
+    fn trusted_keys<F: Fn(&Record) -> bool>(&self, zone: &str, vouched: F, ds: &[Record], fetch: &Fetch) -> Result<Keys> {
+        let known = ds.iter().any(|ds| matches!(ds, Record::DS { algorithm, digest_type, .. } if supported_algorithm(*algorithm) && digest_algorithm(*digest_type).is_some()));
+        if !ds.is_empty() && !known {
+            return Ok(Keys::Insecure);
+        }
+        let response = fetch(zone, QueryType::DNSKEY)?;
+        let keys: Vec<Record> = response.answers.iter()
+            .filter(|record| matches!(record, Record::DNSKEY { .. }) && record.domain() == zone)
+            .cloned()
+            .collect();
+        let entry: Vec<Record> = keys.iter().filter(|key| vouched(key)).cloned().collect();
+        if entry.is_empty() {
+            return Err(format!("no DNSKEY of `{}` matches its trust anchor or DS", zone).into());
+        }
+        let sigs = signatures(&response.answers, zone, QueryType::DNSKEY.to_num());
+        verify_rrset(&keys, &sigs, &entry, zone, unix_time())?;
+        Ok(Keys::Secure(keys))
+    }
+
+    fn cached(&self, name: &str) -> Option<Cut> {
+        let mut cuts = self.cuts.lock().unwrap();
+        match cuts.get(name) {
+            Some((cut, expires)) if Instant::now() < *expires => Some(cut.clone()),
+            Some(_) => {
+                cuts.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn remember(&self, name: &str, cut: Cut, ttl: u32) {
+        let expires = Instant::now() + Duration::from_secs(ttl.min(MAX_KEY_TTL) as u64);
+        self.cuts.lock().unwrap().insert(name.to_string(), (cut, expires));
+    }
+}
+
+// the answer's RRsets, signatures aside, as (owner, type, records)
+fn rrsets(records: &[Record]) -> Vec<(String, u16, Vec<Record>)> {
+    let mut sets: Vec<(String, u16, Vec<Record>)> = Vec::new();
+    for record in records {
+        if matches!(record, Record::RRSIG { .. } | Record::OPT { .. }) {
+            continue;
+        }
+        let qtype = record.qtype().to_num();
+        match sets.iter_mut().find(|(owner, other, _)| owner == record.domain() && *other == qtype) {
+            Some((_, _, set)) => set.push(record.clone()),
+            None => sets.push((record.domain().to_string(), qtype, vec![record.clone()])),
+        }
+    }
+    sets
+}
+
+fn signatures<'a>(records: &'a [Record], owner: &str, qtype: u16) -> Vec<&'a Record> {
+    records.iter()
+        .filter(|record| matches!(record, Record::RRSIG { domain, type_covered, .. } if domain == owner && *type_covered == qtype))
+        .collect()
+}
+
+// the first of `sigs` made by `signer` that one of `keys` verifies over
+// `rrset`
+fn verify_rrset<'a>(rrset: &[Record], sigs: &[&'a Record], keys: &[Record], signer: &str, now: u32) -> Result<&'a Record> {
+    let (owner, qtype) = match rrset.first() {
+        Some(record) => (record.domain(), record.qtype().to_num()),
+        None => return Err("empty RRset".into()),
+    };
+    let mut problem = format!("{} {} has no signature by `{}`", owner, qtype, signer);
+    for sig in sigs {
+        let (algorithm, labels, expiration, inception, key_tag, signer_name, signature) = match sig {
+            Record::RRSIG { algorithm, labels, expiration, inception, key_tag, signer_name, signature, .. } => {
+                (*algorithm, *labels, *expiration, *inception, *key_tag, signer_name, signature)
+            }
+            _ => continue,
+        };
+        if signer_name != signer || labels as usize > label_count(owner) {
+            continue;
+        }
+        if !supported_algorithm(algorithm) {
+            problem = format!("{} {} is signed with unsupported algorithm {}", owner, qtype, algorithm);
+            continue;
+        }
+        // RFC 4034 3.1.5 compares the times in serial number arithmetic
+        if (now.wrapping_sub(inception) as i32) < 0 || (expiration.wrapping_sub(now) as i32) < 0 {
+            problem = format!("signature over {} {} is outside its validity period", owner, qtype);
+            continue;
+        }
+        let data = signed_data(sig, rrset)?;
+        for key in keys {
+            let (flags, key_algorithm, public_key) = match key {
+                Record::DNSKEY { flags, algorithm, public_key, .. } => (*flags, *algorithm, public_key),
+                _ => continue,
+            };
+            if flags & FLAG_ZONE_KEY == 0 || key_algorithm != algorithm || key_tag_of(key)? != key_tag {
+                continue;
+            }
+            if verify_signature(algorithm, public_key, &data, signature) {
+                return Ok(sig);
+            }
+            problem = format!("signature over {} {} by key {} doesn't verify", owner, qtype, key_tag);
+        }
+    }
+    Err(problem.into())
+}
+
+// the RRSIG's own fields followed by every record of the set in canonical
+// form and order, with the original ttl (RFC 4034 3.1.8.1)
+pub fn signed_data(sig: &Record, rrset: &[Record]) -> Result<Vec<u8>> {
+    let (type_covered, labels, original_ttl) = match sig {
+        Record::RRSIG { type_covered, labels, original_ttl, .. } => (*type_covered, *labels as usize, *original_ttl),
+        _ => return Err("not an RRSIG".into()),
+    };
+    let mut buffer = BytePacketBuffer::uncompressed();
+    let whole = rdata(sig)?;
+    let signature_len = match sig {
+        Record::RRSIG { signature, .. } => signature.len(),
+        _ => 0,
+    };
+    for b in &whole[..whole.len() - signature_len] {
+        buffer.write_u8(*b)?;
+    }
+    // a record expanded from a wildcard is signed under the wildcard's name
+    let domain = rrset[0].domain();
+    let owner = if label_count(domain) > labels {
+        format!("*.{}", suffix(domain, labels)).trim_end_matches('.').to_string()
+    } else {
+        domain.to_string()
+    };
+    let mut rdatas = rrset.iter().map(rdata).collect::<Result<Vec<Vec<u8>>>>()?;
+    rdatas.sort();
+    rdatas.dedup();
+    for data in rdatas {
+        buffer.write_qname(&owner)?;
+        buffer.write_u16(type_covered)?;
+        buffer.write_u16(1)?;
+        buffer.write_u32(original_ttl)?;
+        buffer.write_u16(data.len() as u16)?;
+        for b in data {
+            buffer.write_u8(b)?;
+        }
+    }
+    buffer.buf.truncate(buffer.pos());
+    Ok(buffer.buf)
+}
+
+// a record's rdata in canonical form: names written out in full and, since
+// names are lowercased on the way in, in lower case
+fn rdata(record: &Record) -> Result<Vec<u8>> {
+    let mut buffer = BytePacketBuffer::uncompressed();
+    record.write(&mut buffer)?;
+    let start = name_length(record.domain()) + 10;
+    Ok(buffer.buf[start..buffer.pos()].to_vec())
+}
+
+fn verify_signature(algorithm: u8, public_key: &[u8], data: &[u8], sig: &[u8]) -> bool {
+    let rsa = |params: &signature::RsaParameters| -> bool {
+        // RFC 3110: exponent length, exponent, modulus
+        let (len, rest) = match public_key {
+            [0, hi, lo, rest @ ..] => (u16::from_be_bytes([*hi, *lo]) as usize, rest),
+            [len, rest @ ..] => (*len as usize, rest),
+            [] => return false,
+        };
+        if len == 0 || rest.len() <= len {
+            return false;
+        }
+        let (e, n) = rest.split_at(len);
+        signature::RsaPublicKeyComponents { n, e }.verify(params, data, sig).is_ok()
+    };
+    let ecdsa = |params: &'static signature::EcdsaVerificationAlgorithm| -> bool {
+        let mut point = vec![0x04];
+        point.extend_from_slice(public_key);
+        signature::UnparsedPublicKey::new(params, point).verify(data, sig).is_ok()
+    };
+    match algorithm {
+        5 | 7 => rsa(&signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY),
+        8 => rsa(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY),
+        10 => rsa(&signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY),
+        13 => ecdsa(&signature::ECDSA_P256_SHA256_FIXED),
+        14 => ecdsa(&signature::ECDSA_P384_SHA384_FIXED),
+        15 => signature::UnparsedPublicKey::new(&signature::ED25519, public_key).verify(data, sig).is_ok(),
+        _ => false,
+    }
+}
+
+fn supported_algorithm(algorithm: u8) -> bool {
+    matches!(algorithm, 5 | 7 | 8 | 10 | 13 | 14 | 15)
+}
+
+fn digest_algorithm(digest_type: u8) -> Option<&'static digest::Algorithm> {
+    match digest_type {
+        1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+        2 => Some(&digest::SHA256),
+        4 => Some(&digest::SHA384),
+        _ => None,
+    }
+}
+
+// a DS record is the digest of its key's owner name and rdata (RFC 4034 5.1.4)
+pub fn ds_matches(ds: &Record, key: &Record) -> bool {
+    let (key_tag, algorithm, digest_type, expected) = match ds {
+        Record::DS { key_tag, algorithm, digest_type, digest, .. } => (*key_tag, *algorithm, *digest_type, digest),
+        _ => return false,
+    };
+    let key_algorithm = match key {
+        Record::DNSKEY { algorithm, .. } => *algorithm,
+        _ => return false,
+    };
+    let algo = match digest_algorithm(digest_type) {
+        Some(algo) => algo,
+        None => return false,
+    };
+    if ds.domain() != key.domain() || algorithm != key_algorithm || key_tag_of(key).ok() != Some(key_tag) {
+        return false;
+    }
+    let mut buffer = BytePacketBuffer::uncompressed();
+    if buffer.write_qname(key.domain()).is_err() {
+        return false;
+    }
+    let mut data = buffer.buf[..buffer.pos()].to_vec();
+    match rdata(key) {
+        Ok(key_data) => data.extend(key_data),
+        Err(_) => return false,
+    }
+    digest::digest(algo, &data).as_ref() == expected.as_slice()
+}
+
+// RFC 4034 appendix B
+pub fn key_tag_of(key: &Record) -> Result<u16> {
+    let data = rdata(key)?;
+    let mut sum: u32 = 0;
+    for (i, b) in data.iter().enumerate() {
+        sum += if i & 1 == 0 { (*b as u32) << 8 } else { *b as u32 };
+    }
+    sum += (sum >> 16) & 0xFFFF;
+    Ok((sum & 0xFFFF) as u16)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Proof {
+    Denied,
+    // an opt-out NSEC3 span covers the name, so it may be an unsigned
+    // delegation (RFC 5155 6)
+    OptOut,
+    Missing,
+}
+
+// the types at `name` according to an NSEC or NSEC3 record owned by it
+fn types_at(denials: &[&Record], zone: &str, name: &str) -> Result<Option<Vec<u16>>> {
+    for record in denials {
+        match record {
+            Record::NSEC { domain, types, .. } if domain == name => return Ok(Some(types.clone())),
+            Record::NSEC3 { domain, types, .. } => {
+                if let Some(hash) = nsec3_hash(record, name)? {
+                    if *domain == format!("{}.{}", hash, zone).trim_end_matches('.') {
+                        return Ok(Some(types.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+// that `name` doesn't exist: an NSEC spanning it, or for NSEC3 an
+// NSEC3 spanning `next_closer`, the name one label below its closest
+// encloser
+fn prove_covered(denials: &[&Record], zone: &str, name: &str, next_closer: &str) -> Result<Proof> {
+    for record in denials {
+        match record {
+            Record::NSEC { domain, next_domain, .. } if nsec_covers(domain, next_domain, name) => return Ok(Proof::Denied),
+            Record::NSEC3 { domain, next_hashed, flags, .. } => {
+                let hash = match nsec3_hash(record, next_closer)? {
+                    Some(hash) => hash,
+                    None => return Ok(Proof::OptOut),
+                };
+                let owner = domain.split('.').next().unwrap_or("");
+                if !zone::in_zone(domain, zone) || !nsec3_covers(owner, &base32hex(next_hashed), &hash) {
+                    continue;
+                }
+                if flags & FLAG_OPT_OUT != 0 {
+                    return Ok(Proof::OptOut);
+                }
+                return Ok(Proof::Denied);
+            }
+            _ => {}
+        }
+    }
+    Ok(Proof::Missing)
+}
+
+// the closest encloser of `name` that a denial vouches for, plus the name
+// one label further down (RFC 5155 8.3)
+fn closest_encloser(denials: &[&Record], zone: &str, name: &str) -> Result<Option<(String, String)>> {
+    let nsec3 = denials.iter().any(|record| matches!(record, Record::NSEC3 { .. }));
+    let depth = label_count(name);
+    for labels in (label_count(zone)..depth).rev() {
+        let candidate = suffix(name, labels);
+        let next_closer = suffix(name, labels + 1);
+        let exists = if nsec3 {
+            types_at(denials, zone, &candidate)?.is_some()
+        } else {
+            // with NSEC the encloser is whatever the covering span shares
+            // with the name
+            denials.iter().any(|record| match record {
+                Record::NSEC { domain, next_domain, .. } => {
+                    nsec_covers(domain, next_domain, name) && (zone::in_zone(domain, &candidate) || zone::in_zone(next_domain, &candidate))
+                }
+                _ => false,
+            })
+        };
+        if exists {
+            return Ok(Some((candidate, next_closer)));
+        }
+    }
+    Ok(None)
+}
+
+fn prove_nxdomain(denials: &[&Record], zone: &str, name: &str) -> Result<Proof> {
+    let (encloser, next_closer) = match closest_encloser(denials, zone, name)? {
+        Some(found) => found,
+        None => return Ok(Proof::Missing),
+    };
+    let covered = prove_covered(denials, zone, name, &next_closer)?;
+    if covered != Proof::Denied {
+        return Ok(covered);
+    }
+    // and no wildcard could have answered instead
+    let wildcard = format!("*.{}", encloser).trim_end_matches('.').to_string();
+    prove_covered(denials, zone, &wildcard, &wildcard)
+}
+
+fn prove_nodata(denials: &[&Record], zone: &str, name: &str, qtype: QueryType) -> Result<Proof> {
+    let absent = |types: &[u16]| !types.contains(&qtype.to_num()) && !types.contains(&QueryType::CNAME.to_num());
+    if let Some(types) = types_at(denials, zone, name)? {
+        return Ok(if absent(&types) { Proof::Denied } else { Proof::Missing });
+    }
+    // an empty non-terminal: the span ends at a name below it
+    let empty = denials.iter().any(|record| match record {
+        Record::NSEC { domain, next_domain, .. } => nsec_covers(domain, next_domain, name) && zone::in_zone(next_domain, name),
+        _ => false,
+    });
+    if empty {
+        return Ok(Proof::Denied);
+    }
+    let (encloser, next_closer) = match closest_encloser(denials, zone, name)? {
+        Some(found) => found,
+        None => return Ok(Proof::Missing),
+    };
+    let covered = prove_covered(denials, zone, name, &next_closer)?;
+    if covered != Proof::Denied || qtype == QueryType::DS {
+        return Ok(covered);
+    }
+    // a wildcard matched but has no records of the type
+    let wildcard = format!("*.{}", encloser).trim_end_matches('.').to_string();
+    match types_at(denials, zone, &wildcard)? {
+        Some(types) if absent(&types) => Ok(Proof::Denied),
+        _ => Ok(Proof::Missing),
+    }
+}
+
+// the NSEC3 hash of `name` with `nsec3`'s parameters, None when those are
+// unsupported or too costly to compute
+fn nsec3_hash(nsec3: &Record, name: &str) -> Result<Option<String>> {
+    let (hash_algorithm, iterations, salt) = match nsec3 {
+        Record::NSEC3 { hash_algorithm, iterations, salt, .. } => (*hash_algorithm, *iterations, salt),
+        _ => return Ok(None),
+    };
+    if hash_algorithm != 1 || iterations > MAX_NSEC3_ITERATIONS {
+        return Ok(None);
+    }
+    let mut buffer = BytePacketBuffer::uncompressed();
+    buffer.write_qname(name)?;
+    let mut data = buffer.buf[..buffer.pos()].to_vec();
+    for _ in 0..=iterations {
+        data.extend_from_slice(salt);
+        data = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data).as_ref().to_vec();
+    }
+    Ok(Some(base32hex(&data)))
+}
+
+// RFC 4648 section 7, lower case and unpadded as NSEC3 owner names use it
+pub fn base32hex(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+    let mut text = String::new();
+    let mut bits: u64 = 0;
+    let mut count = 0;
+    for b in data {
+        bits = (bits << 8) | *b as u64;
+        count += 8;
+        while count >= 5 {
+            count -= 5;
+            text.push(ALPHABET[((bits >> count) & 0x1F) as usize] as char);
+        }
+    }
+    if count > 0 {
+        text.push(ALPHABET[((bits << (5 - count)) & 0x1F) as usize] as char);
+    }
+    text
+}
+
+fn nsec_covers(owner: &str, next: &str, name: &str) -> bool {
+    let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+    // the last NSEC of a zone points back at the apex
+    if canonical_cmp(owner, next) != Ordering::Less {
+        return after_owner || canonical_cmp(name, next) == Ordering::Less;
+    }
+    after_owner && canonical_cmp(name, next) == Ordering::Less
+}
+
+// base32hex keeps the order of the hashes, so the encoded forms compare
+fn nsec3_covers(owner: &str, next: &str, hash: &str) -> bool {
+    if owner >= next {
+        return hash > owner || hash < next;
+    }
+    hash > owner && hash < next
+}
+
+// RFC 4034 6.1: label by label from the root down
+fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let a: Vec<&str> = a.split('.').filter(|label| !label.is_empty()).rev().collect();
+    let b: Vec<&str> = b.split('.').filter(|label| !label.is_empty()).rev().collect();
+    for (x, y) in a.iter().zip(b.iter()) {
+        match x.as_bytes().cmp(y.as_bytes()) {
+            Ordering::Equal => continue,
+            other => return other,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+// where the answer's CNAMEs lead from `qname`
+fn chase(answers: &[Record], qname: &str, qtype: QueryType) -> String {
+    let mut target = qname.to_string();
+    if qtype == QueryType::CNAME {
+        return target;
+    }
+    for _ in 0..MAX_CNAME_CHAIN {
+        let next = answers.iter().find_map(|record| match record {
+            Record::CNAME { domain, host, .. } if *domain == target => Some(host.clone()),
+            _ => None,
+        });
+        match next {
+            Some(next) => target = next,
+            None => break,
+        }
+    }
+    target
+}
+
+fn label_count(name: &str) -> usize {
+    name.split('.').filter(|label| !label.is_empty()).count()
+}
+
+// the last `labels` labels of `name`
+fn suffix(name: &str, labels: usize) -> String {
+    let all: Vec<&str> = name.split('.').filter(|label| !label.is_empty()).collect();
+    all[all.len().saturating_sub(labels)..].join(".")
+}
+
+fn name_length(name: &str) -> usize {
+    if name.is_empty() {
+        1
+    } else {
+        name.len() + 2
+    }
+}
+
+fn unix_time() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs() as u32).unwrap_or(0)
+}
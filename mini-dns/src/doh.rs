@@ -0,0 +1,195 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::utils::Result;
+use crate::record::Record;
+use crate::packet::Packet;
+use crate::byte_bucket_buffer::{self, BytePacketBuffer};
+use crate::transport;
+
+// DNS over HTTPS (RFC 8484): a query is either the `dns` parameter of a GET,
+// base64url without padding, or the body of a POST, and the answer comes
+// back as the body of the response
+pub const PATH: &str = "/dns-query";
+pub const MEDIA_TYPE: &str = "application/dns-message";
+// request line and headers together
+const MAX_HEAD_SIZE: usize = 8192;
+
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub reason: &'static str,
+    pub content_type: &'static str,
+    // how long the answer may be cached, from its smallest ttl
+    pub max_age: Option<u32>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn error(status: u16, reason: &'static str) -> Response {
+        Response {
+            status,
+            reason,
+            content_type: "text/plain",
+            max_age: None,
+            body: reason.as_bytes().to_vec(),
+        }
+    }
+}
+
+// None is a connection closed between requests
+pub fn read_request<R: BufRead>(stream: &mut R) -> Result<Option<Request>> {
+    let mut head = (&mut *stream).take(MAX_HEAD_SIZE as u64);
+    let mut line = String::new();
+    if head.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method.to_string(), target.to_string()),
+        _ => return Err(format!("bad request line `{}`", line.trim_end()).into()),
+    };
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line)? == 0 {
+            return Err("request head is cut short or too long".into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| format!("bad header `{}`", line))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let mut request = Request {
+        method,
+        target,
+        headers,
+        body: Vec::new(),
+    };
+    if request.header("transfer-encoding").is_some() {
+        return Err("chunked bodies aren't supported".into());
+    }
+    let len = match request.header("content-length") {
+        Some(len) => len.parse::<usize>()?,
+        None => 0,
+    };
+    if len > byte_bucket_buffer::MAX_SIZE {
+        return Err(format!("body of {} bytes is too big for a DNS message", len).into());
+    }
+    request.body = vec![0; len];
+    stream.read_exact(&mut request.body)?;
+    Ok(Some(request))
+}
+
+// the DNS query carried by `request`, or the response refusing it
+pub fn query_of(request: &Request) -> std::result::Result<Packet, Response> {
+    let (path, params) = request.target.split_once('?').unwrap_or((&request.target, ""));
+    if path != PATH {
+        return Err(Response::error(404, "Not Found"));
+    }
+    let wire = match request.method.as_str() {
+        "GET" => {
+            let dns = params.split('&').find_map(|param| param.strip_prefix("dns="));
+            dns.and_then(base64url).ok_or(Response::error(400, "Bad Request"))?
+        }
+        "POST" => {
+            if request.header("content-type") != Some(MEDIA_TYPE) {
+                return Err(Response::error(415, "Unsupported Media Type"));
+            }
+            request.body.clone()
+        }
+        _ => return Err(Response::error(405, "Method Not Allowed")),
+    };
+    let mut buffer = BytePacketBuffer::with_size(wire.len());
+    buffer.buf.copy_from_slice(&wire);
+    Packet::from_buffer(&mut buffer).map_err(|_| Response::error(400, "Bad Request"))
+}
+
+pub fn write_response<W: Write>(stream: &mut W, response: &Response) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+        response.status, response.reason, response.content_type, response.body.len()
+    );
+    if let Some(max_age) = response.max_age {
+        head.push_str(&format!("Cache-Control: max-age={}\r\n", max_age));
+    }
+    head.push_str("\r\n");
+    let mut message = head.into_bytes();
+    message.extend_from_slice(&response.body);
+    stream.write_all(&message)?;
+    stream.flush()?;
+    Ok(())
+}
+
+// answers each request on a connection in turn until the client hangs up or
+// asks to close
+pub fn serve<S: Read + Write>(stream: S, mut answer: impl FnMut(Packet) -> Packet) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    while let Some(request) = read_request(&mut stream)? {
+        let response = match query_of(&request) {
+            Ok(query) => {
+                let mut packet = answer(query);
+                let buffer = transport::encode(&mut packet, transport::MAX_TCP_SIZE)?;
+                Response {
+                    status: 200,
+                    reason: "OK",
+                    content_type: MEDIA_TYPE,
+                    max_age: packet.answers.iter()
+                        .chain(&packet.authorities)
+                        .chain(&packet.resources)
+                        .filter(|record| !matches!(record, Record::OPT { .. }))
+                        .map(|record| record.ttl())
+                        .min(),
+                    body: buffer.buf[0..buffer.pos()].to_vec(),
+                }
+            }
+            Err(response) => response,
+        };
+        write_response(stream.get_mut(), &response)?;
+        if request.header("connection").is_some_and(|value| value.eq_ignore_ascii_case("close")) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// RFC 4648 section 5, with the padding left off as RFC 8484 asks
+pub fn base64url(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(bytes)
+}
@@ -0,0 +1,126 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::utils::{Error, Result};
+use crate::byte_bucket_buffer::BytePacketBuffer;
+
+pub const OPTION_CLIENT_SUBNET: u16 = 8;
+pub const OPTION_COOKIE: u16 = 10;
+
+// what this server advertises and the most it sends over UDP, the size
+// DNS flag day 2020 settled on to stay clear of fragmentation
+pub const UDP_PAYLOAD_SIZE: u16 = 1232;
+
+// EDNS(0) options carried in an OPT record (RFC 6891)
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EdnsOption {
+    // RFC 7871; only the first `source_prefix` bits of `addr` go on the wire
+    ClientSubnet {
+        source_prefix: u8,
+        scope_prefix: u8,
+        addr: IpAddr,
+    },
+    // RFC 7873; `server` is empty until a server has handed one out
+    Cookie {
+        client: Vec<u8>,
+        server: Vec<u8>,
+    },
+    Unknown {
+        code: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl EdnsOption {
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<EdnsOption> {
+        let code = buffer.read_u16()?;
+        let len = buffer.read_u16()? as usize;
+        let data = buffer.get_range(buffer.pos(), len)?.to_vec();
+        buffer.step(len)?;
+        match code {
+            OPTION_CLIENT_SUBNET => {
+                if len < 4 {
+                    return Err(Error::BadRdata("client subnet option is too short".to_string()));
+                }
+                let family = u16::from_be_bytes([data[0], data[1]]);
+                let source_prefix = data[2];
+                let scope_prefix = data[3];
+                let address = &data[4..];
+                let (width, addr) = match family {
+                    1 => {
+                        let mut octets = [0; 4];
+                        octets.get_mut(..address.len()).ok_or_else(|| Error::BadRdata("client subnet address is too long".to_string()))?.copy_from_slice(address);
+                        (32, IpAddr::V4(Ipv4Addr::from(octets)))
+                    }
+                    2 => {
+                        let mut octets = [0; 16];
+                        octets.get_mut(..address.len()).ok_or_else(|| Error::BadRdata("client subnet address is too long".to_string()))?.copy_from_slice(address);
+                        (128, IpAddr::V6(Ipv6Addr::from(octets)))
+                    }
+                    _ => return Err(Error::BadRdata(format!("unknown client subnet family {}", family))),
+                };
+                if source_prefix > width || scope_prefix > width || address.len() != (source_prefix as usize).div_ceil(8) {
+                    return Err(Error::BadRdata("malformed client subnet option".to_string()));
+                }
+                Ok(EdnsOption::ClientSubnet {
+                    source_prefix,
+                    scope_prefix,
+                    addr,
+                })
+            }
+            OPTION_COOKIE => {
+                if len != 8 && !(16..=40).contains(&len) {
+                    return Err(Error::BadRdata(format!("cookie option of {} bytes", len)));
+                }
+                Ok(EdnsOption::Cookie {
+                    client: data[..8].to_vec(),
+                    server: data[8..].to_vec(),
+                })
+            }
+            _ => Ok(EdnsOption::Unknown { code, data }),
+        }
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        match *self {
+            EdnsOption::ClientSubnet {
+                source_prefix,
+                scope_prefix,
+                ref addr,
+            } => {
+                let (family, octets) = match addr {
+                    IpAddr::V4(addr) => (1, addr.octets().to_vec()),
+                    IpAddr::V6(addr) => (2, addr.octets().to_vec()),
+                };
+                let len = (source_prefix as usize).div_ceil(8).min(octets.len());
+                buffer.write_u16(OPTION_CLIENT_SUBNET)?;
+                buffer.write_u16(4 + len as u16)?;
+                buffer.write_u16(family)?;
+                buffer.write_u8(source_prefix)?;
+                buffer.write_u8(scope_prefix)?;
+                for (i, octet) in octets[..len].iter().enumerate() {
+                    // bits past the prefix must be zero
+                    let keep = (source_prefix as usize).saturating_sub(i * 8).min(8);
+                    buffer.write_u8(octet & !(0xFFu8.checked_shr(keep as u32).unwrap_or(0)))?;
+                }
+            }
+            EdnsOption::Cookie {
+                ref client,
+                ref server,
+            } => {
+                buffer.write_u16(OPTION_COOKIE)?;
+                buffer.write_u16((client.len() + server.len()) as u16)?;
+                for b in client.iter().chain(server) {
+                    buffer.write_u8(*b)?;
+                }
+            }
+            EdnsOption::Unknown { code, ref data } => {
+                buffer.write_u16(code)?;
+                buffer.write_u16(data.len() as u16)?;
+                for b in data {
+                    buffer.write_u8(*b)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
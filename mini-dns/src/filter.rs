@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use crate::utils::{Result, ResultCode};
+use crate::record::Record;
+use crate::query_type::QueryType;
+use crate::zone::{self, Answer};
+
+const MAX_CNAME_CHAIN: usize = 8;
+// how long clients may hold on to a sinkhole address, short so a reload
+// shows quickly
+const FILTER_TTL: u32 = 60;
+// names every hosts file carries for the machine itself
+const HOSTS_BOILERPLATE: [&str; 8] = [
+    "localhost", "localhost.localdomain", "local", "broadcasthost",
+    "ip6-localhost", "ip6-loopback", "ip6-localnet", "0.0.0.0",
+];
+
+// answers given before zones or upstreams are asked: static overrides first,
+// then blocklists, either NXDOMAIN or the sinkhole addresses
+pub struct Filter {
+    pub blocklists: Vec<PathBuf>,
+    pub overrides: Vec<PathBuf>,
+    pub sinkhole: Vec<IpAddr>,
+    blocked: HashSet<String>,
+    // domains whose subdomains are all blocked, from `*.domain`
+    blocked_below: HashSet<String>,
+    fixed: HashMap<String, Vec<Record>>,
+}
+
+impl Filter {
+    pub fn load(blocklists: Vec<PathBuf>, overrides: Vec<PathBuf>, sinkhole: Vec<IpAddr>) -> Result<Filter> {
+        let mut filter = Filter {
+            blocklists,
+            overrides,
+            sinkhole,
+            blocked: HashSet::new(),
+            blocked_below: HashSet::new(),
+            fixed: HashMap::new(),
+        };
+        for path in &filter.blocklists.clone() {
+            filter.load_blocklist(path)?;
+        }
+        for path in &filter.overrides.clone() {
+            filter.load_overrides(path)?;
+        }
+        Ok(filter)
+    }
+
+    // hosts files, `0.0.0.0 ads.example.com`, and plain lists of names, one
+    // to a line, where `*.example.com` takes in everything below the domain
+    fn load_blocklist(&mut self, path: &Path) -> Result<()> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let names = match words.as_slice() {
+                [] => continue,
+                [first, rest @ ..] if first.parse::<IpAddr>().is_ok() => rest,
+                [_] => &words[..],
+                _ => return Err(format!("{}:{}: expected a name or a hosts file entry", path.display(), i + 1).into()),
+            };
+            for name in names {
+                let name = name.trim_end_matches('.').to_lowercase();
+                if HOSTS_BOILERPLATE.contains(&name.as_str()) {
+                    continue;
+                }
+                match name.strip_prefix("*.") {
+                    Some(domain) => self.blocked_below.insert(domain.to_string()),
+                    None => self.blocked.insert(name),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    // overrides are A, AAAA and CNAME records in master file syntax
+    fn load_overrides(&mut self, path: &Path) -> Result<()> {
+        for record in zone::parse(path)? {
+            if !matches!(record, Record::A { .. } | Record::AAAA { .. } | Record::CNAME { .. }) {
+                return Err(format!("{}: `{}` overrides only take A, AAAA and CNAME records", path.display(), record.domain()).into());
+            }
+            let rrset = self.fixed.entry(record.domain().to_string()).or_default();
+            let clash = match record {
+                Record::CNAME { .. } => !rrset.is_empty(),
+                _ => rrset.iter().any(|other| matches!(other, Record::CNAME { .. })),
+            };
+            if clash {
+                return Err(format!("{}: `{}` has a CNAME next to other data", path.display(), record.domain()).into());
+            }
+            rrset.push(record);
+        }
+        Ok(())
+    }
+
+    pub fn is_blocked(&self, qname: &str) -> bool {
+        if self.blocked.contains(qname) {
+            return true;
+        }
+        let mut name = qname;
+        while let Some(i) = name.find('.') {
+            name = &name[i + 1..];
+            if self.blocked_below.contains(name) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // None lets the query through; an override ending in a CNAME that leads
+    // outside the overrides is for the caller to follow
+    pub fn answer(&self, qname: &str, qtype: QueryType) -> Option<Answer> {
+        let mut answer = Answer {
+            rescode: ResultCode::NOERROR,
+            authoritative: false,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            resources: Vec::new(),
+        };
+        if self.fixed.contains_key(qname) {
+            let mut name = qname.to_string();
+            for _ in 0..MAX_CNAME_CHAIN {
+                let Some(rrset) = self.fixed.get(&name) else { break };
+                match rrset.as_slice() {
+                    [Record::CNAME { host, .. }] if qtype != QueryType::CNAME => {
+                        answer.answers.push(rrset[0].clone());
+                        name = host.clone();
+                    }
+                    _ => {
+                        answer.answers.extend(rrset.iter().filter(|record| record.qtype() == qtype).cloned());
+                        break;
+                    }
+                }
+            }
+            return Some(answer);
+        }
+        if !self.is_blocked(qname) {
+            return None;
+        }
+        if self.sinkhole.is_empty() {
+            answer.rescode = ResultCode::NXDOMAIN;
+            return Some(answer);
+        }
+        for addr in &self.sinkhole {
+            let domain = qname.to_string();
+            match (addr, qtype) {
+                (IpAddr::V4(addr), QueryType::A) => answer.answers.push(Record::A { domain, addr: *addr, ttl: FILTER_TTL }),
+                (IpAddr::V6(addr), QueryType::AAAA) => answer.answers.push(Record::AAAA { domain, addr: *addr, ttl: FILTER_TTL }),
+                _ => {}
+            }
+        }
+        Some(answer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocked.len() + self.blocked_below.len() + self.fixed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -20,6 +20,12 @@ pub struct Header {
     pub resource_entries: u16,
 }
 
+impl Default for Header {
+    fn default() -> Header {
+        Header::new()
+    }
+}
+
 impl Header {
     pub fn new() -> Header {
         Header {
@@ -51,7 +57,7 @@ impl Header {
         self.authoritative_answer = (a & (1 << 2)) > 0;
         self.opcode = (a >> 3) & 0x0F;
         self.response = (a & (1 << 7)) > 0;
-        self.rescode = ResultCode::from_num(b & 0x0F);
+        self.rescode = ResultCode::from_num((b & 0x0F) as u16);
         self.checking_disabled = (b & (1 << 4)) > 0;
         self.authed_data = (b & (1 << 5)) > 0;
         self.z = (b & (1 << 6)) > 0;
@@ -65,8 +71,8 @@ impl Header {
 
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
         buffer.write_u16(self.id)?;
-        buffer.write_u8((self.recursion_desired as u8) | ((self.truncated_message as u8) << 1) | ((self.authoritative_answer as u8) << 2) | (self.opcode << 3) | ((self.response as u8) << 7) as u8)?;
-        buffer.write_u8((self.rescode as u8) | ((self.checking_disabled as u8) << 4) | ((self.authed_data as u8) << 5) | ((self.z as u8) << 6) | ((self.recursion_available as u8) << 7))?;
+        buffer.write_u8((self.recursion_desired as u8) | ((self.truncated_message as u8) << 1) | ((self.authoritative_answer as u8) << 2) | (self.opcode << 3) | ((self.response as u8) << 7))?;
+        buffer.write_u8(((self.rescode.to_num() as u8) & 0x0F) | ((self.checking_disabled as u8) << 4) | ((self.authed_data as u8) << 5) | ((self.z as u8) << 6) | ((self.recursion_available as u8) << 7))?;
         buffer.write_u16(self.questions)?;
         buffer.write_u16(self.answers)?;
         buffer.write_u16(self.authoritative_entries)?;
@@ -0,0 +1,20 @@
+pub mod utils;
+pub mod record;
+pub mod header;
+pub mod packet;
+pub mod question;
+pub mod query_type;
+pub mod byte_bucket_buffer;
+pub mod zone;
+pub mod resolver;
+pub mod cache;
+pub mod transport;
+pub mod edns;
+pub mod dnssec;
+pub mod upstream;
+pub mod filter;
+pub mod tls;
+pub mod doh;
+pub mod client;
+pub mod tsig;
+pub mod update;
@@ -1,46 +1,212 @@
-mod utils;
-mod record;
-mod header;
-mod packet;
-mod question;
-mod query_type;
-mod byte_bucket_buffer;
-
-use std::net::UdpSocket;
-use crate::utils::{Result, ResultCode};
-use crate::query_type::QueryType;
-use crate::packet::Packet;
-use crate::question::Question;
-use crate::byte_bucket_buffer::BytePacketBuffer;
-
-fn lookup(qname: &str, qtype: QueryType) -> Result<Packet> {
-    let server = ("8.8.8.8", 53);
-    let socket = UdpSocket::bind(("0.0.0.0", 43210))?;
-    let mut packet = Packet::new();
-    packet.header.id = 6666;
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet.questions.push(Question::new(qname.to_string(), qtype));
-    let mut req_buffer = BytePacketBuffer::new();
-    packet.write(&mut req_buffer)?;
-    socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
-    let mut res_buffer = BytePacketBuffer::new();
-    socket.recv_from(&mut res_buffer.buf)?;
-    Packet::from_buffer(&mut res_buffer)
-}
-
-fn handle_query(socket: &UdpSocket) -> Result<()> {
-    let mut req_buffer = BytePacketBuffer::new();
-    let (_, src) = socket.recv_from(&mut req_buffer.buf)?;
-    let mut request = Packet::from_buffer(&mut req_buffer)?;
+use std::env;
+use std::io;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use mini_dns::utils::{Error, Result, ResultCode};
+use mini_dns::query_type::QueryType;
+use mini_dns::packet::Packet;
+use mini_dns::question::Question;
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::cache::{Cache, Key};
+use mini_dns::client::{self, Client};
+use mini_dns::dnssec::{Status, Validator};
+use mini_dns::doh;
+use mini_dns::edns::{self, EdnsOption};
+use mini_dns::filter::Filter;
+use mini_dns::record::Record;
+use mini_dns::resolver::Resolver;
+use mini_dns::tls;
+use mini_dns::transport;
+use mini_dns::tsig::{self, Tsig};
+use mini_dns::update::{self, Update};
+use mini_dns::upstream::{self, Upstreams};
+use mini_dns::zone::{Answer, Zones};
+
+// where queries are forwarded without root hints, unless told otherwise
+const DEFAULT_UPSTREAMS: [&str; 2] = ["8.8.8.8", "1.1.1.1"];
+// where `mini-dns query` asks unless given `@server`: this server itself
+const LOCAL_SERVER: &str = "127.0.0.1:2053";
+// how often servers taken out of rotation are asked whether they're back
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_TCP_CONNECTIONS: usize = 128;
+
+fn fetch(server: &Server, qname: &str, qtype: QueryType, dnssec_ok: bool) -> Result<Packet> {
+    match &server.resolver {
+        Some(resolver) => resolver.resolve(qname, qtype),
+        None => server.upstreams.query(qname, qtype, dnssec_ok),
+    }
+}
+
+// with a validator, AD says the answer checked out; bogus answers are never
+// cached and only go to clients that asked for no checking with CD
+fn resolve(question: &Question, checking_disabled: bool, server: &Server) -> Result<Packet> {
+    let key = Key::new(&question.name, question.qtype, question.class);
+    let now = Instant::now();
+    if let Some(cached) = server.cache.get(&key, now) {
+        return Ok(cached);
+    }
+    let mut result = fetch(server, &question.name, question.qtype, server.validator.is_some())?;
+    result.header.authed_data = false;
+    if let Some(validator) = &server.validator {
+        match validator.validate(&question.name, question.qtype, &result, &|qname, qtype| fetch(server, qname, qtype, true)) {
+            Status::Secure => result.header.authed_data = true,
+            Status::Insecure => {}
+            Status::Bogus(reason) if checking_disabled => {
+                println!("Bogus answer for {} passed on with CD: {}", question.name, reason);
+                return Ok(result);
+            }
+            Status::Bogus(reason) => return Err(format!("bogus answer for {}: {}", question.name, reason).into()),
+        }
+    }
+    server.cache.insert(key, &result, now);
+    Ok(result)
+}
+
+// DNSSEC records only go to clients that set DO, or asked for them by type
+// (RFC 4035 3.2.1)
+fn strip_dnssec(records: &mut Vec<Record>, qtype: QueryType) {
+    records.retain(|rec| {
+        !matches!(rec, Record::RRSIG { .. } | Record::NSEC { .. } | Record::NSEC3 { .. }) || rec.qtype() == qtype
+    });
+}
+
+// an override's CNAME that leads out of the overrides is followed like any
+// other name
+fn follow_override(server: &Server, answer: &mut Answer, qtype: QueryType, checking_disabled: bool) {
+    let target = match answer.answers.last() {
+        Some(Record::CNAME { host, .. }) if qtype != QueryType::CNAME => host.clone(),
+        _ => return,
+    };
+    match resolve(&Question::new(target, qtype), checking_disabled, server) {
+        Ok(mut result) => {
+            strip_dnssec(&mut result.answers, qtype);
+            answer.rescode = result.header.rescode;
+            answer.answers.append(&mut result.answers);
+        }
+        Err(_) => answer.rescode = ResultCode::SERVFAIL,
+    }
+}
+
+// what every worker shares
+struct Server {
+    udp: UdpSocket,
+    zones: RwLock<Zones>,
+    filter: RwLock<Filter>,
+    resolver: Option<Resolver>,
+    upstreams: Upstreams,
+    validator: Option<Validator>,
+    cache: Cache,
+    // keys the server cookies handed to clients
+    cookie_secret: RandomState,
+    // dynamic updates are taken when signed with one of these keys, or
+    // unsigned as well with `allow_updates`
+    tsig_keys: Vec<tsig::Key>,
+    allow_updates: bool,
+}
+
+// the OPT record answering a client's: our payload size, its DO bit, a
+// server cookie to go with its client cookie, and its subnet echoed back with
+// a scope of 0 since answers don't depend on it
+fn reply_edns(server: &Server, opt: &Record, src: IpAddr) -> Record {
+    let (dnssec_ok, options) = match opt {
+        Record::OPT { dnssec_ok, options, .. } => (*dnssec_ok, options.as_slice()),
+        _ => (false, &[][..]),
+    };
+    let options = options.iter()
+        .filter_map(|option| match option {
+            EdnsOption::Cookie { client, .. } => Some(EdnsOption::Cookie {
+                client: client.clone(),
+                server: server.cookie_secret.hash_one((client, src)).to_be_bytes().to_vec(),
+            }),
+            EdnsOption::ClientSubnet { source_prefix, addr, .. } => Some(EdnsOption::ClientSubnet {
+                source_prefix: *source_prefix,
+                scope_prefix: 0,
+                addr: *addr,
+            }),
+            EdnsOption::Unknown { .. } => None,
+        })
+        .collect();
+    Record::OPT {
+        packet_len: edns::UDP_PAYLOAD_SIZE,
+        ext_rcode: 0,
+        version: 0,
+        dnssec_ok,
+        options,
+    }
+}
+
+// how big a UDP response the client said it takes, within what we send
+fn payload_limit(request: &Packet) -> usize {
+    match request.edns() {
+        Some(Record::OPT { packet_len, .. }) => (*packet_len as usize).clamp(transport::MAX_UDP_SIZE, edns::UDP_PAYLOAD_SIZE as usize),
+        _ => transport::MAX_UDP_SIZE,
+    }
+}
+
+fn respond(server: &Server, mut request: Packet, src: IpAddr) -> Packet {
     let mut packet = Packet::new();
     packet.header.id = request.header.id;
     packet.header.recursion_desired = true;
     packet.header.recursion_available = true;
     packet.header.response = true;
+    packet.header.checking_disabled = request.header.checking_disabled;
+    let opts = request.resources.iter().filter(|rec| matches!(rec, Record::OPT { .. })).count();
+    let edns = request.edns().cloned();
+    // a query asks exactly one question (RFC 9619)
+    if opts > 1 || request.questions.len() != 1 {
+        packet.header.rescode = ResultCode::FORMERR;
+        return packet;
+    }
+    if let Some(opt) = &edns {
+        packet.resources.push(reply_edns(server, opt, src));
+        if matches!(opt, Record::OPT { version, .. } if *version > 0) {
+            packet.questions.append(&mut request.questions);
+            packet.header.rescode = ResultCode::BADVERS;
+            return packet;
+        }
+    }
     if let Some(question) = request.questions.pop() {
         println!("Received query: {:?}", question);
-        if let Ok(result) = lookup(&question.name, question.qtype) {
+        let qname = question.name.to_lowercase();
+        // the locks are let go before anything goes upstream
+        let filtered = server.filter.read().unwrap().answer(&qname, question.qtype);
+        let local = match filtered {
+            Some(_) => None,
+            None => server.zones.read().unwrap().find(&qname).map(|zone| zone.answer(&qname, question.qtype)),
+        };
+        if let Some(mut answer) = filtered {
+            println!("Filtered: {}", qname);
+            follow_override(server, &mut answer, question.qtype, request.header.checking_disabled);
+            packet.questions.push(question);
+            packet.header.rescode = answer.rescode;
+            packet.answers = answer.answers;
+        } else if let Some(answer) = local {
+            packet.questions.push(question);
+            packet.header.recursion_available = false;
+            packet.header.authoritative_answer = answer.authoritative;
+            packet.header.rescode = answer.rescode;
+            packet.answers = answer.answers;
+            packet.authorities = answer.authorities;
+            packet.resources.extend(answer.resources);
+        } else if let Ok(mut result) = resolve(&question, request.header.checking_disabled, server) {
+            let dnssec_ok = matches!(edns, Some(Record::OPT { dnssec_ok: true, .. }));
+            if !dnssec_ok {
+                strip_dnssec(&mut result.answers, question.qtype);
+                strip_dnssec(&mut result.authorities, question.qtype);
+                strip_dnssec(&mut result.resources, question.qtype);
+            }
+            packet.header.authed_data = result.header.authed_data && (dnssec_ok || request.header.authed_data);
             packet.questions.push(question);
             packet.header.rescode = result.header.rescode;
             for rec in result.answers {
@@ -51,7 +217,8 @@ fn handle_query(socket: &UdpSocket) -> Result<()> {
                 println!("Authority: {:?}", rec);
                 packet.authorities.push(rec);
             }
-            for rec in result.resources {
+            // OPT only means something between the two ends that sent it
+            for rec in result.resources.into_iter().filter(|rec| !matches!(rec, Record::OPT { .. })) {
                 println!("Resource: {:?}", rec);
                 packet.resources.push(rec);
             }
@@ -61,20 +228,464 @@ fn handle_query(socket: &UdpSocket) -> Result<()> {
     } else {
         packet.header.rescode = ResultCode::FORMERR;
     }
-    let mut res_buffer = BytePacketBuffer::new();
-    packet.write(&mut res_buffer)?;
-    let len = res_buffer.pos();
-    let data = res_buffer.get_range(0, len)?;
-    socket.send_to(data, src)?;
+    packet
+}
+
+// an update that fails its TSIG check is NOTAUTH and says why in the
+// reply's own TSIG, signed unless the key or MAC was the problem (RFC 8945
+// 5.2)
+fn handle_update(server: &Server, req_buffer: &mut BytePacketBuffer, src: IpAddr) -> Result<BytePacketBuffer> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    let (signed, update) = match (tsig::check(&server.tsig_keys, &req_buffer.buf, None, now), Update::from_buffer(req_buffer)) {
+        (Ok(signed), Ok(update)) => (signed, update),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("Malformed update from {}: {}", src, e);
+            let mut packet = Packet::format_error(req_buffer).ok_or(e)?;
+            return transport::encode(&mut packet, transport::MAX_UDP_SIZE);
+        }
+    };
+    let mut packet = Packet::new();
+    packet.header.id = update.header.id;
+    packet.header.opcode = update::OPCODE_UPDATE;
+    packet.header.response = true;
+    packet.questions.push(update.zone.clone());
+    packet.header.rescode = match &signed {
+        Some(signed) if signed.error != 0 => ResultCode::NOTAUTH,
+        None if !server.allow_updates => ResultCode::REFUSED,
+        _ => match server.zones.write().unwrap().get_mut(&update.zone.name) {
+            Some(zone) => update::apply(zone, &update).unwrap_or_else(|e| {
+                eprintln!("Update of {} failed: {}", update.zone.name, e);
+                ResultCode::SERVFAIL
+            }),
+            None => ResultCode::NOTAUTH,
+        },
+    };
+    println!("Update of {} from {}: {}", update.zone.name, src, packet.header.rescode);
+    let mut res_buffer = transport::encode(&mut packet, transport::MAX_UDP_SIZE)?;
+    if let Some(signed) = signed {
+        // a client whose clock is off is sent ours to compare
+        let (time_signed, other) = match signed.error {
+            tsig::BADTIME => (signed.tsig.time_signed, now.to_be_bytes()[2..].to_vec()),
+            _ => (now, Vec::new()),
+        };
+        let mut tsig = Tsig {
+            time_signed,
+            original_id: update.header.id,
+            error: signed.error,
+            other,
+            ..signed.tsig.clone()
+        };
+        tsig.sign(signed.key, &mut res_buffer, Some(&signed.tsig.mac))?;
+    }
+    Ok(res_buffer)
+}
+
+fn handle_query(server: &Server) -> Result<()> {
+    let socket = &server.udp;
+    let mut req_buffer = BytePacketBuffer::with_size(edns::UDP_PAYLOAD_SIZE as usize);
+    let (len, src) = socket.recv_from(&mut req_buffer.buf)?;
+    req_buffer.buf.truncate(len);
+    if update::is_update(&req_buffer) {
+        let res_buffer = handle_update(server, &mut req_buffer, src.ip())?;
+        socket.send_to(&res_buffer.buf[0..res_buffer.pos()], src)?;
+        return Ok(());
+    }
+    let (mut packet, limit) = match Packet::from_buffer(&mut req_buffer) {
+        // answering responses would let two servers bounce a packet forever
+        Ok(request) if request.header.response => return Ok(()),
+        Ok(request) => {
+            let limit = payload_limit(&request);
+            (respond(server, request, src.ip()), limit)
+        }
+        Err(e) => {
+            println!("Malformed query from {}: {}", src, e);
+            (Packet::format_error(&mut req_buffer).ok_or(e)?, transport::MAX_UDP_SIZE)
+        }
+    };
+    let res_buffer = transport::encode(&mut packet, limit)?;
+    socket.send_to(&res_buffer.buf[0..res_buffer.pos()], src)?;
     Ok(())
 }
 
+// connections that go quiet are dropped
+fn client_of(stream: &TcpStream) -> Result<IpAddr> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    Ok(stream.peer_addr()?.ip())
+}
+
+// updates don't parse as queries, so they're picked out of the stream
+// before anything else
+fn answer_stream(server: &Server, req_buffer: &mut BytePacketBuffer, src: IpAddr) -> Result<Option<BytePacketBuffer>> {
+    if update::is_update(req_buffer) {
+        return handle_update(server, req_buffer, src).map(Some);
+    }
+    transport::reply_to(req_buffer, |request| respond(server, request, src))
+}
+
+fn handle_connection(server: &Server, stream: TcpStream) -> Result<()> {
+    let src = client_of(&stream)?;
+    transport::serve_raw(stream, |req_buffer| answer_stream(server, req_buffer, src))
+}
+
+// DNS over TLS is DNS over TCP inside the TLS session
+fn handle_dot(server: &Server, stream: TcpStream, config: &Arc<ServerConfig>) -> Result<()> {
+    let src = client_of(&stream)?;
+    let stream = StreamOwned::new(ServerConnection::new(Arc::clone(config))?, stream);
+    transport::serve_raw(stream, |req_buffer| answer_stream(server, req_buffer, src))
+}
+
+fn handle_doh(server: &Server, stream: TcpStream, config: &Arc<ServerConfig>) -> Result<()> {
+    let src = client_of(&stream)?;
+    let stream = StreamOwned::new(ServerConnection::new(Arc::clone(config))?, stream);
+    doh::serve(stream, |request| respond(server, request, src))
+}
+
+fn serve_tcp<F>(server: Arc<Server>, listener: TcpListener, handle: F)
+where
+    F: Fn(&Server, TcpStream) -> Result<()> + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("An error occurred: {}", e);
+                continue;
+            }
+        };
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_TCP_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let server = Arc::clone(&server);
+        let connections = Arc::clone(&connections);
+        let handle = Arc::clone(&handle);
+        thread::spawn(move || {
+            if let Err(e) = handle(&server, stream) {
+                if !is_wakeup(&e) {
+                    eprintln!("An error occurred: {}", e);
+                }
+            }
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+struct Options {
+    zones: Vec<PathBuf>,
+    blocklists: Vec<PathBuf>,
+    overrides: Vec<PathBuf>,
+    // what blocked names resolve to, instead of NXDOMAIN
+    sinkhole: Vec<IpAddr>,
+    upstreams: Vec<SocketAddr>,
+    // domains whose names go to servers of their own
+    routes: Vec<(String, Vec<SocketAddr>)>,
+    root_hints: Option<PathBuf>,
+    trust_anchor: Option<PathBuf>,
+    cache_size: usize,
+    workers: usize,
+    // DNS over TLS and over HTTPS listen only when given a port
+    dot_port: Option<u16>,
+    doh_port: Option<u16>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tsig_keys: Vec<tsig::Key>,
+    allow_updates: bool,
+}
+
+fn parse_args() -> Result<Options> {
+    let mut options = Options {
+        zones: Vec::new(),
+        blocklists: Vec::new(),
+        overrides: Vec::new(),
+        sinkhole: Vec::new(),
+        upstreams: Vec::new(),
+        routes: Vec::new(),
+        root_hints: None,
+        trust_anchor: None,
+        cache_size: 10000,
+        workers: 64,
+        dot_port: None,
+        doh_port: None,
+        tls_cert: None,
+        tls_key: None,
+        tsig_keys: Vec::new(),
+        allow_updates: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--zone" => options.zones.push(PathBuf::from(args.next().ok_or("--zone needs a file")?)),
+            "--blocklist" => options.blocklists.push(PathBuf::from(args.next().ok_or("--blocklist needs a file")?)),
+            "--overrides" => options.overrides.push(PathBuf::from(args.next().ok_or("--overrides needs a file")?)),
+            "--sinkhole" => options.sinkhole.push(args.next().ok_or("--sinkhole needs an address")?.parse()?),
+            "--upstream" => options.upstreams.push(upstream::parse_server(&args.next().ok_or("--upstream needs a server")?)?),
+            "--route" => options.routes.push(parse_route(&args.next().ok_or("--route needs a domain and servers")?)?),
+            "--root-hints" => options.root_hints = Some(PathBuf::from(args.next().ok_or("--root-hints needs a file")?)),
+            "--trust-anchor" => options.trust_anchor = Some(PathBuf::from(args.next().ok_or("--trust-anchor needs a file")?)),
+            "--cache-size" => options.cache_size = args.next().ok_or("--cache-size needs a number")?.parse()?,
+            "--workers" => options.workers = args.next().ok_or("--workers needs a number")?.parse()?,
+            "--dot-port" => options.dot_port = Some(args.next().ok_or("--dot-port needs a port")?.parse()?),
+            "--doh-port" => options.doh_port = Some(args.next().ok_or("--doh-port needs a port")?.parse()?),
+            "--tls-cert" => options.tls_cert = Some(PathBuf::from(args.next().ok_or("--tls-cert needs a file")?)),
+            "--tls-key" => options.tls_key = Some(PathBuf::from(args.next().ok_or("--tls-key needs a file")?)),
+            "--tsig-key" => options.tsig_keys.push(tsig::Key::parse(&args.next().ok_or("--tsig-key needs [algorithm:]name:secret")?)?),
+            "--allow-updates" => options.allow_updates = true,
+            _ => return Err(format!("unknown argument `{}`", arg).into()),
+        }
+    }
+    Ok(options)
+}
+
+// `corp.internal=10.0.0.53,10.0.0.54:5353`
+fn parse_route(arg: &str) -> Result<(String, Vec<SocketAddr>)> {
+    let (domain, servers) = arg.split_once('=').ok_or_else(|| format!("route `{}` needs a domain=server", arg))?;
+    let servers = servers.split(',').map(upstream::parse_server).collect::<Result<Vec<SocketAddr>>>()?;
+    Ok((domain.to_string(), servers))
+}
+
+// what `mini-dns query` is to do, parsed dig style: a name, then a type,
+// `@server` and any `+option`
+struct QueryOptions {
+    client: Client,
+    qname: String,
+    qtype: QueryType,
+    trace: bool,
+}
+
+fn parse_query_args(args: &[String]) -> Result<QueryOptions> {
+    let mut client = Client::new(upstream::parse_server(LOCAL_SERVER)?);
+    let mut qname = None;
+    let mut qtype = None;
+    let mut trace = false;
+    for arg in args {
+        if let Some(server) = arg.strip_prefix('@') {
+            client.server = upstream::parse_server(server)?;
+        } else if let Some(option) = arg.strip_prefix('+') {
+            match option.split_once('=') {
+                Some(("bufsize", size)) => client.bufsize = size.parse()?,
+                Some(_) => return Err(format!("unknown option `{}`", arg).into()),
+                None => match option {
+                    "tcp" => client.tcp = true,
+                    "notcp" => client.tcp = false,
+                    "rec" | "recurse" => client.recursion_desired = true,
+                    "norec" | "norecurse" => client.recursion_desired = false,
+                    "dnssec" => client.dnssec_ok = true,
+                    "nodnssec" => client.dnssec_ok = false,
+                    "edns" => client.edns = true,
+                    "noedns" => client.edns = false,
+                    "trace" => trace = true,
+                    "notrace" => trace = false,
+                    _ => return Err(format!("unknown option `{}`", arg).into()),
+                },
+            }
+        } else if qname.is_none() {
+            qname = Some(arg.clone());
+        } else if qtype.is_none() {
+            qtype = Some(QueryType::from_name(arg).ok_or_else(|| format!("unknown type `{}`", arg))?);
+        } else {
+            return Err(format!("unexpected argument `{}`", arg).into());
+        }
+    }
+    Ok(QueryOptions {
+        client,
+        qname: qname.ok_or("query needs a name")?,
+        qtype: qtype.unwrap_or(QueryType::A),
+        trace,
+    })
+}
+
+fn run_query(args: &[String]) -> Result<()> {
+    let options = match parse_query_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: mini-dns query <name> [<type>] [@<server>] [+tcp] [+norec] [+dnssec] [+noedns] [+bufsize=<bytes>] [+trace]");
+            process::exit(2);
+        }
+    };
+    println!("; <<>> mini-dns <<>> {}", args.join(" "));
+    let client = &options.client;
+    if options.trace {
+        let traced = client.trace(&options.qname, options.qtype, |response, server, elapsed| {
+            for record in response.answers.iter().chain(&response.authorities) {
+                println!("{}", record);
+            }
+            println!(";; Received from {} in {} ms\n", server, elapsed.as_millis());
+        });
+        if let Err(e) = traced {
+            eprintln!(";; trace failed: {}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    match client.query(&options.qname, options.qtype) {
+        Ok((response, elapsed)) => {
+            print!("{}", client::format(&response));
+            println!("\n;; Query time: {} msec", elapsed.as_millis());
+            println!(";; SERVER: {} ({})", client.server, if client.tcp { "TCP" } else { "UDP" });
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!(";; no answer from {}: {}", client.server, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn is_wakeup(e: &Error) -> bool {
+    match e {
+        Error::Io(e) => matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut),
+        _ => false,
+    }
+}
+
 fn main() -> Result<()> {
-    let socket = UdpSocket::bind(("0.0.0.0", 2053))?;
-    loop {
-        match handle_query(&socket) {
-            Ok(_) => {}
-            Err(e) => eprintln!("An error occurred: {}", e),
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "query") {
+        return run_query(&args[1..]);
+    }
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: mini-dns [--zone <file>]... [--blocklist <file>]... [--overrides <file>]... [--sinkhole <addr>]... [--upstream <server>]... [--route <domain>=<server>[,<server>]...]... [--root-hints <file>] [--trust-anchor <file>] [--cache-size <entries>] [--workers <n>] [--dot-port <port>] [--doh-port <port>] [--tls-cert <file>] [--tls-key <file>] [--tsig-key [<algorithm>:]<name>:<secret>]... [--allow-updates]");
+            eprintln!("       mini-dns query <name> [<type>] [@<server>] [+<option>]...");
+            process::exit(2);
+        }
+    };
+    let zones = match Zones::load(options.zones) {
+        Ok(zones) => zones,
+        Err(e) => {
+            eprintln!("Can't load zones: {}", e);
+            process::exit(1);
+        }
+    };
+    println!("Loaded {} zone(s)", zones.len());
+    let filter = match Filter::load(options.blocklists, options.overrides, options.sinkhole) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Can't load filters: {}", e);
+            process::exit(1);
+        }
+    };
+    println!("Loaded {} filtered name(s)", filter.len());
+    // without a trust anchor answers are passed on unchecked
+    let validator = match options.trust_anchor.as_deref().map(Validator::from_file).transpose() {
+        Ok(validator) => validator,
+        Err(e) => {
+            eprintln!("Can't load trust anchor: {}", e);
+            process::exit(1);
+        }
+    };
+    // without root hints queries are forwarded as before
+    let mut resolver = match options.root_hints.as_deref().map(Resolver::from_hints).transpose() {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            eprintln!("Can't load root hints: {}", e);
+            process::exit(1);
         }
+    };
+    if let Some(resolver) = resolver.as_mut() {
+        resolver.dnssec_ok = validator.is_some();
     }
-}
\ No newline at end of file
+    let defaults = DEFAULT_UPSTREAMS.iter().map(|server| upstream::parse_server(server)).collect::<Result<Vec<SocketAddr>>>()?;
+    let mut upstreams = Upstreams::new(if options.upstreams.is_empty() { defaults } else { options.upstreams });
+    for (domain, servers) in options.routes {
+        upstreams.route(&domain, servers);
+    }
+    let tls_config = |alpn| match (&options.tls_cert, &options.tls_key) {
+        (Some(cert), Some(key)) => tls::server_config(cert, key, alpn),
+        _ => Err("DNS over TLS or HTTPS needs --tls-cert and --tls-key".into()),
+    };
+    let dot = options.dot_port.map(|port| tls_config(tls::DOT_ALPN).map(|config| (port, config))).transpose();
+    let doh = options.doh_port.map(|port| tls_config(tls::DOH_ALPN).map(|config| (port, config))).transpose();
+    let (dot, doh) = match (dot, doh) {
+        (Ok(dot), Ok(doh)) => (dot, doh),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Can't set up TLS: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut signals = Signals::new([SIGHUP])?;
+    if options.workers == 0 {
+        eprintln!("--workers must be at least 1");
+        process::exit(2);
+    }
+    let server = Arc::new(Server {
+        udp: UdpSocket::bind(("0.0.0.0", 2053))?,
+        zones: RwLock::new(zones),
+        filter: RwLock::new(filter),
+        resolver,
+        upstreams,
+        validator,
+        cache: Cache::new(options.cache_size),
+        cookie_secret: RandomState::new(),
+        tsig_keys: options.tsig_keys,
+        allow_updates: options.allow_updates,
+    });
+    // every worker blocks on the same socket, so a slow upstream only holds
+    // up the query it is working on
+    for _ in 0..options.workers {
+        let server = Arc::clone(&server);
+        thread::spawn(move || loop {
+            match handle_query(&server) {
+                Ok(_) => {}
+                Err(e) if is_wakeup(&e) => {}
+                Err(e) => eprintln!("An error occurred: {}", e),
+            }
+        });
+    }
+    let checked = Arc::clone(&server);
+    thread::spawn(move || loop {
+        thread::sleep(HEALTH_CHECK_INTERVAL);
+        checked.upstreams.check();
+    });
+    let listener = TcpListener::bind(("0.0.0.0", 2053))?;
+    let tcp = Arc::clone(&server);
+    thread::spawn(move || serve_tcp(tcp, listener, handle_connection));
+    if let Some((port, config)) = dot {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let tcp = Arc::clone(&server);
+        thread::spawn(move || serve_tcp(tcp, listener, move |server, stream| handle_dot(server, stream, &config)));
+    }
+    if let Some((port, config)) = doh {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let tcp = Arc::clone(&server);
+        thread::spawn(move || serve_tcp(tcp, listener, move |server, stream| handle_doh(server, stream, &config)));
+    }
+    for _ in signals.forever() {
+        // the files are read with no lock held, so queries and updates go on
+        // meanwhile; updates that came in are replayed under the write lock
+        // before the swap, so none are lost with the old zones
+        let (paths, seen) = {
+            let zones = server.zones.read().unwrap();
+            (zones.paths.clone(), zones.generations())
+        };
+        let reloaded = Zones::load(paths).and_then(|mut reloaded| {
+            let mut zones = server.zones.write().unwrap();
+            reloaded.catch_up(&zones, &seen)?;
+            let count = reloaded.len();
+            *zones = reloaded;
+            Ok(count)
+        });
+        match reloaded {
+            Ok(count) => println!("Reloaded {} zone(s)", count),
+            Err(e) => eprintln!("Zone reload failed, keeping the old zones: {}", e),
+        }
+        let reloaded = {
+            let filter = server.filter.read().unwrap();
+            Filter::load(filter.blocklists.clone(), filter.overrides.clone(), filter.sinkhole.clone())
+        };
+        match reloaded {
+            Ok(filter) => {
+                println!("Reloaded {} filtered name(s)", filter.len());
+                *server.filter.write().unwrap() = filter;
+            }
+            Err(e) => eprintln!("Filter reload failed, keeping the old filters: {}", e),
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::utils::Result;
+use crate::utils::{Error, Result, ResultCode};
 use crate::header::Header;
 use crate::question::Question;
 use crate::record::Record;
@@ -14,6 +14,12 @@ pub struct Packet {
     pub resources: Vec<Record>,
 }
 
+impl Default for Packet {
+    fn default() -> Packet {
+        Packet::new()
+    }
+}
+
 impl Packet {
     pub fn new() -> Packet {
         Packet {
@@ -28,6 +34,13 @@ impl Packet {
     pub fn from_buffer(buffer: &mut BytePacketBuffer) -> Result<Packet> {
         let mut result = Packet::new();
         result.header.read(buffer)?;
+        // a question takes at least 5 bytes and a record 11, so counts the
+        // message can't hold are refused before anything is read
+        let questions = result.header.questions as usize;
+        let records = result.header.answers as usize + result.header.authoritative_entries as usize + result.header.resource_entries as usize;
+        if buffer.pos() + 5 * questions + 11 * records > buffer.buf.len() {
+            return Err(Error::BadCounts { records: questions + records, len: buffer.buf.len() });
+        }
         for _ in 0..result.header.questions {
             let mut question = Question::new(String::new(), QueryType::UNKNOWN(0));
             question.read(buffer)?;
@@ -45,10 +58,44 @@ impl Packet {
             let rec = Record::read(buffer)?;
             result.resources.push(rec);
         }
+        if let Some(Record::OPT { ext_rcode, .. }) = result.edns() {
+            let code = ((*ext_rcode as u16) << 4) | result.header.rescode.to_num();
+            result.header.rescode = ResultCode::from_num(code);
+        }
         Ok(result)
     }
 
+    // the reply to a query that doesn't parse: FORMERR under its id, or None
+    // when there's no header to answer or it's a response itself
+    pub fn format_error(buffer: &mut BytePacketBuffer) -> Option<Packet> {
+        let mut header = Header::new();
+        buffer.seek(0).ok()?;
+        header.read(buffer).ok()?;
+        if header.response {
+            return None;
+        }
+        let mut packet = Packet::new();
+        packet.header.id = header.id;
+        packet.header.opcode = header.opcode;
+        packet.header.recursion_desired = header.recursion_desired;
+        packet.header.response = true;
+        packet.header.rescode = ResultCode::FORMERR;
+        Some(packet)
+    }
+
+    // the sender's OPT record, present when it speaks EDNS(0)
+    pub fn edns(&self) -> Option<&Record> {
+        self.resources.iter().find(|rec| matches!(rec, Record::OPT { .. }))
+    }
+
     pub fn write(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        // the upper rcode bits travel in the OPT record
+        let upper = (self.header.rescode.to_num() >> 4) as u8;
+        for rec in self.resources.iter_mut() {
+            if let Record::OPT { ext_rcode, .. } = rec {
+                *ext_rcode = upper;
+            }
+        }
         self.header.questions = self.questions.len() as u16;
         self.header.answers = self.answers.len() as u16;
         self.header.authoritative_entries = self.authorities.len() as u16;
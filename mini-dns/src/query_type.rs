@@ -1,22 +1,51 @@
+use std::fmt;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum QueryType {
     UNKNOWN(u16),
     A,
     NS,
     CNAME,
+    SOA,
+    PTR,
+    HINFO,
     MX,
+    TXT,
     AAAA,
+    SRV,
+    NAPTR,
+    OPT,
+    DS,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    CAA,
 }
 
 impl QueryType {
-    pub fn to_num(&self) -> u16 {
-        match *self {
+    pub fn to_num(self) -> u16 {
+        match self {
             QueryType::UNKNOWN(x) => x,
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::CAA => 257,
         }
     }
 
@@ -25,9 +54,59 @@ impl QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            13 => QueryType::HINFO,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            257 => QueryType::CAA,
             _ => QueryType::UNKNOWN(num),
         }
     }
-}
\ No newline at end of file
+
+    // the mnemonic as written in zone files and queries, or the generic
+    // `TYPE<n>` form (RFC 3597 5)
+    pub fn from_name(name: &str) -> Option<QueryType> {
+        let name = name.to_uppercase();
+        let qtype = match name.as_str() {
+            "A" => QueryType::A,
+            "NS" => QueryType::NS,
+            "CNAME" => QueryType::CNAME,
+            "SOA" => QueryType::SOA,
+            "PTR" => QueryType::PTR,
+            "HINFO" => QueryType::HINFO,
+            "MX" => QueryType::MX,
+            "TXT" => QueryType::TXT,
+            "AAAA" => QueryType::AAAA,
+            "SRV" => QueryType::SRV,
+            "NAPTR" => QueryType::NAPTR,
+            "OPT" => QueryType::OPT,
+            "DS" => QueryType::DS,
+            "RRSIG" => QueryType::RRSIG,
+            "NSEC" => QueryType::NSEC,
+            "DNSKEY" => QueryType::DNSKEY,
+            "NSEC3" => QueryType::NSEC3,
+            "CAA" => QueryType::CAA,
+            _ => return name.strip_prefix("TYPE")?.parse().ok().map(QueryType::from_num),
+        };
+        Some(qtype)
+    }
+}
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryType::UNKNOWN(x) => write!(f, "TYPE{}", x),
+            known => write!(f, "{:?}", known),
+        }
+    }
+}
@@ -6,20 +6,22 @@ use crate::byte_bucket_buffer::BytePacketBuffer;
 pub struct Question {
     pub name: String,
     pub qtype: QueryType,
+    pub class: u16,
 }
 
 impl Question {
     pub fn new(name: String, qtype: QueryType) -> Question {
         Question {
             name,
-            qtype
+            qtype,
+            class: 1,
         }
     }
 
     pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
         buffer.read_qname(&mut self.name)?;
         self.qtype = QueryType::from_num(buffer.read_u16()?);
-        let _ = buffer.read_u16()?;
+        self.class = buffer.read_u16()?;
         Ok(())
     }
 
@@ -27,7 +29,7 @@ impl Question {
         buffer.write_qname(&self.name)?;
         let typenum = self.qtype.to_num();
         buffer.write_u16(typenum)?;
-        buffer.write_u16(1)?;
+        buffer.write_u16(self.class)?;
         Ok(())
     }
 }
\ No newline at end of file
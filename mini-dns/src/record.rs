@@ -1,14 +1,23 @@
+use std::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
-use crate::utils::Result;
+use crate::utils::{Error, Result};
 use crate::query_type::QueryType;
 use crate::byte_bucket_buffer::BytePacketBuffer;
+use crate::edns::EdnsOption;
+use crate::dnssec::base32hex;
+
+pub const CLASS_IN: u16 = 1;
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Record {
+    // any type without a variant of its own, and any record outside class
+    // IN, kept as raw rdata so it can be passed on verbatim (RFC 3597)
     UNKNOWN {
         domain: String,
         qtype: u16,
-        data_len: u16,
+        class: u16,
+        data: Vec<u8>,
         ttl: u32,
     }, // 0
     A {
@@ -26,28 +35,284 @@ pub enum Record {
         host: String,
         ttl: u32,
     }, // 5
+    SOA {
+        domain: String,
+        m_name: String,
+        r_name: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    }, // 6
+    PTR {
+        domain: String,
+        host: String,
+        ttl: u32,
+    }, // 12
+    HINFO {
+        domain: String,
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+        ttl: u32,
+    }, // 13
     MX {
         domain: String,
         priority: u16,
         host: String,
         ttl: u32,
     }, // 15
+    TXT {
+        domain: String,
+        data: Vec<Vec<u8>>,
+        ttl: u32,
+    }, // 16
     AAAA {
         domain: String,
         addr: Ipv6Addr,
         ttl: u32,
     }, // 28
+    SRV {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: String,
+        ttl: u32,
+    }, // 33
+    NAPTR {
+        domain: String,
+        order: u16,
+        preference: u16,
+        flags: Vec<u8>,
+        services: Vec<u8>,
+        regexp: Vec<u8>,
+        replacement: String,
+        ttl: u32,
+    }, // 35
+    // the EDNS(0) pseudo-record: its class carries the sender's UDP payload
+    // size and its ttl the extended rcode, version and flags
+    OPT {
+        packet_len: u16,
+        ext_rcode: u8,
+        version: u8,
+        dnssec_ok: bool,
+        options: Vec<EdnsOption>,
+    }, // 41
+    DS {
+        domain: String,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+        ttl: u32,
+    }, // 43
+    RRSIG {
+        domain: String,
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: String,
+        signature: Vec<u8>,
+        ttl: u32,
+    }, // 46
+    // `types` lists every type at the owner, in ascending order
+    NSEC {
+        domain: String,
+        next_domain: String,
+        types: Vec<u16>,
+        ttl: u32,
+    }, // 47
+    DNSKEY {
+        domain: String,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    }, // 48
+    // the owner's first label is the base32hex hash of the name it stands
+    // for (RFC 5155)
+    NSEC3 {
+        domain: String,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed: Vec<u8>,
+        types: Vec<u16>,
+        ttl: u32,
+    }, // 50
+    CAA {
+        domain: String,
+        flags: u8,
+        tag: Vec<u8>,
+        value: Vec<u8>,
+        ttl: u32,
+    }, // 257
 }
 
 impl Record {
+    pub fn domain(&self) -> &str {
+        match self {
+            Record::UNKNOWN { domain, .. }
+            | Record::A { domain, .. }
+            | Record::NS { domain, .. }
+            | Record::CNAME { domain, .. }
+            | Record::SOA { domain, .. }
+            | Record::PTR { domain, .. }
+            | Record::HINFO { domain, .. }
+            | Record::MX { domain, .. }
+            | Record::TXT { domain, .. }
+            | Record::AAAA { domain, .. }
+            | Record::SRV { domain, .. }
+            | Record::NAPTR { domain, .. }
+            | Record::DS { domain, .. }
+            | Record::RRSIG { domain, .. }
+            | Record::NSEC { domain, .. }
+            | Record::DNSKEY { domain, .. }
+            | Record::NSEC3 { domain, .. }
+            | Record::CAA { domain, .. } => domain,
+            Record::OPT { .. } => "",
+        }
+    }
+
+    pub fn set_domain(&mut self, value: String) {
+        match self {
+            Record::UNKNOWN { domain, .. }
+            | Record::A { domain, .. }
+            | Record::NS { domain, .. }
+            | Record::CNAME { domain, .. }
+            | Record::SOA { domain, .. }
+            | Record::PTR { domain, .. }
+            | Record::HINFO { domain, .. }
+            | Record::MX { domain, .. }
+            | Record::TXT { domain, .. }
+            | Record::AAAA { domain, .. }
+            | Record::SRV { domain, .. }
+            | Record::NAPTR { domain, .. }
+            | Record::DS { domain, .. }
+            | Record::RRSIG { domain, .. }
+            | Record::NSEC { domain, .. }
+            | Record::DNSKEY { domain, .. }
+            | Record::NSEC3 { domain, .. }
+            | Record::CAA { domain, .. } => *domain = value,
+            Record::OPT { .. } => {}
+        }
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match *self {
+            Record::UNKNOWN { ttl, .. }
+            | Record::A { ttl, .. }
+            | Record::NS { ttl, .. }
+            | Record::CNAME { ttl, .. }
+            | Record::SOA { ttl, .. }
+            | Record::PTR { ttl, .. }
+            | Record::HINFO { ttl, .. }
+            | Record::MX { ttl, .. }
+            | Record::TXT { ttl, .. }
+            | Record::AAAA { ttl, .. }
+            | Record::SRV { ttl, .. }
+            | Record::NAPTR { ttl, .. }
+            | Record::DS { ttl, .. }
+            | Record::RRSIG { ttl, .. }
+            | Record::NSEC { ttl, .. }
+            | Record::DNSKEY { ttl, .. }
+            | Record::NSEC3 { ttl, .. }
+            | Record::CAA { ttl, .. } => ttl,
+            Record::OPT { .. } => 0,
+        }
+    }
+
+    pub fn set_ttl(&mut self, value: u32) {
+        match self {
+            Record::UNKNOWN { ttl, .. }
+            | Record::A { ttl, .. }
+            | Record::NS { ttl, .. }
+            | Record::CNAME { ttl, .. }
+            | Record::SOA { ttl, .. }
+            | Record::PTR { ttl, .. }
+            | Record::HINFO { ttl, .. }
+            | Record::MX { ttl, .. }
+            | Record::TXT { ttl, .. }
+            | Record::AAAA { ttl, .. }
+            | Record::SRV { ttl, .. }
+            | Record::NAPTR { ttl, .. }
+            | Record::DS { ttl, .. }
+            | Record::RRSIG { ttl, .. }
+            | Record::NSEC { ttl, .. }
+            | Record::DNSKEY { ttl, .. }
+            | Record::NSEC3 { ttl, .. }
+            | Record::CAA { ttl, .. } => *ttl = value,
+            Record::OPT { .. } => {}
+        }
+    }
+
+    pub fn qtype(&self) -> QueryType {
+        match *self {
+            Record::UNKNOWN { qtype, .. } => QueryType::from_num(qtype),
+            Record::A { .. } => QueryType::A,
+            Record::NS { .. } => QueryType::NS,
+            Record::CNAME { .. } => QueryType::CNAME,
+            Record::SOA { .. } => QueryType::SOA,
+            Record::PTR { .. } => QueryType::PTR,
+            Record::HINFO { .. } => QueryType::HINFO,
+            Record::MX { .. } => QueryType::MX,
+            Record::TXT { .. } => QueryType::TXT,
+            Record::AAAA { .. } => QueryType::AAAA,
+            Record::SRV { .. } => QueryType::SRV,
+            Record::NAPTR { .. } => QueryType::NAPTR,
+            Record::OPT { .. } => QueryType::OPT,
+            Record::DS { .. } => QueryType::DS,
+            Record::RRSIG { .. } => QueryType::RRSIG,
+            Record::NSEC { .. } => QueryType::NSEC,
+            Record::DNSKEY { .. } => QueryType::DNSKEY,
+            Record::NSEC3 { .. } => QueryType::NSEC3,
+            Record::CAA { .. } => QueryType::CAA,
+        }
+    }
+
     pub fn read(buffer: &mut BytePacketBuffer) -> Result<Record> {
+        Record::read_record(buffer, false)
+    }
+
+    // rdata parsed by type whatever the class says, for the class NONE
+    // deletions of an update, which name records of the zone's class
+    // (RFC 2136 2.5.4)
+    pub fn read_any_class(buffer: &mut BytePacketBuffer) -> Result<Record> {
+        Record::read_record(buffer, true)
+    }
+
+    fn read_record(buffer: &mut BytePacketBuffer, any_class: bool) -> Result<Record> {
         let mut domain = String::new();
         buffer.read_qname(&mut domain)?;
-        let qtype_num = buffer.read_u16()?;
-        let qtype = QueryType::from_num(qtype_num);
-        let _ = buffer.read_u16()?;
+        let mut qtype = QueryType::from_num(buffer.read_u16()?);
+        let class = buffer.read_u16()?;
+        // rdata outside class IN may be laid out differently, so it stays raw
+        if class != CLASS_IN && qtype != QueryType::OPT && !any_class {
+            qtype = QueryType::UNKNOWN(qtype.to_num());
+        }
         let ttl = buffer.read_u32()?;
-        let data_len = buffer.read_u16()?;
+        let data_len = buffer.read_u16()? as usize;
+        let start = buffer.pos();
+        if start + data_len > buffer.buf.len() {
+            return Err(Error::EndOfBuffer);
+        }
+        let record = Record::read_rdata(buffer, domain, qtype, class, ttl, start + data_len)?;
+        if buffer.pos() != start + data_len {
+            return Err(Error::RdataLength { qtype: qtype.to_num(), declared: data_len, used: buffer.pos() - start });
+        }
+        Ok(record)
+    }
+
+    // the rdata up to `end`, which a record may not stop short of or run past
+    fn read_rdata(buffer: &mut BytePacketBuffer, domain: String, qtype: QueryType, class: u16, ttl: u32, end: usize) -> Result<Record> {
         match qtype {
             QueryType::A => {
                 let raw_addr = buffer.read_u32()?;
@@ -55,7 +320,7 @@ impl Record {
                     ((raw_addr >> 24) & 0xFF) as u8,
                     ((raw_addr >> 16) & 0xFF) as u8,
                     ((raw_addr >> 8) & 0xFF) as u8,
-                    ((raw_addr >> 0) & 0xFF) as u8,
+                    (raw_addr & 0xFF) as u8,
                 );
                 Ok(Record::A { domain, addr, ttl })
             }
@@ -66,13 +331,13 @@ impl Record {
                 let raw_addr4 = buffer.read_u32()?;
                 let addr = Ipv6Addr::new(
                     ((raw_addr1 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr1 >> 0) & 0xFFFF) as u16,
+                    (raw_addr1 & 0xFFFF) as u16,
                     ((raw_addr2 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr2 >> 0) & 0xFFFF) as u16,
+                    (raw_addr2 & 0xFFFF) as u16,
                     ((raw_addr3 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr3 >> 0) & 0xFFFF) as u16,
+                    (raw_addr3 & 0xFFFF) as u16,
                     ((raw_addr4 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr4 >> 0) & 0xFFFF) as u16,
+                    (raw_addr4 & 0xFFFF) as u16,
                 );
                 Ok(Record::AAAA { domain, addr, ttl })
             }
@@ -105,12 +370,180 @@ impl Record {
                     ttl,
                 })
             }
-            QueryType::UNKNOWN(_) => {
-                buffer.step(data_len as usize)?;
+            QueryType::SOA => {
+                let mut m_name = String::new();
+                buffer.read_qname(&mut m_name)?;
+                let mut r_name = String::new();
+                buffer.read_qname(&mut r_name)?;
+                Ok(Record::SOA {
+                    domain,
+                    m_name,
+                    r_name,
+                    serial: buffer.read_u32()?,
+                    refresh: buffer.read_u32()?,
+                    retry: buffer.read_u32()?,
+                    expire: buffer.read_u32()?,
+                    minimum: buffer.read_u32()?,
+                    ttl,
+                })
+            }
+            QueryType::TXT => {
+                let mut data = Vec::new();
+                while buffer.pos() < end {
+                    data.push(read_character_string(buffer)?);
+                }
+                Ok(Record::TXT { domain, data, ttl })
+            }
+            QueryType::OPT => {
+                let mut options = Vec::new();
+                while buffer.pos() < end {
+                    options.push(EdnsOption::read(buffer)?);
+                }
+                Ok(Record::OPT {
+                    packet_len: class,
+                    ext_rcode: (ttl >> 24) as u8,
+                    version: (ttl >> 16) as u8,
+                    dnssec_ok: ttl & 0x8000 != 0,
+                    options,
+                })
+            }
+            QueryType::PTR => {
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(Record::PTR { domain, host, ttl })
+            }
+            QueryType::HINFO => {
+                let cpu = read_character_string(buffer)?;
+                let os = read_character_string(buffer)?;
+                Ok(Record::HINFO { domain, cpu, os, ttl })
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(Record::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    host,
+                    ttl,
+                })
+            }
+            QueryType::NAPTR => {
+                let order = buffer.read_u16()?;
+                let preference = buffer.read_u16()?;
+                let flags = read_character_string(buffer)?;
+                let services = read_character_string(buffer)?;
+                let regexp = read_character_string(buffer)?;
+                let mut replacement = String::new();
+                buffer.read_qname(&mut replacement)?;
+                Ok(Record::NAPTR {
+                    domain,
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                    ttl,
+                })
+            }
+            QueryType::DS => {
+                Ok(Record::DS {
+                    domain,
+                    key_tag: buffer.read_u16()?,
+                    algorithm: buffer.read()?,
+                    digest_type: buffer.read()?,
+                    digest: read_bytes(buffer, end)?,
+                    ttl,
+                })
+            }
+            QueryType::RRSIG => {
+                let type_covered = buffer.read_u16()?;
+                let algorithm = buffer.read()?;
+                let labels = buffer.read()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                let mut signer_name = String::new();
+                buffer.read_qname(&mut signer_name)?;
+                Ok(Record::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    signature: read_bytes(buffer, end)?,
+                    ttl,
+                })
+            }
+            QueryType::NSEC => {
+                let mut next_domain = String::new();
+                buffer.read_qname(&mut next_domain)?;
+                Ok(Record::NSEC {
+                    domain,
+                    next_domain,
+                    types: read_type_bitmap(buffer, end)?,
+                    ttl,
+                })
+            }
+            QueryType::NSEC3 => {
+                let hash_algorithm = buffer.read()?;
+                let flags = buffer.read()?;
+                let iterations = buffer.read_u16()?;
+                let salt_len = buffer.read()? as usize;
+                let salt = buffer.get_range(buffer.pos(), salt_len)?.to_vec();
+                buffer.step(salt_len)?;
+                let hash_len = buffer.read()? as usize;
+                let next_hashed = buffer.get_range(buffer.pos(), hash_len)?.to_vec();
+                buffer.step(hash_len)?;
+                Ok(Record::NSEC3 {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed,
+                    types: read_type_bitmap(buffer, end)?,
+                    ttl,
+                })
+            }
+            QueryType::DNSKEY => {
+                Ok(Record::DNSKEY {
+                    domain,
+                    flags: buffer.read_u16()?,
+                    protocol: buffer.read()?,
+                    algorithm: buffer.read()?,
+                    public_key: read_bytes(buffer, end)?,
+                    ttl,
+                })
+            }
+            QueryType::CAA => {
+                let flags = buffer.read()?;
+                let tag = read_character_string(buffer)?;
+                let value = read_bytes(buffer, end)?;
+                Ok(Record::CAA {
+                    domain,
+                    flags,
+                    tag,
+                    value,
+                    ttl,
+                })
+            }
+            QueryType::UNKNOWN(qtype_num) => {
                 Ok(Record::UNKNOWN {
                     domain,
                     qtype: qtype_num,
-                    data_len,
+                    class,
+                    data: read_bytes(buffer, end)?,
                     ttl,
                 })
             }
@@ -183,6 +616,59 @@ impl Record {
                 let size = buffer.pos() - (pos + 2);
                 buffer.set_u16(pos, size as u16)?;
             }
+            Record::SOA {
+                ref domain,
+                ref m_name,
+                ref r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_qname(m_name)?;
+                buffer.write_qname(r_name)?;
+                buffer.write_u32(serial)?;
+                buffer.write_u32(refresh)?;
+                buffer.write_u32(retry)?;
+                buffer.write_u32(expire)?;
+                buffer.write_u32(minimum)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::TXT {
+                ref domain,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                for text in data {
+                    // a character-string holds at most 255 bytes
+                    for chunk in text.chunks(255) {
+                        buffer.write_u8(chunk.len() as u8)?;
+                        for b in chunk {
+                            buffer.write_u8(*b)?;
+                        }
+                    }
+                    if text.is_empty() {
+                        buffer.write_u8(0)?;
+                    }
+                }
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
             Record::AAAA {
                 ref domain,
                 ref addr,
@@ -197,10 +683,476 @@ impl Record {
                     buffer.write_u16(*octet)?;
                 }
             }
-            Record::UNKNOWN { .. } => {
-                println!("Skipping record: {:?}", self);
+            Record::OPT {
+                packet_len,
+                ext_rcode,
+                version,
+                dnssec_ok,
+                ref options,
+            } => {
+                buffer.write_qname("")?;
+                buffer.write_u16(QueryType::OPT.to_num())?;
+                buffer.write_u16(packet_len)?;
+                buffer.write_u32(((ext_rcode as u32) << 24) | ((version as u32) << 16) | ((dnssec_ok as u32) << 15))?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                for option in options {
+                    option.write(buffer)?;
+                }
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::PTR {
+                ref domain,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::PTR.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_qname(host)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::HINFO {
+                ref domain,
+                ref cpu,
+                ref os,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::HINFO.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                write_character_string(buffer, cpu)?;
+                write_character_string(buffer, os)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            // names in the rdata of the types below are never compressed
+            // (RFC 3597 4)
+            Record::SRV {
+                ref domain,
+                priority,
+                weight,
+                port,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SRV.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_u16(port)?;
+                buffer.write_plain_qname(host)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::NAPTR {
+                ref domain,
+                order,
+                preference,
+                ref flags,
+                ref services,
+                ref regexp,
+                ref replacement,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NAPTR.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_u16(order)?;
+                buffer.write_u16(preference)?;
+                write_character_string(buffer, flags)?;
+                write_character_string(buffer, services)?;
+                write_character_string(buffer, regexp)?;
+                buffer.write_plain_qname(replacement)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::DS {
+                ref domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                ref digest,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DS.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_u16(key_tag)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(digest_type)?;
+                write_bytes(buffer, digest)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::RRSIG {
+                ref domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer_name,
+                ref signature,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::RRSIG.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_u16(type_covered)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(labels)?;
+                buffer.write_u32(original_ttl)?;
+                buffer.write_u32(expiration)?;
+                buffer.write_u32(inception)?;
+                buffer.write_u16(key_tag)?;
+                buffer.write_plain_qname(signer_name)?;
+                write_bytes(buffer, signature)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::NSEC {
+                ref domain,
+                ref next_domain,
+                ref types,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_plain_qname(next_domain)?;
+                write_type_bitmap(buffer, types)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::NSEC3 {
+                ref domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                ref salt,
+                ref next_hashed,
+                ref types,
+                ttl,
+            } => {
+                if salt.len() > 255 || next_hashed.len() > 255 {
+                    return Err("NSEC3 salt and hash take at most 255 bytes".into());
+                }
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC3.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_u8(hash_algorithm)?;
+                buffer.write_u8(flags)?;
+                buffer.write_u16(iterations)?;
+                buffer.write_u8(salt.len() as u8)?;
+                write_bytes(buffer, salt)?;
+                buffer.write_u8(next_hashed.len() as u8)?;
+                write_bytes(buffer, next_hashed)?;
+                write_type_bitmap(buffer, types)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::DNSKEY {
+                ref domain,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNSKEY.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_u16(flags)?;
+                buffer.write_u8(protocol)?;
+                buffer.write_u8(algorithm)?;
+                write_bytes(buffer, public_key)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::CAA {
+                ref domain,
+                flags,
+                ref tag,
+                ref value,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::CAA.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+                buffer.write_u8(flags)?;
+                write_character_string(buffer, tag)?;
+                write_bytes(buffer, value)?;
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            Record::UNKNOWN {
+                ref domain,
+                qtype,
+                class,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(qtype)?;
+                buffer.write_u16(class)?;
+                buffer.write_u32(ttl)?;
+                if data.len() > u16::MAX as usize {
+                    return Err(format!("rdata of {} bytes doesn't fit a record", data.len()).into());
+                }
+                buffer.write_u16(data.len() as u16)?;
+                write_bytes(buffer, data)?;
             }
         }
         Ok(buffer.pos() - start_pos)
     }
+}
+
+// presentation format, one record to a line the way dig prints them:
+// owner, ttl, class, type and rdata, separated by tabs
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Record::OPT { packet_len, version, dnssec_ok, ref options, .. } = *self {
+            write!(f, "; EDNS: version: {}, flags:{}; udp: {}", version, if dnssec_ok { " do" } else { "" }, packet_len)?;
+            for option in options {
+                match option {
+                    EdnsOption::ClientSubnet { source_prefix, scope_prefix, addr } => write!(f, "\n; CLIENT-SUBNET: {}/{}/{}", addr, source_prefix, scope_prefix)?,
+                    EdnsOption::Cookie { client, server } => write!(f, "\n; COOKIE: {}{}", hex(client), hex(server))?,
+                    EdnsOption::Unknown { code, data } => write!(f, "\n; OPT={}: {}", code, hex(data))?,
+                }
+            }
+            return Ok(());
+        }
+        let class = match *self {
+            Record::UNKNOWN { class, .. } => class,
+            _ => CLASS_IN,
+        };
+        write!(f, "{}\t{}\t{}\t{}\t", fqdn(self.domain()), self.ttl(), class_name(class), self.qtype())?;
+        match *self {
+            Record::A { addr, .. } => write!(f, "{}", addr),
+            Record::AAAA { addr, .. } => write!(f, "{}", addr),
+            Record::NS { ref host, .. } | Record::CNAME { ref host, .. } | Record::PTR { ref host, .. } => write!(f, "{}", fqdn(host)),
+            Record::SOA { ref m_name, ref r_name, serial, refresh, retry, expire, minimum, .. } => {
+                write!(f, "{} {} {} {} {} {} {}", fqdn(m_name), fqdn(r_name), serial, refresh, retry, expire, minimum)
+            }
+            Record::HINFO { ref cpu, ref os, .. } => write!(f, "{} {}", quoted(cpu), quoted(os)),
+            Record::MX { priority, ref host, .. } => write!(f, "{} {}", priority, fqdn(host)),
+            Record::TXT { ref data, .. } => write!(f, "{}", data.iter().map(|text| quoted(text)).collect::<Vec<String>>().join(" ")),
+            Record::SRV { priority, weight, port, ref host, .. } => write!(f, "{} {} {} {}", priority, weight, port, fqdn(host)),
+            Record::NAPTR { order, preference, ref flags, ref services, ref regexp, ref replacement, .. } => {
+                write!(f, "{} {} {} {} {} {}", order, preference, quoted(flags), quoted(services), quoted(regexp), fqdn(replacement))
+            }
+            Record::DS { key_tag, algorithm, digest_type, ref digest, .. } => write!(f, "{} {} {} {}", key_tag, algorithm, digest_type, hex(digest)),
+            Record::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer_name,
+                ref signature,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                QueryType::from_num(type_covered), algorithm, labels, original_ttl,
+                timestamp(expiration), timestamp(inception), key_tag, fqdn(signer_name), base64(signature)
+            ),
+            Record::NSEC { ref next_domain, ref types, .. } => write!(f, "{}{}", fqdn(next_domain), type_list(types)),
+            Record::DNSKEY { flags, protocol, algorithm, ref public_key, .. } => write!(f, "{} {} {} {}", flags, protocol, algorithm, base64(public_key)),
+            Record::NSEC3 { hash_algorithm, flags, iterations, ref salt, ref next_hashed, ref types, .. } => {
+                let salt = if salt.is_empty() { "-".to_string() } else { hex(salt) };
+                write!(f, "{} {} {} {} {}{}", hash_algorithm, flags, iterations, salt, base32hex(next_hashed).to_uppercase(), type_list(types))
+            }
+            Record::CAA { flags, ref tag, ref value, .. } => write!(f, "{} {} {}", flags, escaped(tag), quoted(value)),
+            Record::UNKNOWN { ref data, .. } => write!(f, "\\# {} {}", data.len(), hex(data)),
+            Record::OPT { .. } => unreachable!(),
+        }
+    }
+}
+
+fn fqdn(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+// the mnemonic of a class, or CLASSnnn for one without (RFC 3597 5)
+pub fn class_name(class: u16) -> String {
+    match class {
+        CLASS_IN => "IN".to_string(),
+        3 => "CH".to_string(),
+        4 => "HS".to_string(),
+        class => format!("CLASS{}", class),
+    }
+}
+
+// a character string in double quotes (RFC 1035 5.1)
+fn quoted(text: &[u8]) -> String {
+    format!("\"{}\"", escaped(text))
+}
+
+// quotes and backslashes escaped and anything unprintable as \DDD
+fn escaped(text: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in text {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            0x20..=0x7E => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03}", b)),
+        }
+    }
+    out
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn type_list(types: &[u16]) -> String {
+    types.iter().map(|qtype| format!(" {}", QueryType::from_num(*qtype))).collect()
+}
+
+// signature times as YYYYMMDDHHmmSS in UTC (RFC 4034 3.2)
+fn timestamp(secs: u32) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    // days since the epoch to a civil date, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+// a length byte followed by up to 255 bytes of text (RFC 1035 3.3), which
+// needn't be UTF-8
+fn read_character_string(buffer: &mut BytePacketBuffer) -> Result<Vec<u8>> {
+    let len = buffer.read()? as usize;
+    let text = buffer.get_range(buffer.pos(), len)?.to_vec();
+    buffer.step(len)?;
+    Ok(text)
+}
+
+fn write_character_string(buffer: &mut BytePacketBuffer, text: &[u8]) -> Result<()> {
+    if text.len() > 255 {
+        return Err(format!("character string of {} bytes exceeds 255", text.len()).into());
+    }
+    buffer.write_u8(text.len() as u8)?;
+    write_bytes(buffer, text)
+}
+
+// whatever is left of the rdata up to `end`
+fn read_bytes(buffer: &mut BytePacketBuffer, end: usize) -> Result<Vec<u8>> {
+    let len = end.checked_sub(buffer.pos()).ok_or_else(|| Error::BadRdata("rdata is shorter than its fixed fields".to_string()))?;
+    let data = buffer.get_range(buffer.pos(), len)?.to_vec();
+    buffer.step(len)?;
+    Ok(data)
+}
+
+fn write_bytes(buffer: &mut BytePacketBuffer, data: &[u8]) -> Result<()> {
+    for b in data {
+        buffer.write_u8(*b)?;
+    }
+    Ok(())
+}
+
+// the types present at a name, as windows of 256 types each with a bitmap
+// of up to 32 bytes (RFC 4034 4.1.2)
+fn read_type_bitmap(buffer: &mut BytePacketBuffer, end: usize) -> Result<Vec<u16>> {
+    let mut types = Vec::new();
+    while buffer.pos() < end {
+        let window = buffer.read()? as u16;
+        let len = buffer.read()? as usize;
+        if len == 0 || len > 32 {
+            return Err(Error::BadRdata(format!("type bitmap window of {} bytes", len)));
+        }
+        let bits = buffer.get_range(buffer.pos(), len)?.to_vec();
+        buffer.step(len)?;
+        for (i, byte) in bits.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push((window << 8) | (i * 8 + bit) as u16);
+                }
+            }
+        }
+    }
+    Ok(types)
+}
+
+fn write_type_bitmap(buffer: &mut BytePacketBuffer, types: &[u16]) -> Result<()> {
+    let mut sorted = types.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    for window in sorted.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let mut bits = [0u8; 32];
+        for qtype in window {
+            let low = (qtype & 0xFF) as usize;
+            bits[low / 8] |= 0x80 >> (low % 8);
+        }
+        let len = (window[window.len() - 1] & 0xFF) as usize / 8 + 1;
+        buffer.write_u8((window[0] >> 8) as u8)?;
+        buffer.write_u8(len as u8)?;
+        write_bytes(buffer, &bits[..len])?;
+    }
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,215 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::utils::{Result, ResultCode};
+use crate::record::Record;
+use crate::query_type::QueryType;
+use crate::packet::Packet;
+use crate::question::Question;
+use crate::transport;
+use crate::zone;
+
+const MAX_CNAME_CHAIN: usize = 8;
+
+// a server's reply, sorted by what the resolver does next
+enum Step {
+    Done(Packet),
+    Referral(String, Packet),
+    Lame,
+}
+
+// iterative resolution from the root hints down, the way a stub resolver
+// expects a recursive server to do it
+pub struct Resolver {
+    pub roots: Vec<IpAddr>,
+    // port every nameserver is asked on, only ever changed by tests
+    pub port: u16,
+    // how deep nameserver names may be resolved to find a nameserver
+    pub max_depth: usize,
+    // queries one resolution may send in total
+    pub max_queries: usize,
+    pub timeout: Duration,
+    // rounds over a zone's servers before giving up on it
+    pub attempts: usize,
+    // ask for DNSSEC records along with the answers
+    pub dnssec_ok: bool,
+}
+
+impl Resolver {
+    pub fn new(roots: Vec<IpAddr>) -> Resolver {
+        Resolver {
+            roots,
+            port: 53,
+            max_depth: 4,
+            max_queries: 64,
+            timeout: Duration::from_secs(2),
+            attempts: 2,
+            dnssec_ok: false,
+        }
+    }
+
+    // the root servers' addresses out of a named.root style hints file
+    pub fn from_hints(path: &Path) -> Result<Resolver> {
+        let roots: Vec<IpAddr> = zone::parse(path)?
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::A { addr, .. } => Some(IpAddr::V4(addr)),
+                Record::AAAA { addr, .. } => Some(IpAddr::V6(addr)),
+                _ => None,
+            })
+            .collect();
+        if roots.is_empty() {
+            return Err(format!("{}: no root server addresses", path.display()).into());
+        }
+        Ok(Resolver::new(roots))
+    }
+
+    pub fn resolve(&self, qname: &str, qtype: QueryType) -> Result<Packet> {
+        let mut budget = self.max_queries;
+        self.resolve_at(&qname.to_lowercase(), qtype, 0, &mut budget)
+    }
+
+    fn resolve_at(&self, qname: &str, qtype: QueryType, depth: usize, budget: &mut usize) -> Result<Packet> {
+        if depth > self.max_depth {
+            return Err(format!("resolving {} nests too deep", qname).into());
+        }
+        let mut chain: Vec<Record> = Vec::new();
+        let mut name = qname.to_string();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let mut response = self.iterate(&name, qtype, depth, budget)?;
+            // follow the chain as far as this answer takes it
+            let mut target = name.clone();
+            if qtype != QueryType::CNAME {
+                for _ in 0..MAX_CNAME_CHAIN {
+                    match cname_of(&response.answers, &target) {
+                        Some(next) => target = next,
+                        None => break,
+                    }
+                }
+            }
+            let answered = response.answers.iter().any(|record| record.domain() == target && record.qtype() == qtype);
+            if target == name || answered || response.header.rescode != ResultCode::NOERROR {
+                chain.append(&mut response.answers);
+                response.answers = chain;
+                return Ok(response);
+            }
+            chain.append(&mut response.answers);
+            name = target;
+        }
+        Err(format!("CNAME chain of {} is too long", qname).into())
+    }
+
+    // walks down from the roots until some server answers for `qname`
+    fn iterate(&self, qname: &str, qtype: QueryType, depth: usize, budget: &mut usize) -> Result<Packet> {
+        let mut zone = String::new();
+        let mut servers = self.roots.clone();
+        loop {
+            let mut referral = None;
+            let mut failure: Option<String> = None;
+            for server in servers.iter().cycle().take(servers.len() * self.attempts) {
+                if *budget == 0 {
+                    return Err(format!("query budget exhausted resolving {}", qname).into());
+                }
+                *budget -= 1;
+                let response = match self.query(qname, qtype, *server) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        failure = Some(format!("{}: {}", server, e));
+                        continue;
+                    }
+                };
+                match classify(response, qname, &zone) {
+                    Step::Done(response) => return Ok(response),
+                    Step::Referral(cut, response) => {
+                        referral = Some((cut, response));
+                        break;
+                    }
+                    Step::Lame => failure = Some(format!("{} sent a lame answer for {}", server, qname)),
+                }
+            }
+            let (cut, response) = match referral {
+                Some(referral) => referral,
+                None => return Err(failure.unwrap_or_else(|| format!("no nameserver for `{}`", zone)).into()),
+            };
+            servers = self.nameservers(&response, &cut, &zone, depth, budget);
+            if servers.is_empty() {
+                return Err(format!("no reachable nameserver for `{}`", cut).into());
+            }
+            zone = cut;
+        }
+    }
+
+    // addresses of the servers a referral points at; glue is only taken from
+    // inside the zone of the server that sent it, everything else is looked
+    // up from the roots
+    fn nameservers(&self, response: &Packet, cut: &str, zone: &str, depth: usize, budget: &mut usize) -> Vec<IpAddr> {
+        let hosts: Vec<&str> = response.authorities.iter()
+            .filter_map(|record| match record {
+                Record::NS { domain, host, .. } if domain == cut => Some(host.as_str()),
+                _ => None,
+            })
+            .collect();
+        let mut servers: Vec<IpAddr> = response.resources.iter()
+            .filter(|record| hosts.contains(&record.domain()) && zone::in_zone(record.domain(), zone))
+            .filter_map(|record| match *record {
+                Record::A { addr, .. } => Some(IpAddr::V4(addr)),
+                Record::AAAA { addr, .. } => Some(IpAddr::V6(addr)),
+                _ => None,
+            })
+            .collect();
+        if !servers.is_empty() {
+            return servers;
+        }
+        for host in hosts {
+            // a server named inside the zone it serves needs glue, without it
+            // the lookup would just come back here
+            if zone::in_zone(host, cut) {
+                continue;
+            }
+            if let Ok(found) = self.resolve_at(host, QueryType::A, depth + 1, budget) {
+                servers.extend(found.answers.iter().filter_map(|record| match *record {
+                    Record::A { addr, .. } => Some(IpAddr::V4(addr)),
+                    _ => None,
+                }));
+            }
+            if !servers.is_empty() {
+                break;
+            }
+        }
+        servers
+    }
+
+    fn query(&self, qname: &str, qtype: QueryType, server: IpAddr) -> Result<Packet> {
+        let question = Question::new(qname.to_string(), qtype);
+        transport::exchange(SocketAddr::new(server, self.port), &question, false, self.dnssec_ok, self.timeout)
+    }
+}
+
+fn cname_of(records: &[Record], name: &str) -> Option<String> {
+    records.iter().find_map(|record| match record {
+        Record::CNAME { domain, host, .. } if domain == name => Some(host.clone()),
+        _ => None,
+    })
+}
+
+// a referral has to move strictly closer to `qname` than the zone the
+// server was asked as, or the walk could go in circles
+fn classify(response: Packet, qname: &str, zone: &str) -> Step {
+    match response.header.rescode {
+        ResultCode::NOERROR | ResultCode::NXDOMAIN => {}
+        _ => return Step::Lame,
+    }
+    if !response.answers.is_empty() || response.header.rescode == ResultCode::NXDOMAIN {
+        return Step::Done(response);
+    }
+    let cut = response.authorities.iter().find_map(|record| match record {
+        Record::NS { domain, .. } => Some(domain.clone()),
+        _ => None,
+    });
+    match cut {
+        Some(cut) if cut != zone && zone::in_zone(&cut, zone) && zone::in_zone(qname, &cut) => Step::Referral(cut, response),
+        Some(_) if !response.header.authoritative_answer => Step::Lame,
+        _ => Step::Done(response),
+    }
+}
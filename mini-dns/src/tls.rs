@@ -0,0 +1,31 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::ServerConfig;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+
+use crate::utils::Result;
+
+// what clients ask for in ALPN: DNS over TLS (RFC 7858) and DNS over HTTPS,
+// which we speak over HTTP/1.1
+pub const DOT_ALPN: &[u8] = b"dot";
+pub const DOH_ALPN: &[u8] = b"http/1.1";
+
+// a TLS server config from a PEM certificate chain and its private key
+pub fn server_config(cert: &Path, key: &Path, alpn: &[u8]) -> Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<CertificateDer>, _>>())
+        .map_err(|e| format!("{}: {}", cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates", cert.display()).into());
+    }
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| format!("{}: {}", key.display(), e))?;
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![alpn.to_vec()];
+    Ok(Arc::new(config))
+}
@@ -0,0 +1,209 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::utils::{Result, ResultCode};
+use crate::header::Header;
+use crate::record::Record;
+use crate::packet::Packet;
+use crate::question::Question;
+use crate::byte_bucket_buffer::{self, BytePacketBuffer};
+use crate::edns;
+
+// what a client without EDNS(0) can take over UDP
+pub const MAX_UDP_SIZE: usize = 512;
+pub const MAX_TCP_SIZE: usize = byte_bucket_buffer::MAX_SIZE;
+
+// sends one question to `server` and waits up to `timeout` for its reply,
+// asking again over TCP when the UDP reply comes back truncated; with
+// `dnssec_ok` the reply carries signatures and, since we check them
+// ourselves, skips the server's own validation
+pub fn exchange(server: SocketAddr, question: &Question, recursion_desired: bool, dnssec_ok: bool, timeout: Duration) -> Result<Packet> {
+    let mut request = Packet::new();
+    request.header.id = rand::random();
+    request.header.recursion_desired = recursion_desired;
+    request.header.checking_disabled = dnssec_ok;
+    request.questions.push(question.clone());
+    request.resources.push(opt(edns::UDP_PAYLOAD_SIZE, dnssec_ok));
+    let response = send(server, &mut request, timeout)?;
+    // servers that predate EDNS(0) reject the OPT record instead of
+    // ignoring it (RFC 6891 6.2.2)
+    if matches!(response.header.rescode, ResultCode::FORMERR | ResultCode::NOTIMP) && response.edns().is_none() {
+        request.header.id = rand::random();
+        request.resources.clear();
+        return send(server, &mut request, timeout);
+    }
+    Ok(response)
+}
+
+pub fn opt(packet_len: u16, dnssec_ok: bool) -> Record {
+    Record::OPT {
+        packet_len,
+        ext_rcode: 0,
+        version: 0,
+        dnssec_ok,
+        options: Vec::new(),
+    }
+}
+
+// sends a query the caller built as it stands, over TCP from the start when
+// `tcp` is set
+pub fn send_request(server: SocketAddr, request: &mut Packet, tcp: bool, timeout: Duration) -> Result<Packet> {
+    if tcp {
+        return exchange_tcp(server, request, timeout);
+    }
+    send(server, request, timeout)
+}
+
+fn send(server: SocketAddr, request: &mut Packet, timeout: Duration) -> Result<Packet> {
+    match exchange_udp(server, request, timeout)? {
+        Some(response) => Ok(response),
+        None => exchange_tcp(server, request, timeout),
+    }
+}
+
+// every query comes from a fresh ephemeral port, and anything that doesn't
+// match id, sender and question is dropped instead of taken as the answer;
+// None means the server truncated its reply
+fn exchange_udp(server: SocketAddr, request: &mut Packet, timeout: Duration) -> Result<Option<Packet>> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    let req_buffer = encode(request, MAX_UDP_SIZE)?;
+    socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
+    // room for as big a reply as the query says it takes
+    let size = match request.edns() {
+        Some(Record::OPT { packet_len, .. }) => (*packet_len).max(edns::UDP_PAYLOAD_SIZE) as usize,
+        _ => edns::UDP_PAYLOAD_SIZE as usize,
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("no reply from {}", server)).into());
+        }
+        socket.set_read_timeout(Some(left))?;
+        let mut res_buffer = BytePacketBuffer::with_size(size);
+        let (len, src) = match socket.recv_from(&mut res_buffer.buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if src != server {
+            continue;
+        }
+        res_buffer.buf.truncate(len);
+        // a truncated reply may not even parse past its header
+        let mut header = Header::new();
+        if header.read(&mut res_buffer).is_err() || header.id != request.header.id || !header.response {
+            continue;
+        }
+        if header.truncated_message {
+            return Ok(None);
+        }
+        res_buffer.seek(0)?;
+        let response = match Packet::from_buffer(&mut res_buffer) {
+            Ok(response) => response,
+            Err(_) => continue,
+        };
+        if same_question(&response, &request.questions[0]) {
+            return Ok(Some(response));
+        }
+    }
+}
+
+fn exchange_tcp(server: SocketAddr, request: &mut Packet, timeout: Duration) -> Result<Packet> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write_message(&mut stream, &encode(request, MAX_TCP_SIZE)?)?;
+    let mut res_buffer = read_message(&mut stream)?.ok_or(format!("{} closed the connection", server))?;
+    let response = Packet::from_buffer(&mut res_buffer)?;
+    if response.header.id != request.header.id || !same_question(&response, &request.questions[0]) {
+        return Err(format!("reply from {} doesn't match the query", server).into());
+    }
+    Ok(response)
+}
+
+fn same_question(response: &Packet, question: &Question) -> bool {
+    match response.questions.as_slice() {
+        [answered] => answered.qtype == question.qtype && answered.class == question.class && answered.name.eq_ignore_ascii_case(&question.name),
+        _ => false,
+    }
+}
+
+// the wire form of `packet` in at most `limit` bytes; a response that doesn't
+// fit goes out with just its question and TC set, so the client asks again
+// over TCP
+pub fn encode(packet: &mut Packet, limit: usize) -> Result<BytePacketBuffer> {
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer)?;
+    if buffer.pos() <= limit {
+        return Ok(buffer);
+    }
+    if !packet.header.response {
+        return Err(format!("query of {} bytes is over the {} byte limit", buffer.pos(), limit).into());
+    }
+    packet.header.truncated_message = true;
+    packet.answers.clear();
+    packet.authorities.clear();
+    packet.resources.retain(|rec| matches!(rec, Record::OPT { .. }));
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer)?;
+    Ok(buffer)
+}
+
+// TCP messages carry a two byte length prefix (RFC 1035 4.2.2); None is a
+// connection closed between messages
+pub fn read_message<R: Read>(stream: &mut R) -> Result<Option<BytePacketBuffer>> {
+    let mut len = [0; 2];
+    match stream.read_exact(&mut len) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut buffer = BytePacketBuffer::with_size(u16::from_be_bytes(len) as usize);
+    stream.read_exact(&mut buffer.buf)?;
+    Ok(Some(buffer))
+}
+
+pub fn write_message<W: Write>(stream: &mut W, buffer: &BytePacketBuffer) -> Result<()> {
+    let data = &buffer.buf[0..buffer.pos()];
+    let mut framed = Vec::with_capacity(data.len() + 2);
+    framed.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    framed.extend_from_slice(data);
+    stream.write_all(&framed)?;
+    stream.flush()?;
+    Ok(())
+}
+
+// a client may send any number of queries down one connection, each answered
+// in turn until it hangs up
+pub fn serve_messages<S: Read + Write>(stream: S, mut answer: impl FnMut(Packet) -> Packet) -> Result<()> {
+    serve_raw(stream, |req_buffer| reply_to(req_buffer, &mut answer))
+}
+
+// the same for callers that need each message as it came off the wire;
+// None sends nothing back
+pub fn serve_raw<S: Read + Write>(mut stream: S, mut answer: impl FnMut(&mut BytePacketBuffer) -> Result<Option<BytePacketBuffer>>) -> Result<()> {
+    while let Some(mut req_buffer) = read_message(&mut stream)? {
+        if let Some(res_buffer) = answer(&mut req_buffer)? {
+            write_message(&mut stream, &res_buffer)?;
+        }
+    }
+    Ok(())
+}
+
+// the reply to one message off a stream: none to responses, FORMERR to
+// anything that doesn't parse
+pub fn reply_to(req_buffer: &mut BytePacketBuffer, answer: impl FnOnce(Packet) -> Packet) -> Result<Option<BytePacketBuffer>> {
+    let mut response = match Packet::from_buffer(req_buffer) {
+        Ok(request) if request.header.response => return Ok(None),
+        Ok(request) => answer(request),
+        Err(e) => Packet::format_error(req_buffer).ok_or(e)?,
+    };
+    Ok(Some(encode(&mut response, MAX_TCP_SIZE)?))
+}
@@ -0,0 +1,250 @@
+use ring::hmac;
+
+use crate::utils::{Error, Result};
+use crate::byte_bucket_buffer::BytePacketBuffer;
+use crate::zone;
+
+// transaction signatures (RFC 8945): an HMAC over the message keyed with a
+// secret both ends share, carried in a TSIG record at the very end
+pub const TYPE_TSIG: u16 = 250;
+const CLASS_ANY: u16 = 255;
+// what a signer allows between its clock and ours
+pub const FUDGE: u16 = 300;
+
+// errors carried in the record itself, next to a NOTAUTH rcode
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+
+pub struct Key {
+    // both are lowercase names without the trailing dot
+    pub name: String,
+    pub algorithm: String,
+    secret: hmac::Key,
+}
+
+impl Key {
+    pub fn new(name: &str, algorithm: &str, secret: &[u8]) -> Result<Key> {
+        let algorithm = algorithm.trim_end_matches('.').to_lowercase();
+        let hmac = match algorithm.as_str() {
+            "hmac-sha1" => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            "hmac-sha256" => hmac::HMAC_SHA256,
+            "hmac-sha384" => hmac::HMAC_SHA384,
+            "hmac-sha512" => hmac::HMAC_SHA512,
+            _ => return Err(format!("unsupported TSIG algorithm `{}`", algorithm).into()),
+        };
+        Ok(Key {
+            name: name.trim_end_matches('.').to_lowercase(),
+            algorithm,
+            secret: hmac::Key::new(hmac, secret),
+        })
+    }
+
+    // `[algorithm:]name:secret` with the secret in base64, the way dig and
+    // nsupdate take `-y`; the algorithm defaults to hmac-sha256
+    pub fn parse(text: &str) -> Result<Key> {
+        let parts: Vec<&str> = text.split(':').collect();
+        let (algorithm, name, secret) = match parts.as_slice() {
+            [name, secret] => ("hmac-sha256", *name, *secret),
+            [algorithm, name, secret] => (*algorithm, *name, *secret),
+            _ => return Err(format!("TSIG key `{}` isn't [algorithm:]name:secret", text).into()),
+        };
+        Key::new(name, algorithm, &zone::base64(secret)?)
+    }
+
+    fn mac_len(&self) -> usize {
+        self.secret.algorithm().digest_algorithm().output_len()
+    }
+}
+
+// the rdata of a TSIG record, along with its owner, the key's name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tsig {
+    pub key_name: String,
+    pub algorithm: String,
+    // seconds since the epoch, 48 bits on the wire
+    pub time_signed: u64,
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    pub original_id: u16,
+    pub error: u16,
+    pub other: Vec<u8>,
+}
+
+impl Tsig {
+    pub fn new(key: &Key, original_id: u16, time_signed: u64) -> Tsig {
+        Tsig {
+            key_name: key.name.clone(),
+            algorithm: key.algorithm.clone(),
+            time_signed,
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id,
+            error: 0,
+            other: Vec::new(),
+        }
+    }
+
+    fn read(buffer: &mut BytePacketBuffer) -> Result<Tsig> {
+        let mut key_name = String::new();
+        buffer.read_qname(&mut key_name)?;
+        buffer.step(8)?;
+        let len = buffer.read_u16()? as usize;
+        let start = buffer.pos();
+        let mut algorithm = String::new();
+        buffer.read_qname(&mut algorithm)?;
+        let time_signed = (buffer.read_u16()? as u64) << 32 | buffer.read_u32()? as u64;
+        let fudge = buffer.read_u16()?;
+        let mac_len = buffer.read_u16()? as usize;
+        let mac = buffer.get_range(buffer.pos(), mac_len)?.to_vec();
+        buffer.step(mac_len)?;
+        let original_id = buffer.read_u16()?;
+        let error = buffer.read_u16()?;
+        let other_len = buffer.read_u16()? as usize;
+        let other = buffer.get_range(buffer.pos(), other_len)?.to_vec();
+        buffer.step(other_len)?;
+        if buffer.pos() != start + len {
+            return Err(Error::RdataLength { qtype: TYPE_TSIG, declared: len, used: buffer.pos() - start });
+        }
+        Ok(Tsig {
+            key_name: key_name.to_lowercase(),
+            algorithm: algorithm.to_lowercase(),
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+
+    fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_plain_qname(&self.key_name)?;
+        buffer.write_u16(TYPE_TSIG)?;
+        buffer.write_u16(CLASS_ANY)?;
+        buffer.write_u32(0)?;
+        let len_pos = buffer.pos();
+        buffer.write_u16(0)?;
+        buffer.write_plain_qname(&self.algorithm)?;
+        buffer.write_u16((self.time_signed >> 32) as u16)?;
+        buffer.write_u32(self.time_signed as u32)?;
+        buffer.write_u16(self.fudge)?;
+        buffer.write_u16(self.mac.len() as u16)?;
+        for b in &self.mac {
+            buffer.write_u8(*b)?;
+        }
+        buffer.write_u16(self.original_id)?;
+        buffer.write_u16(self.error)?;
+        buffer.write_u16(self.other.len() as u16)?;
+        for b in &self.other {
+            buffer.write_u8(*b)?;
+        }
+        buffer.set_u16(len_pos, (buffer.pos() - len_pos - 2) as u16)
+    }
+
+    // what the MAC covers: the request's MAC when this signs a response, the
+    // message without its TSIG record, then the record's own fields in
+    // canonical form (RFC 8945 4.3)
+    fn covered(&self, message: &[u8], request_mac: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if let Some(request_mac) = request_mac {
+            data.extend_from_slice(&(request_mac.len() as u16).to_be_bytes());
+            data.extend_from_slice(request_mac);
+        }
+        data.extend_from_slice(message);
+        let mut fields = BytePacketBuffer::uncompressed();
+        fields.write_plain_qname(&self.key_name)?;
+        fields.write_u16(CLASS_ANY)?;
+        fields.write_u32(0)?;
+        fields.write_plain_qname(&self.algorithm)?;
+        fields.write_u16((self.time_signed >> 32) as u16)?;
+        fields.write_u32(self.time_signed as u32)?;
+        fields.write_u16(self.fudge)?;
+        fields.write_u16(self.error)?;
+        fields.write_u16(self.other.len() as u16)?;
+        data.extend_from_slice(&fields.buf[0..fields.pos()]);
+        data.extend_from_slice(&self.other);
+        Ok(data)
+    }
+
+    // appends this record to the message in `buffer`, signed with `key`, or
+    // with an empty MAC for errors that can't be signed
+    pub fn sign(&mut self, key: Option<&Key>, buffer: &mut BytePacketBuffer, request_mac: Option<&[u8]>) -> Result<()> {
+        self.mac = match key {
+            Some(key) => hmac::sign(&key.secret, &self.covered(&buffer.buf[0..buffer.pos()], request_mac)?).as_ref().to_vec(),
+            None => Vec::new(),
+        };
+        self.write(buffer)?;
+        let records = u16::from_be_bytes([buffer.buf[10], buffer.buf[11]]);
+        buffer.set_u16(10, records + 1)
+    }
+}
+
+// a signed message once checked: the key to sign the reply with, None when
+// the reply goes out unsigned, and the TSIG error to report
+pub struct Signed<'a> {
+    pub key: Option<&'a Key>,
+    pub tsig: Tsig,
+    pub error: u16,
+}
+
+// the TSIG record of `message` checked against `keys`; None for a message
+// that isn't signed, an error for one whose TSIG isn't the last record
+pub fn check<'a>(keys: &'a [Key], message: &[u8], request_mac: Option<&[u8]>, now: u64) -> Result<Option<Signed<'a>>> {
+    let Some((start, tsig)) = find(message)? else { return Ok(None) };
+    // the MAC was made before the record was added, and under the id the
+    // message first went out with
+    let mut unsigned = message[..start].to_vec();
+    unsigned[0..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+    let records = u16::from_be_bytes([unsigned[10], unsigned[11]]) - 1;
+    unsigned[10..12].copy_from_slice(&records.to_be_bytes());
+    let key = keys.iter().find(|key| key.name == tsig.key_name && key.algorithm == tsig.algorithm);
+    let Some(key) = key else { return Ok(Some(Signed { key: None, tsig, error: BADKEY })) };
+    // truncated MACs aren't taken
+    if tsig.mac.len() != key.mac_len() || hmac::verify(&key.secret, &tsig.covered(&unsigned, request_mac)?, &tsig.mac).is_err() {
+        return Ok(Some(Signed { key: None, tsig, error: BADSIG }));
+    }
+    if now.abs_diff(tsig.time_signed) > tsig.fudge as u64 {
+        return Ok(Some(Signed { key: Some(key), tsig, error: BADTIME }));
+    }
+    Ok(Some(Signed { key: Some(key), tsig, error: 0 }))
+}
+
+// where the TSIG record starts and what it says, skipping over everything
+// before it without parsing any rdata
+fn find(message: &[u8]) -> Result<Option<(usize, Tsig)>> {
+    let mut buffer = BytePacketBuffer::with_size(message.len());
+    buffer.buf.copy_from_slice(message);
+    if message.len() < 12 {
+        return Err(Error::EndOfBuffer);
+    }
+    let count = |i: usize| u16::from_be_bytes([message[i], message[i + 1]]) as usize;
+    buffer.seek(12)?;
+    for _ in 0..count(4) {
+        buffer.read_qname(&mut String::new())?;
+        buffer.step(4)?;
+    }
+    let records = count(6) + count(8) + count(10);
+    let mut found = None;
+    for i in 0..records {
+        let start = buffer.pos();
+        buffer.read_qname(&mut String::new())?;
+        let qtype = buffer.read_u16()?;
+        buffer.step(6)?;
+        let len = buffer.read_u16()? as usize;
+        buffer.step(len)?;
+        if qtype == TYPE_TSIG {
+            if i + 1 != records || count(10) == 0 {
+                return Err(Error::BadRdata("TSIG isn't the last additional record".to_string()));
+            }
+            found = Some(start);
+        }
+    }
+    match found {
+        Some(start) => {
+            buffer.seek(start)?;
+            Ok(Some((start, Tsig::read(&mut buffer)?)))
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+
+use crate::utils::{Error, Result, ResultCode};
+use crate::header::Header;
+use crate::record::{Record, CLASS_IN};
+use crate::query_type::QueryType;
+use crate::question::Question;
+use crate::byte_bucket_buffer::BytePacketBuffer;
+use crate::zone::{self, Delta, Zone};
+
+// dynamic updates (RFC 2136): the question section names the zone, the
+// answer section holds prerequisites and the authority section the changes
+pub const OPCODE_UPDATE: u8 = 5;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const TYPE_ANY: u16 = 255;
+
+// one record of the prerequisite or update section; under class ANY and
+// NONE it stands for a condition or a deletion and may carry no rdata at all
+// (RFC 2136 2.4, 2.5)
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub name: String,
+    pub qtype: QueryType,
+    pub class: u16,
+    pub ttl: u32,
+    pub record: Option<Record>,
+}
+
+impl Change {
+    fn read(buffer: &mut BytePacketBuffer) -> Result<Change> {
+        let start = buffer.pos();
+        let mut name = String::new();
+        buffer.read_qname(&mut name)?;
+        let qtype = QueryType::from_num(buffer.read_u16()?);
+        let class = buffer.read_u16()?;
+        let ttl = buffer.read_u32()?;
+        let len = buffer.read_u16()? as usize;
+        let name = name.to_lowercase();
+        if len == 0 {
+            return Ok(Change { name, qtype, class, ttl, record: None });
+        }
+        buffer.seek(start)?;
+        let mut record = Record::read_any_class(buffer)?;
+        record.set_domain(name.clone());
+        Ok(Change { name, qtype, class, ttl, record: Some(record) })
+    }
+}
+
+pub struct Update {
+    pub header: Header,
+    pub zone: Question,
+    pub prerequisites: Vec<Change>,
+    pub updates: Vec<Change>,
+}
+
+impl Update {
+    // the additional section is read past but not kept; a TSIG record there
+    // is checked on the raw message before this
+    pub fn from_buffer(buffer: &mut BytePacketBuffer) -> Result<Update> {
+        let mut header = Header::new();
+        header.read(buffer)?;
+        if header.questions != 1 {
+            return Err(Error::BadRdata(format!("update names {} zones", header.questions)));
+        }
+        let mut zone = Question::new(String::new(), QueryType::UNKNOWN(0));
+        zone.read(buffer)?;
+        zone.name = zone.name.to_lowercase();
+        let mut prerequisites = Vec::new();
+        for _ in 0..header.answers {
+            prerequisites.push(Change::read(buffer)?);
+        }
+        let mut updates = Vec::new();
+        for _ in 0..header.authoritative_entries {
+            updates.push(Change::read(buffer)?);
+        }
+        for _ in 0..header.resource_entries {
+            Change::read(buffer)?;
+        }
+        Ok(Update { header, zone, prerequisites, updates })
+    }
+}
+
+// whether a raw message is an update request, which has to be told apart
+// before parsing since its deletions don't parse as ordinary records
+pub fn is_update(buffer: &BytePacketBuffer) -> bool {
+    buffer.buf.len() >= 12 && buffer.buf[2] & 0x80 == 0 && (buffer.buf[2] >> 3) & 0x0F == OPCODE_UPDATE
+}
+
+fn same_data(a: &Record, b: &Record) -> bool {
+    let mut a = a.clone();
+    let mut b = b.clone();
+    a.set_ttl(0);
+    b.set_ttl(0);
+    a == b
+}
+
+// serial numbers compare in sequence space (RFC 1982)
+fn serial_newer(new: u32, old: u32) -> bool {
+    new != old && new.wrapping_sub(old) < 1 << 31
+}
+
+// the records of `zone` at `name` as the prerequisite section sees them
+fn rrset<'a>(zone: &'a Zone, name: &str, qtype: QueryType) -> impl Iterator<Item = &'a Record> {
+    zone.records(name).iter().filter(move |record| record.qtype() == qtype)
+}
+
+// RFC 2136 3.2
+fn check_prerequisites(zone: &Zone, prerequisites: &[Change]) -> ResultCode {
+    let mut expected: BTreeMap<(String, u16), Vec<Record>> = BTreeMap::new();
+    for change in prerequisites {
+        if change.ttl != 0 {
+            return ResultCode::FORMERR;
+        }
+        if !zone::in_zone(&change.name, &zone.origin) {
+            return ResultCode::NOTZONE;
+        }
+        let any = change.qtype.to_num() == TYPE_ANY;
+        match (change.class, &change.record) {
+            (CLASS_ANY, None) if any => {
+                if zone.records(&change.name).is_empty() {
+                    return ResultCode::NXDOMAIN;
+                }
+            }
+            (CLASS_ANY, None) => {
+                if rrset(zone, &change.name, change.qtype).next().is_none() {
+                    return ResultCode::NXRRSET;
+                }
+            }
+            (CLASS_NONE, None) if any => {
+                if !zone.records(&change.name).is_empty() {
+                    return ResultCode::YXDOMAIN;
+                }
+            }
+            (CLASS_NONE, None) => {
+                if rrset(zone, &change.name, change.qtype).next().is_some() {
+                    return ResultCode::YXRRSET;
+                }
+            }
+            (CLASS_IN, Some(record)) => {
+                let mut record = record.clone();
+                record.set_ttl(0);
+                expected.entry((change.name.clone(), change.qtype.to_num())).or_default().push(record);
+            }
+            _ => return ResultCode::FORMERR,
+        }
+    }
+    // value dependent prerequisites have to match whole RRsets
+    for ((name, qtype), mut records) in expected {
+        let mut present: Vec<Record> = rrset(zone, &name, QueryType::from_num(qtype))
+            .map(|record| {
+                let mut record = record.clone();
+                record.set_ttl(0);
+                record
+            })
+            .collect();
+        records.sort();
+        records.dedup();
+        present.sort();
+        if records != present {
+            return ResultCode::NXRRSET;
+        }
+    }
+    ResultCode::NOERROR
+}
+
+// RFC 2136 3.4.1; the types a zone file can't hold are refused too, since
+// the journal is written in zone file syntax and signed zones aren't
+// updated in place anyway
+fn prescan(zone: &Zone, updates: &[Change]) -> ResultCode {
+    for change in updates {
+        if !zone::in_zone(&change.name, &zone.origin) {
+            return ResultCode::NOTZONE;
+        }
+        let qtype = change.qtype.to_num();
+        // ANY, AXFR, MAILB, MAILA, plus OPT and TSIG, which only ever live
+        // in a message
+        let meta = (251..=255).contains(&qtype) || qtype == 41 || qtype == 250;
+        let valid = match (change.class, &change.record) {
+            (CLASS_IN, Some(_)) => !meta,
+            (CLASS_ANY, None) => change.ttl == 0 && (!meta || qtype == TYPE_ANY),
+            (CLASS_NONE, Some(_)) => change.ttl == 0 && !meta,
+            _ => false,
+        };
+        if !valid {
+            return ResultCode::FORMERR;
+        }
+        if change.class == CLASS_IN && matches!(change.qtype, QueryType::RRSIG | QueryType::NSEC | QueryType::NSEC3) {
+            return ResultCode::REFUSED;
+        }
+    }
+    ResultCode::NOERROR
+}
+
+// the deltas one update record calls for against the zone as it stands
+// (RFC 2136 3.4.2)
+fn deltas(zone: &Zone, change: &Change) -> Vec<Delta> {
+    let at_apex = change.name == zone.origin;
+    let present = zone.records(&change.name);
+    match (change.class, &change.record) {
+        (CLASS_IN, Some(record)) => {
+            if let Record::SOA { serial, .. } = *record {
+                let Record::SOA { serial: current, .. } = zone.soa else { return Vec::new() };
+                if !at_apex || !serial_newer(serial, current) {
+                    return Vec::new();
+                }
+                return vec![Delta::Remove(zone.soa.clone()), Delta::Add(record.clone())];
+            }
+            let cname = matches!(record, Record::CNAME { .. });
+            // a CNAME can't share its name with other data, whichever came
+            // first; one CNAME replaces another
+            if cname && present.iter().any(|other| !matches!(other, Record::CNAME { .. })) {
+                return Vec::new();
+            }
+            if !cname && present.iter().any(|other| matches!(other, Record::CNAME { .. })) {
+                return Vec::new();
+            }
+            let replaced: Vec<&Record> = present.iter()
+                .filter(|other| (cname && matches!(other, Record::CNAME { .. })) || same_data(other, record))
+                .collect();
+            if replaced.contains(&record) {
+                return Vec::new();
+            }
+            let mut deltas: Vec<Delta> = replaced.into_iter().map(|other| Delta::Remove(other.clone())).collect();
+            deltas.push(Delta::Add(record.clone()));
+            deltas
+        }
+        (CLASS_ANY, None) => present.iter()
+            .filter(|other| match change.qtype.to_num() {
+                // the apex keeps its SOA and NS whatever is asked
+                TYPE_ANY => !at_apex || !matches!(other, Record::SOA { .. } | Record::NS { .. }),
+                _ => other.qtype() == change.qtype && !(at_apex && matches!(other, Record::SOA { .. } | Record::NS { .. })),
+            })
+            .map(|other| Delta::Remove(other.clone()))
+            .collect(),
+        (CLASS_NONE, Some(record)) => {
+            if matches!(record, Record::SOA { .. }) {
+                return Vec::new();
+            }
+            // nor does the apex lose its last NS
+            let nameservers = present.iter().filter(|other| matches!(other, Record::NS { .. })).count();
+            if at_apex && matches!(record, Record::NS { .. }) && nameservers <= 1 {
+                return Vec::new();
+            }
+            present.iter().filter(|other| same_data(other, record)).map(|other| Delta::Remove(other.clone())).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+// checks and applies `update` to `zone` as one transaction: either every
+// change is made, the serial goes up unless the update set it, and the
+// journal has it all, or the zone is left as it was
+pub fn apply(zone: &mut Zone, update: &Update) -> Result<ResultCode> {
+    if update.zone.qtype != QueryType::SOA || update.zone.class != CLASS_IN {
+        return Ok(ResultCode::FORMERR);
+    }
+    for check in [check_prerequisites(zone, &update.prerequisites), prescan(zone, &update.updates)] {
+        if check != ResultCode::NOERROR {
+            return Ok(check);
+        }
+    }
+    let old_soa = zone.soa.clone();
+    let mut applied = Vec::new();
+    for change in &update.updates {
+        for delta in deltas(zone, change) {
+            zone.apply(&delta);
+            applied.push(delta);
+        }
+    }
+    if applied.is_empty() {
+        return Ok(ResultCode::NOERROR);
+    }
+    if zone.soa == old_soa {
+        let mut soa = old_soa.clone();
+        if let Record::SOA { ref mut serial, .. } = soa {
+            *serial = serial.wrapping_add(1);
+        }
+        for delta in [Delta::Remove(old_soa), Delta::Add(soa)] {
+            zone.apply(&delta);
+            applied.push(delta);
+        }
+    }
+    if let Err(e) = zone.write_journal(&applied) {
+        for delta in applied.iter().rev() {
+            zone.apply(&match delta {
+                Delta::Add(record) => Delta::Remove(record.clone()),
+                Delta::Remove(record) => Delta::Add(record.clone()),
+            });
+        }
+        return Err(e);
+    }
+    Ok(ResultCode::NOERROR)
+}
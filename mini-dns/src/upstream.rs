@@ -0,0 +1,168 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::utils::{Result, ResultCode};
+use crate::query_type::QueryType;
+use crate::packet::Packet;
+use crate::question::Question;
+use crate::transport;
+use crate::zone;
+
+// failures in a row before a server is taken out of rotation, and for how long
+const MAX_FAILURES: u32 = 3;
+const DOWN_TIME: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Health {
+    // smoothed round trip time; None until the server has answered once
+    srtt: Option<Duration>,
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+pub struct Upstream {
+    pub addr: SocketAddr,
+    health: Mutex<Health>,
+}
+
+impl Upstream {
+    pub fn new(addr: SocketAddr) -> Upstream {
+        Upstream {
+            addr,
+            health: Mutex::new(Health::default()),
+        }
+    }
+
+    pub fn srtt(&self) -> Option<Duration> {
+        self.health.lock().unwrap().srtt
+    }
+
+    pub fn is_down(&self, now: Instant) -> bool {
+        matches!(self.health.lock().unwrap().down_until, Some(until) if until > now)
+    }
+
+    // weighted like BIND's, so one slow reply doesn't condemn a server
+    fn answered(&self, rtt: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(match health.srtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        });
+        health.failures = 0;
+        health.down_until = None;
+    }
+
+    // a timeout counts as a reply that took the whole timeout
+    fn failed(&self, timeout: Duration, now: Instant) {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(health.srtt.map_or(timeout, |srtt| (srtt * 7 + timeout) / 8));
+        health.failures += 1;
+        if health.failures >= MAX_FAILURES {
+            health.down_until = Some(now + DOWN_TIME);
+        }
+    }
+}
+
+// the servers queries are forwarded to, picked by the longest domain that
+// routes the name and, within that, fastest first
+pub struct Upstreams {
+    // the default servers sit under the root, ""
+    routes: Vec<(String, Vec<Upstream>)>,
+    pub timeout: Duration,
+    // rounds over a route's servers before the query fails
+    pub attempts: usize,
+}
+
+impl Upstreams {
+    pub fn new(servers: Vec<SocketAddr>) -> Upstreams {
+        Upstreams {
+            routes: vec![(String::new(), servers.into_iter().map(Upstream::new).collect())],
+            timeout: Duration::from_secs(2),
+            attempts: 2,
+        }
+    }
+
+    // names in and under `domain` go to `servers` instead
+    pub fn route(&mut self, domain: &str, servers: Vec<SocketAddr>) {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        let servers = servers.into_iter().map(Upstream::new).collect();
+        match self.routes.iter_mut().find(|(origin, _)| *origin == domain) {
+            Some((_, existing)) => *existing = servers,
+            None => self.routes.push((domain, servers)),
+        }
+    }
+
+    pub fn servers_for(&self, qname: &str) -> &[Upstream] {
+        self.routes.iter()
+            .filter(|(origin, _)| zone::in_zone(qname, origin))
+            .max_by_key(|(origin, _)| origin.len())
+            .map_or(&[], |(_, servers)| servers.as_slice())
+    }
+
+    // servers still in rotation go first, fastest first and the untried
+    // before them; those taken out are only a last resort
+    fn ranked<'a>(&'a self, qname: &str, now: Instant) -> Vec<&'a Upstream> {
+        let mut servers: Vec<&Upstream> = self.servers_for(qname).iter().collect();
+        servers.sort_by_key(|server| (server.is_down(now), server.srtt()));
+        servers
+    }
+
+    // a server that times out or can't be reached fails over to the next;
+    // one that answers SERVFAIL or REFUSED is passed over too, but its answer
+    // stands if no other server does better
+    pub fn query(&self, qname: &str, qtype: QueryType, dnssec_ok: bool) -> Result<Packet> {
+        let question = Question::new(qname.to_string(), qtype);
+        let mut fallback = None;
+        let mut last_error = None;
+        for _ in 0..self.attempts {
+            for server in self.ranked(&question.name, Instant::now()) {
+                let started = Instant::now();
+                match transport::exchange(server.addr, &question, true, dnssec_ok, self.timeout) {
+                    Ok(response) => {
+                        server.answered(started.elapsed());
+                        if !matches!(response.header.rescode, ResultCode::SERVFAIL | ResultCode::REFUSED) {
+                            return Ok(response);
+                        }
+                        fallback = Some(response);
+                    }
+                    Err(e) => {
+                        server.failed(self.timeout, Instant::now());
+                        last_error = Some(e);
+                    }
+                }
+            }
+            if let Some(response) = fallback {
+                return Ok(response);
+            }
+        }
+        Err(last_error.unwrap_or_else(|| format!("no upstream servers for {}", qname).into()))
+    }
+
+    // asks every server out of rotation for the root's NS records, putting
+    // back the ones that answer
+    pub fn check(&self) {
+        let question = Question::new(String::new(), QueryType::NS);
+        let now = Instant::now();
+        for (_, servers) in &self.routes {
+            for server in servers.iter().filter(|server| server.is_down(now)) {
+                let started = Instant::now();
+                match transport::exchange(server.addr, &question, true, false, self.timeout) {
+                    Ok(_) => server.answered(started.elapsed()),
+                    Err(_) => server.failed(self.timeout, Instant::now()),
+                }
+            }
+        }
+    }
+}
+
+// an address with the port left off means port 53
+pub fn parse_server(s: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    match s.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+        Err(_) => Err(format!("`{}` isn't a server address", s).into()),
+    }
+}
@@ -1,25 +1,164 @@
-pub type Error = Box<dyn std::error::Error>;
+use std::fmt;
+use std::io;
+use std::net::AddrParseError;
+use std::num::ParseIntError;
+
+#[derive(Debug)]
+pub enum Error {
+    // a message ending before a field it promises
+    EndOfBuffer,
+    // a message that would be longer than any can be
+    MessageTooLong,
+    // a compression pointer that doesn't point back to an earlier name
+    BadPointer { at: usize, to: usize },
+    TooManyJumps,
+    LabelTooLong(usize),
+    EmptyLabel,
+    NameTooLong(usize),
+    // header counts that the message is too short to hold
+    BadCounts { records: usize, len: usize },
+    // a record whose rdata isn't as long as its length field says
+    RdataLength { qtype: u16, declared: usize, used: usize },
+    // rdata of the right length but wrong shape
+    BadRdata(String),
+    Io(io::Error),
+    Tls(rustls::Error),
+    // anything outside of the wire format, such as zone files and options
+    Other(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::EndOfBuffer => write!(f, "End of buffer"),
+            Error::MessageTooLong => write!(f, "Message is over the {} byte limit", crate::byte_bucket_buffer::MAX_SIZE),
+            Error::BadPointer { at, to } => write!(f, "Compression pointer at {} points forward to {}", at, to),
+            Error::TooManyJumps => write!(f, "Limit of {} jumps exceeded", crate::byte_bucket_buffer::MAX_JUMPS),
+            Error::LabelTooLong(len) => write!(f, "Label of {} characters, must be 1 to 63", len),
+            Error::EmptyLabel => write!(f, "Empty label in a name"),
+            Error::NameTooLong(len) => write!(f, "Name of {} characters exceeds 253", len),
+            Error::BadCounts { records, len } => write!(f, "{} entries can't fit in {} bytes", records, len),
+            Error::RdataLength { qtype, declared, used } => write!(f, "type {} rdata of {} bytes, but {} were read", qtype, declared, used),
+            Error::BadRdata(e) | Error::Other(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Tls(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Tls(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<rustls::Error> for Error {
+    fn from(e: rustls::Error) -> Error {
+        Error::Tls(e)
+    }
+}
+
+impl From<String> for Error {
+    fn from(e: String) -> Error {
+        Error::Other(e)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(e: &str) -> Error {
+        Error::Other(e.to_string())
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(e: ParseIntError) -> Error {
+        Error::Other(e.to_string())
+    }
+}
+
+impl From<AddrParseError> for Error {
+    fn from(e: AddrParseError) -> Error {
+        Error::Other(e.to_string())
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ResultCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
+    NOERROR,
+    FORMERR,
+    SERVFAIL,
+    NXDOMAIN,
+    NOTIMP,
+    REFUSED,
+    // the update rcodes (RFC 2136 2.2)
+    YXDOMAIN,
+    YXRRSET,
+    NXRRSET,
+    NOTAUTH,
+    NOTZONE,
+    // extended rcodes need the upper bits from an OPT record
+    BADVERS,
+    // any other code, kept as it came so a failure never reads as success
+    Other(u16),
 }
 
 impl ResultCode {
-    pub fn from_num(num: u8) -> ResultCode {
+    pub fn from_num(num: u16) -> ResultCode {
         match num {
+            0 => ResultCode::NOERROR,
             1 => ResultCode::FORMERR,
             2 => ResultCode::SERVFAIL,
             3 => ResultCode::NXDOMAIN,
             4 => ResultCode::NOTIMP,
             5 => ResultCode::REFUSED,
-            0 | _ => ResultCode::NOERROR,
+            6 => ResultCode::YXDOMAIN,
+            7 => ResultCode::YXRRSET,
+            8 => ResultCode::NXRRSET,
+            9 => ResultCode::NOTAUTH,
+            10 => ResultCode::NOTZONE,
+            16 => ResultCode::BADVERS,
+            _ => ResultCode::Other(num),
         }
     }
-}
\ No newline at end of file
+
+    pub fn to_num(self) -> u16 {
+        match self {
+            ResultCode::NOERROR => 0,
+            ResultCode::FORMERR => 1,
+            ResultCode::SERVFAIL => 2,
+            ResultCode::NXDOMAIN => 3,
+            ResultCode::NOTIMP => 4,
+            ResultCode::REFUSED => 5,
+            ResultCode::YXDOMAIN => 6,
+            ResultCode::YXRRSET => 7,
+            ResultCode::NXRRSET => 8,
+            ResultCode::NOTAUTH => 9,
+            ResultCode::NOTZONE => 10,
+            ResultCode::BADVERS => 16,
+            ResultCode::Other(num) => num,
+        }
+    }
+}
+
+// the mnemonic, or the bare number for a code without one
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResultCode::Other(num) => write!(f, "{}", num),
+            rescode => write!(f, "{:?}", rescode),
+        }
+    }
+}
@@ -0,0 +1,861 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use crate::utils::{Result, ResultCode};
+use crate::record::{Record, CLASS_IN};
+use crate::query_type::QueryType;
+use crate::byte_bucket_buffer::BytePacketBuffer;
+
+const MAX_INCLUDE_DEPTH: usize = 8;
+const MAX_CNAME_CHAIN: usize = 8;
+const QTYPE_ANY: u16 = 255;
+
+struct Token {
+    text: String,
+    // the text as bytes, with \DDD escapes standing for a single byte
+    data: Vec<u8>,
+    quoted: bool,
+}
+
+// one logical line of a master file, with comments stripped and
+// parenthesised continuations joined; `owner` is None when the line starts
+// with blanks and reuses the previous owner
+struct Line {
+    number: usize,
+    owner: Option<String>,
+    tokens: Vec<Token>,
+}
+
+fn tokenize(content: &str, file: &str) -> Result<Vec<Line>> {
+    let mut lines = Vec::new();
+    let mut depth = 0;
+    let mut current: Option<Line> = None;
+    for (i, text) in content.lines().enumerate() {
+        let chars: Vec<char> = text.chars().collect();
+        if current.is_none() {
+            current = Some(Line {
+                number: i + 1,
+                owner: None,
+                tokens: Vec::new(),
+            });
+        }
+        let line = current.as_mut().unwrap();
+        let blank_start = depth > 0 || chars.first().map(|c| c.is_whitespace()).unwrap_or(true);
+        let mut pos = 0;
+        while pos < chars.len() {
+            let c = chars[pos];
+            if c.is_whitespace() {
+                pos += 1;
+                continue;
+            }
+            match c {
+                ';' => break,
+                '(' => {
+                    depth += 1;
+                    pos += 1;
+                    continue;
+                }
+                ')' => {
+                    if depth == 0 {
+                        return Err(format!("{}:{}: unbalanced `)`", file, i + 1).into());
+                    }
+                    depth -= 1;
+                    pos += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            let quoted = c == '"';
+            if quoted {
+                pos += 1;
+            }
+            let mut token = String::new();
+            let mut data = Vec::new();
+            let mut closed = !quoted;
+            while pos < chars.len() {
+                let c = chars[pos];
+                if quoted && c == '"' {
+                    closed = true;
+                    pos += 1;
+                    break;
+                }
+                if !quoted && (c.is_whitespace() || c == ';' || c == '(' || c == ')') {
+                    break;
+                }
+                if c == '\\' && pos + 1 < chars.len() {
+                    let digits: String = chars[pos + 1..].iter().take(3).collect();
+                    if digits.len() == 3 && digits.chars().all(|d| d.is_ascii_digit()) {
+                        let value: u32 = digits.parse().unwrap();
+                        if value > 255 {
+                            return Err(format!("{}:{}: invalid escape `\\{}`", file, i + 1, digits).into());
+                        }
+                        token.push(value as u8 as char);
+                        data.push(value as u8);
+                        pos += 4;
+                    } else {
+                        token.push(chars[pos + 1]);
+                        push_char(&mut data, chars[pos + 1]);
+                        pos += 2;
+                    }
+                    continue;
+                }
+                token.push(c);
+                push_char(&mut data, c);
+                pos += 1;
+            }
+            if !closed {
+                return Err(format!("{}:{}: unterminated string", file, i + 1).into());
+            }
+            if line.tokens.is_empty() && line.owner.is_none() && !blank_start && !quoted {
+                line.owner = Some(token);
+            } else {
+                line.tokens.push(Token { text: token, data, quoted });
+            }
+        }
+        if depth == 0 {
+            let line = current.take().unwrap();
+            if line.owner.is_some() || !line.tokens.is_empty() {
+                lines.push(line);
+            }
+        }
+    }
+    if depth > 0 {
+        return Err(format!("{}: unbalanced `(`", file).into());
+    }
+    Ok(lines)
+}
+
+fn push_char(data: &mut Vec<u8>, c: char) {
+    data.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+// plain seconds or BIND style units, e.g. `3600` or `1h30m`
+fn parse_ttl(text: &str) -> Option<u32> {
+    if text.is_empty() || !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(ttl) = text.parse::<u32>() {
+        return Some(ttl);
+    }
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total += number.parse::<u64>().ok()? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total += number.parse::<u64>().ok()?;
+    }
+    u32::try_from(total).ok()
+}
+
+fn absolute(name: &str, origin: &str) -> String {
+    let name = if name == "@" {
+        origin.to_string()
+    } else if let Some(name) = name.strip_suffix('.') {
+        name.to_string()
+    } else if origin.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, origin)
+    };
+    name.to_lowercase()
+}
+
+pub fn in_zone(name: &str, origin: &str) -> bool {
+    origin.is_empty() || name == origin || name.ends_with(&format!(".{}", origin))
+}
+
+struct Parser {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<String>,
+    records: Vec<Record>,
+}
+
+impl Parser {
+    fn parse_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        let file = path.display().to_string();
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", file, e))?;
+        for line in tokenize(&content, &file)? {
+            let at = format!("{}:{}", file, line.number);
+            match line.owner.as_deref() {
+                Some("$ORIGIN") => {
+                    let origin = line.tokens.first().ok_or(format!("{}: $ORIGIN needs a name", at))?;
+                    self.origin = absolute(&origin.text, &self.origin);
+                }
+                Some("$TTL") => {
+                    let ttl = line.tokens.first().and_then(|ttl| parse_ttl(&ttl.text));
+                    self.default_ttl = Some(ttl.ok_or(format!("{}: $TTL needs a ttl", at))?);
+                }
+                Some("$INCLUDE") => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(format!("{}: $INCLUDE nested too deep", at).into());
+                    }
+                    let target = line.tokens.first().ok_or(format!("{}: $INCLUDE needs a file", at))?;
+                    let target = path.parent().unwrap_or_else(|| Path::new(".")).join(&target.text);
+                    // the included file may set its own origin, which ends with it
+                    let origin = self.origin.clone();
+                    if let Some(included) = line.tokens.get(1) {
+                        self.origin = absolute(&included.text, &origin);
+                    }
+                    self.parse_file(&target, depth + 1)?;
+                    self.origin = origin;
+                }
+                Some(directive) if directive.starts_with('$') => {
+                    return Err(format!("{}: unknown directive `{}`", at, directive).into());
+                }
+                _ => {
+                    let record = self.parse_record(line).map_err(|e| format!("{}: {}", at, e))?;
+                    self.records.push(record);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_record(&mut self, line: Line) -> Result<Record> {
+        let domain = match line.owner {
+            Some(owner) => absolute(&owner, &self.origin),
+            None => self.last_owner.clone().ok_or("record without an owner")?,
+        };
+        self.last_owner = Some(domain.clone());
+        let mut tokens = line.tokens.into_iter().peekable();
+        let mut ttl = None;
+        let mut class = false;
+        while let Some(token) = tokens.peek() {
+            if !class && token.text.eq_ignore_ascii_case("IN") {
+                class = true;
+            } else if ["CH", "HS", "CS"].iter().any(|other| token.text.eq_ignore_ascii_case(other)) {
+                return Err(format!("unsupported class `{}`", token.text).into());
+            } else if ttl.is_none() && parse_ttl(&token.text).is_some() {
+                ttl = parse_ttl(&token.text);
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+        let rtype = tokens.next().ok_or("missing record type")?.text.to_uppercase();
+        let rdata: Vec<Token> = tokens.collect();
+        if let Some(ttl) = ttl {
+            self.last_ttl = Some(ttl);
+        }
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(ttl) => ttl,
+            None => return Err("no ttl given and no $TTL set".into()),
+        };
+        let arg = |i: usize| -> Result<&str> {
+            match rdata.get(i) {
+                Some(token) => Ok(&token.text),
+                None => Err(format!("{} record is missing fields", rtype).into()),
+            }
+        };
+        let bytes = |i: usize| -> Result<Vec<u8>> {
+            match rdata.get(i) {
+                Some(token) => Ok(token.data.clone()),
+                None => Err(format!("{} record is missing fields", rtype).into()),
+            }
+        };
+        let fields = |n: usize| -> Result<()> {
+            if rdata.len() != n {
+                return Err(format!("{} record takes {} fields, got {}", rtype, n, rdata.len()).into());
+            }
+            Ok(())
+        };
+        let record = match rtype.as_str() {
+            "A" => {
+                fields(1)?;
+                let addr = arg(0)?.parse::<Ipv4Addr>().map_err(|_| format!("invalid IPv4 address `{}`", arg(0).unwrap()))?;
+                Record::A { domain, addr, ttl }
+            }
+            "AAAA" => {
+                fields(1)?;
+                let addr = arg(0)?.parse::<Ipv6Addr>().map_err(|_| format!("invalid IPv6 address `{}`", arg(0).unwrap()))?;
+                Record::AAAA { domain, addr, ttl }
+            }
+            "NS" => {
+                fields(1)?;
+                Record::NS { domain, host: absolute(arg(0)?, &self.origin), ttl }
+            }
+            "CNAME" => {
+                fields(1)?;
+                Record::CNAME { domain, host: absolute(arg(0)?, &self.origin), ttl }
+            }
+            "MX" => {
+                fields(2)?;
+                let priority = arg(0)?.parse::<u16>().map_err(|_| format!("invalid MX preference `{}`", arg(0).unwrap()))?;
+                Record::MX { domain, priority, host: absolute(arg(1)?, &self.origin), ttl }
+            }
+            "TXT" => {
+                if rdata.is_empty() {
+                    return Err("TXT record needs at least one string".into());
+                }
+                Record::TXT { domain, data: rdata.iter().map(|token| token.data.clone()).collect(), ttl }
+            }
+            "SOA" => {
+                fields(7)?;
+                let mut times = [0; 5];
+                for (i, time) in times.iter_mut().enumerate() {
+                    *time = parse_ttl(arg(i + 2)?).ok_or(format!("invalid SOA field `{}`", arg(i + 2).unwrap()))?;
+                }
+                Record::SOA {
+                    domain,
+                    m_name: absolute(arg(0)?, &self.origin),
+                    r_name: absolute(arg(1)?, &self.origin),
+                    serial: times[0],
+                    refresh: times[1],
+                    retry: times[2],
+                    expire: times[3],
+                    minimum: times[4],
+                    ttl,
+                }
+            }
+            "PTR" => {
+                fields(1)?;
+                Record::PTR { domain, host: absolute(arg(0)?, &self.origin), ttl }
+            }
+            "HINFO" => {
+                fields(2)?;
+                Record::HINFO { domain, cpu: bytes(0)?, os: bytes(1)?, ttl }
+            }
+            "SRV" => {
+                fields(4)?;
+                let mut numbers = [0; 3];
+                for (i, number) in numbers.iter_mut().enumerate() {
+                    *number = arg(i)?.parse::<u16>().map_err(|_| format!("invalid SRV field `{}`", arg(i).unwrap()))?;
+                }
+                Record::SRV {
+                    domain,
+                    priority: numbers[0],
+                    weight: numbers[1],
+                    port: numbers[2],
+                    host: absolute(arg(3)?, &self.origin),
+                    ttl,
+                }
+            }
+            "NAPTR" => {
+                fields(6)?;
+                let order = arg(0)?.parse::<u16>().map_err(|_| format!("invalid NAPTR order `{}`", arg(0).unwrap()))?;
+                let preference = arg(1)?.parse::<u16>().map_err(|_| format!("invalid NAPTR preference `{}`", arg(1).unwrap()))?;
+                Record::NAPTR {
+                    domain,
+                    order,
+                    preference,
+                    flags: bytes(2)?,
+                    services: bytes(3)?,
+                    regexp: bytes(4)?,
+                    replacement: absolute(arg(5)?, &self.origin),
+                    ttl,
+                }
+            }
+            "CAA" => {
+                fields(3)?;
+                let flags = arg(0)?.parse::<u8>().map_err(|_| format!("invalid CAA flags `{}`", arg(0).unwrap()))?;
+                Record::CAA { domain, flags, tag: bytes(1)?, value: bytes(2)?, ttl }
+            }
+            "DS" => {
+                if rdata.len() < 4 {
+                    return Err("DS record is missing fields".into());
+                }
+                Record::DS {
+                    domain,
+                    key_tag: arg(0)?.parse::<u16>().map_err(|_| format!("invalid DS key tag `{}`", arg(0).unwrap()))?,
+                    algorithm: arg(1)?.parse::<u8>().map_err(|_| format!("invalid DS algorithm `{}`", arg(1).unwrap()))?,
+                    digest_type: arg(2)?.parse::<u8>().map_err(|_| format!("invalid DS digest type `{}`", arg(2).unwrap()))?,
+                    digest: parse_hex(&rdata[3..])?,
+                    ttl,
+                }
+            }
+            "DNSKEY" => {
+                if rdata.len() < 4 {
+                    return Err("DNSKEY record is missing fields".into());
+                }
+                Record::DNSKEY {
+                    domain,
+                    flags: arg(0)?.parse::<u16>().map_err(|_| format!("invalid DNSKEY flags `{}`", arg(0).unwrap()))?,
+                    protocol: arg(1)?.parse::<u8>().map_err(|_| format!("invalid DNSKEY protocol `{}`", arg(1).unwrap()))?,
+                    algorithm: arg(2)?.parse::<u8>().map_err(|_| format!("invalid DNSKEY algorithm `{}`", arg(2).unwrap()))?,
+                    public_key: parse_base64(&rdata[3..])?,
+                    ttl,
+                }
+            }
+            _ => match rtype.strip_prefix("TYPE").and_then(|num| num.parse::<u16>().ok()) {
+                Some(qtype) => generic(domain, qtype, ttl, &rdata)?,
+                None => return Err(format!("unsupported record type `{}`", rtype).into()),
+            },
+        };
+        let strings = matches!(record, Record::TXT { .. } | Record::HINFO { .. } | Record::NAPTR { .. } | Record::CAA { .. });
+        if rdata.iter().any(|token| token.quoted) && !strings {
+            return Err(format!("unexpected quoted string in {} record", rtype).into());
+        }
+        Ok(record)
+    }
+}
+
+// RFC 3597 rdata, `\# <length> <hex>...`, for a type given as `TYPEnnn`;
+// types this server knows come back as their own variant
+fn generic(domain: String, qtype: u16, ttl: u32, rdata: &[Token]) -> Result<Record> {
+    if rdata.len() < 2 || rdata[0].text != "#" {
+        return Err(format!("TYPE{} record needs `\\# <length> <data>`", qtype).into());
+    }
+    let len = rdata[1].text.parse::<u16>().map_err(|_| format!("invalid rdata length `{}`", rdata[1].text))?;
+    let data = parse_hex(&rdata[2..])?;
+    if data.len() != len as usize {
+        return Err(format!("rdata is {} bytes, not {}", data.len(), len).into());
+    }
+    let mut buffer = BytePacketBuffer::new();
+    Record::UNKNOWN { domain, qtype, class: CLASS_IN, data, ttl }.write(&mut buffer)?;
+    buffer.buf.truncate(buffer.pos());
+    buffer.seek(0)?;
+    let record = Record::read(&mut buffer)?;
+    if buffer.pos() != buffer.buf.len() {
+        return Err(format!("rdata doesn't match type {}", qtype).into());
+    }
+    Ok(record)
+}
+
+fn parse_hex(tokens: &[Token]) -> Result<Vec<u8>> {
+    let text: String = tokens.iter().map(|token| token.text.as_str()).collect();
+    if !text.len().is_multiple_of(2) || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex `{}`", text).into());
+    }
+    Ok((0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect())
+}
+
+fn parse_base64(tokens: &[Token]) -> Result<Vec<u8>> {
+    let text: String = tokens.iter().map(|token| token.text.as_str()).collect();
+    base64(&text)
+}
+
+// RFC 4648 section 4, padding and all
+pub fn base64(text: &str) -> Result<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let data = text.trim_end_matches('=');
+    if !text.len().is_multiple_of(4) || text.len() - data.len() > 2 {
+        return Err(format!("invalid base64 `{}`", text).into());
+    }
+    let mut bytes = Vec::new();
+    let mut bits: u32 = 0;
+    for (i, c) in data.bytes().enumerate() {
+        let value = ALPHABET.iter().position(|&a| a == c).ok_or(format!("invalid base64 `{}`", text))?;
+        bits = (bits << 6) | value as u32;
+        if i % 4 == 3 {
+            bytes.extend_from_slice(&bits.to_be_bytes()[1..]);
+            bits = 0;
+        }
+    }
+    match data.len() % 4 {
+        2 => bytes.push((bits >> 4) as u8),
+        3 => bytes.extend_from_slice(&((bits >> 2) as u16).to_be_bytes()),
+        _ => {}
+    }
+    Ok(bytes)
+}
+
+// every record of a master file, following its $INCLUDEs
+pub fn parse(path: &Path) -> Result<Vec<Record>> {
+    let mut parser = Parser {
+        origin: String::new(),
+        default_ttl: None,
+        last_ttl: None,
+        last_owner: None,
+        records: Vec::new(),
+    };
+    parser.parse_file(path, 0)?;
+    Ok(parser.records)
+}
+
+pub struct Answer {
+    pub rescode: ResultCode,
+    pub authoritative: bool,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+    pub resources: Vec<Record>,
+}
+
+pub struct Zone {
+    pub origin: String,
+    pub soa: Record,
+    // where dynamic updates are written, next to the zone file, so they
+    // outlive a restart; its first line is `$SERIAL` and the serial of the
+    // zone file it follows
+    pub journal: PathBuf,
+    base_serial: u32,
+    // bytes of the journal applied so far, and how many updates were
+    // written to it since the zone was loaded
+    replayed: u64,
+    generation: u64,
+    records: BTreeMap<String, Vec<Record>>,
+    // every owner plus the empty non-terminals between it and the origin
+    names: HashSet<String>,
+}
+
+// one change a dynamic update made, as it goes into the journal: `+` or `-`
+// and the record in presentation format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Delta {
+    Add(Record),
+    Remove(Record),
+}
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Delta::Add(record) => write!(f, "+ {}", record),
+            Delta::Remove(record) => write!(f, "- {}", record),
+        }
+    }
+}
+
+// `name` and every name between it and the origin
+fn add_names(names: &mut HashSet<String>, domain: &str, origin: &str) {
+    let mut name = domain;
+    while names.insert(name.to_string()) && name != origin {
+        name = match name.find('.') {
+            Some(i) => &name[i + 1..],
+            None => "",
+        };
+    }
+}
+
+impl Zone {
+    pub fn load(path: &Path) -> Result<Zone> {
+        let parsed = parse(path)?;
+        let file = path.display();
+        let mut soas = parsed.iter().filter(|record| matches!(record, Record::SOA { .. }));
+        let soa = soas.next().ok_or(format!("{}: zone has no SOA record", file))?.clone();
+        if soas.next().is_some() {
+            return Err(format!("{}: zone has more than one SOA record", file).into());
+        }
+        let origin = soa.domain().to_string();
+        let base_serial = match soa {
+            Record::SOA { serial, .. } => serial,
+            _ => unreachable!(),
+        };
+        let mut records: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        let mut names = HashSet::new();
+        for record in parsed {
+            let domain = record.domain().to_string();
+            if !in_zone(&domain, &origin) {
+                return Err(format!("{}: `{}` is outside of zone `{}`", file, domain, origin).into());
+            }
+            add_names(&mut names, &domain, &origin);
+            let rrset = records.entry(domain).or_default();
+            if !rrset.contains(&record) {
+                rrset.push(record);
+            }
+        }
+        for (name, rrset) in &records {
+            let cname = rrset.iter().any(|record| matches!(record, Record::CNAME { .. }));
+            if cname && rrset.len() > 1 {
+                return Err(format!("{}: `{}` has a CNAME next to other data", file, name).into());
+            }
+        }
+        let mut zone = Zone {
+            origin,
+            soa,
+            journal: PathBuf::from(format!("{}.jnl", path.display())),
+            base_serial,
+            replayed: 0,
+            generation: 0,
+            records,
+            names,
+        };
+        zone.replay()?;
+        Ok(zone)
+    }
+
+    // brings the zone up to date with the updates made since its file was
+    // loaded, or since the last replay; a journal that starts from another
+    // serial belongs to an older version of the file and is refused rather
+    // than half applied
+    fn replay(&mut self) -> Result<()> {
+        let file = self.journal.display().to_string();
+        let content = match fs::read_to_string(&self.journal) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("{}: {}", file, e).into()),
+        };
+        let (done, new) = content.split_at_checked(self.replayed as usize)
+            .ok_or(format!("{}: journal is shorter than what was replayed from it", file))?;
+        // a line still being written is left for the next replay
+        let new = &new[..new.rfind('\n').map_or(0, |i| i + 1)];
+        let skipped = done.lines().count();
+        let mut lines = new.lines().enumerate().map(|(i, text)| (i + skipped, text));
+        if self.replayed == 0 {
+            let Some((_, header)) = lines.next() else { return Ok(()) };
+            match header.strip_prefix("$SERIAL").and_then(|serial| serial.trim().parse::<u32>().ok()) {
+                Some(base) if base == self.base_serial => {}
+                Some(base) => return Err(format!("{}:1: journal starts from serial {} and doesn't follow serial {} of the zone file", file, base, self.base_serial).into()),
+                None => return Err(format!("{}:1: expected `$SERIAL` and the serial of the zone file", file).into()),
+            }
+        }
+        for (i, text) in lines {
+            let at = format!("{}:{}", file, i + 1);
+            let (add, text) = match text.split_at_checked(1) {
+                Some(("+", text)) => (true, text),
+                Some(("-", text)) => (false, text),
+                _ if text.trim().is_empty() || text.starts_with(';') => continue,
+                _ => return Err(format!("{}: expected `+` or `-`", at).into()),
+            };
+            let mut lines = tokenize(text.trim_start(), &at)?;
+            let line = match (lines.pop(), lines.is_empty()) {
+                (Some(line), true) => line,
+                _ => return Err(format!("{}: expected one record", at).into()),
+            };
+            let mut parser = Parser {
+                origin: String::new(),
+                default_ttl: None,
+                last_ttl: None,
+                last_owner: None,
+                records: Vec::new(),
+            };
+            let record = parser.parse_record(line).map_err(|e| format!("{}: {}", at, e))?;
+            if let Record::SOA { serial, .. } = self.soa {
+                if !add && matches!(record, Record::SOA { .. }) && record != self.soa {
+                    return Err(format!("{}: journal doesn't follow serial {} of the zone file", at, serial).into());
+                }
+            }
+            self.apply(&if add { Delta::Add(record) } else { Delta::Remove(record) });
+        }
+        self.replayed += new.len() as u64;
+        Ok(())
+    }
+
+    // the records owned by `name`
+    pub fn records(&self, name: &str) -> &[Record] {
+        self.records.get(name).map(|rrset| rrset.as_slice()).unwrap_or(&[])
+    }
+
+    // deltas are taken as given; working out which ones an update calls for
+    // is up to the caller
+    pub fn apply(&mut self, delta: &Delta) {
+        match delta {
+            Delta::Add(record) => {
+                if let Record::SOA { .. } = record {
+                    self.soa = record.clone();
+                }
+                add_names(&mut self.names, record.domain(), &self.origin);
+                let rrset = self.records.entry(record.domain().to_string()).or_default();
+                if !rrset.contains(record) {
+                    rrset.push(record.clone());
+                }
+            }
+            Delta::Remove(record) => {
+                let Some(rrset) = self.records.get_mut(record.domain()) else { return };
+                rrset.retain(|other| other != record);
+                if rrset.is_empty() {
+                    self.records.remove(record.domain());
+                    self.names.clear();
+                    for name in self.records.keys() {
+                        add_names(&mut self.names, name, &self.origin);
+                    }
+                }
+            }
+        }
+    }
+
+    // appends one update's deltas to the journal, on disk before the update
+    // is acknowledged
+    pub fn write_journal(&mut self, deltas: &[Delta]) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.journal)
+            .map_err(|e| format!("{}: {}", self.journal.display(), e))?;
+        // cut off a torn line a crash left after what was replayed
+        if file.metadata()?.len() > self.replayed {
+            file.set_len(self.replayed)?;
+        }
+        let mut text = String::new();
+        if self.replayed == 0 {
+            text.push_str(&format!("$SERIAL {}\n", self.base_serial));
+        }
+        text.extend(deltas.iter().map(|delta| format!("{}\n", delta)));
+        file.write_all(text.as_bytes())?;
+        file.sync_data()?;
+        self.replayed += text.len() as u64;
+        self.generation += 1;
+        Ok(())
+    }
+
+    pub fn answer(&self, qname: &str, qtype: QueryType) -> Answer {
+        let mut answer = Answer {
+            rescode: ResultCode::NOERROR,
+            authoritative: true,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            resources: Vec::new(),
+        };
+        let mut name = qname.to_string();
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(cut) = self.delegation(&name) {
+                // below a zone cut we only know where to send the client
+                if answer.answers.is_empty() {
+                    answer.authoritative = false;
+                    for record in cut {
+                        if let Record::NS { host, .. } = record {
+                            answer.resources.extend(self.addresses(host));
+                        }
+                    }
+                    answer.authorities.extend(cut.iter().cloned());
+                }
+                return answer;
+            }
+            let rrset = match self.records.get(&name) {
+                Some(rrset) => rrset,
+                None => {
+                    if !self.names.contains(&name) {
+                        answer.rescode = ResultCode::NXDOMAIN;
+                    }
+                    answer.authorities.push(self.negative_soa());
+                    return answer;
+                }
+            };
+            let matching: Vec<Record> = rrset.iter()
+                .filter(|record| qtype == QueryType::UNKNOWN(QTYPE_ANY) || record.qtype() == qtype)
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                for record in &matching {
+                    match record {
+                        Record::NS { host, .. } | Record::MX { host, .. } | Record::SRV { host, .. } => answer.resources.extend(self.addresses(host)),
+                        _ => {}
+                    }
+                }
+                answer.answers.extend(matching);
+                return answer;
+            }
+            let target = rrset.iter().find_map(|record| match record {
+                Record::CNAME { host, .. } => Some(host.clone()),
+                _ => None,
+            });
+            match target {
+                Some(target) => {
+                    answer.answers.extend(rrset.iter().cloned());
+                    // names outside the zone are left for the client to chase
+                    if !in_zone(&target, &self.origin) {
+                        return answer;
+                    }
+                    name = target;
+                }
+                None => {
+                    answer.authorities.push(self.negative_soa());
+                    return answer;
+                }
+            }
+        }
+        answer
+    }
+
+    // the topmost NS set between the origin and `name`, if any
+    fn delegation(&self, name: &str) -> Option<&Vec<Record>> {
+        let mut cut = None;
+        let mut current = name;
+        while current != self.origin && in_zone(current, &self.origin) {
+            let rrset = self.records.get(current);
+            if rrset.map(|rrset| rrset.iter().any(|record| matches!(record, Record::NS { .. }))).unwrap_or(false) {
+                cut = rrset;
+            }
+            current = match current.find('.') {
+                Some(i) => &current[i + 1..],
+                None => "",
+            };
+        }
+        cut
+    }
+
+    fn addresses(&self, host: &str) -> Vec<Record> {
+        self.records.get(host)
+            .map(|rrset| rrset.iter().filter(|record| matches!(record, Record::A { .. } | Record::AAAA { .. })).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // negative answers are cached for the smaller of the SOA ttl and its
+    // minimum field (RFC 2308)
+    fn negative_soa(&self) -> Record {
+        let mut soa = self.soa.clone();
+        if let Record::SOA { ref mut ttl, minimum, .. } = soa {
+            *ttl = (*ttl).min(minimum);
+        }
+        soa
+    }
+}
+
+pub struct Zones {
+    pub paths: Vec<PathBuf>,
+    zones: Vec<Zone>,
+}
+
+impl Zones {
+    pub fn load(paths: Vec<PathBuf>) -> Result<Zones> {
+        let mut zones = Vec::new();
+        for path in &paths {
+            let zone = Zone::load(path)?;
+            if zones.iter().any(|other: &Zone| other.origin == zone.origin) {
+                return Err(format!("{}: zone `{}` is already loaded", path.display(), zone.origin).into());
+            }
+            zones.push(zone);
+        }
+        Ok(Zones { paths, zones })
+    }
+
+    // how many updates each journal has taken, to tell which zones changed
+    // while a reload was reading the files
+    pub fn generations(&self) -> HashMap<PathBuf, u64> {
+        self.zones.iter().map(|zone| (zone.journal.clone(), zone.generation)).collect()
+    }
+
+    // replays the updates `current` took since `seen` on top of these zones,
+    // which were loaded meanwhile; the journals are read past where each
+    // zone's load left off, so updates the load already saw aren't repeated
+    pub fn catch_up(&mut self, current: &Zones, seen: &HashMap<PathBuf, u64>) -> Result<()> {
+        let moved = current.generations().into_iter()
+            .filter(|(journal, generation)| seen.get(journal) != Some(generation))
+            .map(|(journal, _)| journal)
+            .collect::<HashSet<_>>();
+        for zone in &mut self.zones {
+            if moved.contains(&zone.journal) {
+                zone.replay()?;
+            }
+        }
+        Ok(())
+    }
+
+    // the zone whose origin is exactly `origin`
+    pub fn get_mut(&mut self, origin: &str) -> Option<&mut Zone> {
+        self.zones.iter_mut().find(|zone| zone.origin == origin)
+    }
+
+    // the most specific zone containing `qname`
+    pub fn find(&self, qname: &str) -> Option<&Zone> {
+        self.zones.iter()
+            .filter(|zone| in_zone(qname, &zone.origin))
+            .max_by_key(|zone| zone.origin.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+}
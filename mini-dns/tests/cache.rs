@@ -0,0 +1,111 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use mini_dns::cache::{Cache, Key};
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::record::Record;
+use mini_dns::utils::ResultCode;
+
+fn answer(name: &str, ttl: u32) -> Packet {
+    let mut packet = Packet::new();
+    packet.answers.push(Record::A {
+        domain: name.to_string(),
+        addr: Ipv4Addr::new(192, 0, 2, 1),
+        ttl,
+    });
+    packet
+}
+
+fn negative(rescode: ResultCode, ttl: u32, minimum: u32) -> Packet {
+    let mut packet = Packet::new();
+    packet.header.rescode = rescode;
+    packet.authorities.push(Record::SOA {
+        domain: "example.com".to_string(),
+        m_name: "ns.example.com".to_string(),
+        r_name: "hostmaster.example.com".to_string(),
+        serial: 1,
+        refresh: 3600,
+        retry: 600,
+        expire: 86400,
+        minimum,
+        ttl,
+    });
+    packet.authorities.push(Record::NS {
+        domain: "example.com".to_string(),
+        host: "ns.example.com".to_string(),
+        ttl: 3600,
+    });
+    packet
+}
+
+fn secs(n: u64) -> Duration {
+    Duration::from_secs(n)
+}
+
+#[test]
+fn ttls_count_down_until_expiry() {
+    let cache = Cache::new(16);
+    let now = Instant::now();
+    let key = Key::new("WWW.example.com", QueryType::A, 1);
+    cache.insert(key.clone(), &answer("www.example.com", 300), now);
+    let cached = cache.get(&Key::new("www.example.com", QueryType::A, 1), now + secs(100)).unwrap();
+    assert_eq!(cached.answers[0].ttl(), 200);
+    assert!(cache.get(&Key::new("www.example.com", QueryType::AAAA, 1), now).is_none());
+    assert!(cache.get(&Key::new("www.example.com", QueryType::A, 3), now).is_none());
+    assert!(cache.get(&key, now + secs(300)).is_none());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn negative_answers_use_the_soa_minimum() {
+    let cache = Cache::new(16);
+    let now = Instant::now();
+    let nxdomain = Key::new("missing.example.com", QueryType::A, 1);
+    cache.insert(nxdomain.clone(), &negative(ResultCode::NXDOMAIN, 3600, 60), now);
+    let cached = cache.get(&nxdomain, now + secs(59)).unwrap();
+    assert_eq!(cached.header.rescode, ResultCode::NXDOMAIN);
+    assert_eq!(cached.authorities.len(), 1);
+    assert_eq!(cached.authorities[0].ttl(), 1);
+    assert!(cache.get(&nxdomain, now + secs(60)).is_none());
+
+    let nodata = Key::new("www.example.com", QueryType::MX, 1);
+    cache.insert(nodata.clone(), &negative(ResultCode::NOERROR, 30, 600), now);
+    assert!(cache.get(&nodata, now + secs(29)).unwrap().answers.is_empty());
+    assert!(cache.get(&nodata, now + secs(30)).is_none());
+}
+
+#[test]
+fn uncacheable_responses_are_skipped() {
+    let cache = Cache::new(16);
+    let now = Instant::now();
+    let mut servfail = answer("a.example.com", 300);
+    servfail.header.rescode = ResultCode::SERVFAIL;
+    cache.insert(Key::new("a.example.com", QueryType::A, 1), &servfail, now);
+    // NODATA without a SOA says nothing about how long it holds
+    cache.insert(Key::new("b.example.com", QueryType::A, 1), &Packet::new(), now);
+    cache.insert(Key::new("c.example.com", QueryType::A, 1), &answer("c.example.com", 0), now);
+    assert!(cache.is_empty());
+    let disabled = Cache::new(0);
+    disabled.insert(Key::new("d.example.com", QueryType::A, 1), &answer("d.example.com", 300), now);
+    assert!(disabled.is_empty());
+}
+
+#[test]
+fn least_recently_used_entries_are_evicted() {
+    let cache = Cache::new(2);
+    let now = Instant::now();
+    let key = |name: &str| Key::new(name, QueryType::A, 1);
+    cache.insert(key("a"), &answer("a", 300), now);
+    cache.insert(key("b"), &answer("b", 300), now);
+    assert!(cache.get(&key("a"), now).is_some());
+    cache.insert(key("c"), &answer("c", 300), now);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&key("b"), now).is_none());
+    assert!(cache.get(&key("a"), now).is_some());
+    // expired entries go before live ones
+    cache.insert(key("d"), &answer("d", 10), now);
+    cache.insert(key("e"), &answer("e", 300), now + secs(20));
+    assert!(cache.get(&key("a"), now + secs(20)).is_some());
+    assert!(cache.get(&key("e"), now + secs(20)).is_some());
+}
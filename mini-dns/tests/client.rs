@@ -0,0 +1,264 @@
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::client::{self, Client};
+use mini_dns::edns::EdnsOption;
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::question::Question;
+use mini_dns::record::Record;
+use mini_dns::transport;
+use mini_dns::utils::ResultCode;
+use mini_dns::zone::Zones;
+
+#[test]
+fn records_print_in_presentation_format() {
+    let cases = [
+        (Record::A { domain: "www.example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300 }, "www.example.com.\t300\tIN\tA\t192.0.2.1"),
+        (Record::MX { domain: "example.com".to_string(), priority: 10, host: "mail.example.com".to_string(), ttl: 3600 }, "example.com.\t3600\tIN\tMX\t10 mail.example.com."),
+        (
+            Record::TXT { domain: "example.com".to_string(), data: vec![b"v=spf1 -all".to_vec(), b"say \"hi\"\n".to_vec()], ttl: 60 },
+            "example.com.\t60\tIN\tTXT\t\"v=spf1 -all\" \"say \\\"hi\\\"\\010\"",
+        ),
+        (
+            Record::SOA {
+                domain: String::new(),
+                m_name: "a.root-servers.net".to_string(),
+                r_name: "nstld.verisign-grs.com".to_string(),
+                serial: 2024010100,
+                refresh: 1800,
+                retry: 900,
+                expire: 604800,
+                minimum: 86400,
+                ttl: 86400,
+            },
+            ".\t86400\tIN\tSOA\ta.root-servers.net. nstld.verisign-grs.com. 2024010100 1800 900 604800 86400",
+        ),
+        (
+            Record::DS { domain: "example.com".to_string(), key_tag: 370, algorithm: 13, digest_type: 2, digest: vec![0xBE, 0xEF, 0x01], ttl: 3600 },
+            "example.com.\t3600\tIN\tDS\t370 13 2 BEEF01",
+        ),
+        (
+            Record::RRSIG {
+                domain: "example.com".to_string(),
+                type_covered: 1,
+                algorithm: 13,
+                labels: 2,
+                original_ttl: 300,
+                expiration: 1700000000,
+                inception: 951782400,
+                key_tag: 370,
+                signer_name: "example.com".to_string(),
+                signature: vec![1, 2, 3, 4],
+                ttl: 300,
+            },
+            "example.com.\t300\tIN\tRRSIG\tA 13 2 300 20231114221320 20000229000000 370 example.com. AQIDBA==",
+        ),
+        (
+            Record::NSEC { domain: "a.example.com".to_string(), next_domain: "b.example.com".to_string(), types: vec![1, 46, 47, 65280], ttl: 60 },
+            "a.example.com.\t60\tIN\tNSEC\tb.example.com. A RRSIG NSEC TYPE65280",
+        ),
+        (
+            Record::CAA { domain: "example.com".to_string(), flags: 0, tag: b"issue".to_vec(), value: b"ca.example.net".to_vec(), ttl: 300 },
+            "example.com.\t300\tIN\tCAA\t0 issue \"ca.example.net\"",
+        ),
+        (Record::UNKNOWN { domain: "example.com".to_string(), qtype: 65280, class: 1, data: vec![0x0A, 0xFF], ttl: 5 }, "example.com.\t5\tIN\tTYPE65280\t\\# 2 0AFF"),
+    ];
+    for (record, text) in cases {
+        assert_eq!(record.to_string(), text);
+    }
+}
+
+#[test]
+fn type_names_round_trip() {
+    for qtype in [QueryType::A, QueryType::AAAA, QueryType::NSEC3, QueryType::CAA, QueryType::UNKNOWN(65280)] {
+        assert_eq!(QueryType::from_name(&qtype.to_string()), Some(qtype));
+    }
+    assert_eq!(QueryType::from_name("mx"), Some(QueryType::MX));
+    assert_eq!(QueryType::from_name("type28"), Some(QueryType::AAAA));
+    assert_eq!(QueryType::from_name("BOGUS"), None);
+    assert_eq!(QueryType::from_name("TYPE70000"), None);
+}
+
+#[test]
+fn messages_lay_out_like_dig() {
+    let mut packet = Packet::new();
+    packet.header.id = 4242;
+    packet.header.response = true;
+    packet.header.recursion_desired = true;
+    packet.header.recursion_available = true;
+    packet.header.rescode = ResultCode::NXDOMAIN;
+    packet.questions.push(Question::new("nope.example.com".to_string(), QueryType::AAAA));
+    packet.authorities.push(Record::SOA {
+        domain: "example.com".to_string(),
+        m_name: "ns.example.com".to_string(),
+        r_name: "hostmaster.example.com".to_string(),
+        serial: 1,
+        refresh: 1800,
+        retry: 900,
+        expire: 604800,
+        minimum: 60,
+        ttl: 60,
+    });
+    packet.resources.push(Record::OPT {
+        packet_len: 1232,
+        ext_rcode: 0,
+        version: 0,
+        dnssec_ok: true,
+        options: vec![EdnsOption::Cookie { client: vec![1; 8], server: Vec::new() }],
+    });
+    assert_eq!(client::format(&packet), "\
+;; ->>HEADER<<- opcode: QUERY, status: NXDOMAIN, id: 4242
+;; flags: qr rd ra; QUERY: 1, ANSWER: 0, AUTHORITY: 1, ADDITIONAL: 1
+
+;; OPT PSEUDOSECTION:
+; EDNS: version: 0, flags: do; udp: 1232
+; COOKIE: 0101010101010101
+
+;; QUESTION SECTION:
+;nope.example.com.\t\tIN\tAAAA
+
+;; AUTHORITY SECTION:
+example.com.\t60\tIN\tSOA\tns.example.com. hostmaster.example.com. 1 1800 900 604800 60
+");
+}
+
+// echoes each query back as its answer, so the test sees what was sent
+fn echo(request: Packet) -> Packet {
+    let mut response = request.clone();
+    response.header.response = true;
+    let domain = request.questions[0].name.clone();
+    response.answers.push(Record::A { domain, addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300 });
+    response
+}
+
+#[test]
+fn queries_go_over_udp_or_tcp() {
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = udp.local_addr().unwrap();
+    let tcp = TcpListener::bind(server).unwrap();
+    let (sent, seen) = mpsc::channel();
+    let udp_sent = sent.clone();
+    thread::spawn(move || loop {
+        let mut req_buffer = BytePacketBuffer::new();
+        let (_, src) = udp.recv_from(&mut req_buffer.buf).unwrap();
+        let request = Packet::from_buffer(&mut req_buffer).unwrap();
+        udp_sent.send(("udp", request.clone())).unwrap();
+        let res_buffer = transport::encode(&mut echo(request), transport::MAX_UDP_SIZE).unwrap();
+        udp.send_to(&res_buffer.buf[0..res_buffer.pos()], src).unwrap();
+    });
+    thread::spawn(move || {
+        let (stream, _) = tcp.accept().unwrap();
+        transport::serve_messages(stream, |request| {
+            sent.send(("tcp", request.clone())).unwrap();
+            echo(request)
+        })
+        .unwrap();
+    });
+
+    let mut client = Client::new(server);
+    client.dnssec_ok = true;
+    client.bufsize = 4096;
+    let (response, _) = client.query("www.example.com.", QueryType::A).unwrap();
+    assert_eq!(response.answers[0].domain(), "www.example.com");
+    let (transport, request) = seen.recv().unwrap();
+    assert_eq!(transport, "udp");
+    assert!(request.header.recursion_desired);
+    assert!(matches!(request.edns(), Some(Record::OPT { packet_len: 4096, dnssec_ok: true, .. })));
+
+    client.tcp = true;
+    client.dnssec_ok = false;
+    client.edns = false;
+    client.recursion_desired = false;
+    client.query("mail.example.com", QueryType::MX).unwrap();
+    let (transport, request) = seen.recv().unwrap();
+    assert_eq!(transport, "tcp");
+    assert!(!request.header.recursion_desired);
+    assert!(request.edns().is_none());
+    assert_eq!(request.questions, vec![Question::new("mail.example.com".to_string(), QueryType::MX)]);
+}
+
+// a root, `com` and `example.com`, each on its own loopback address
+const ZONES: &[(&str, &str)] = &[
+    ("127.0.0.20", "
+. 3600 IN SOA a.root-servers.net. hostmaster.root. 1 1800 900 604800 300
+. 3600 IN NS a.root-servers.net.
+a.root-servers.net. 3600 IN A 127.0.0.20
+com. 3600 IN NS ns.com.
+ns.com. 3600 IN A 127.0.0.21
+"),
+    ("127.0.0.21", "
+$ORIGIN com.
+$TTL 3600
+@ SOA ns hostmaster 1 1800 900 604800 300
+@ NS ns
+ns A 127.0.0.21
+example NS ns1.example
+ns1.example A 127.0.0.22
+"),
+    ("127.0.0.22", "
+$ORIGIN example.com.
+$TTL 300
+@ SOA ns1 hostmaster 1 1800 900 604800 60
+@ NS ns1
+ns1 A 127.0.0.22
+www A 192.0.2.10
+"),
+];
+
+fn serve(socket: UdpSocket, zones: Zones) {
+    loop {
+        let mut req_buffer = BytePacketBuffer::new();
+        let Ok((_, src)) = socket.recv_from(&mut req_buffer.buf) else { continue };
+        let Ok(request) = Packet::from_buffer(&mut req_buffer) else { continue };
+        let mut packet = Packet::new();
+        packet.header.id = request.header.id;
+        packet.header.response = true;
+        packet.header.recursion_desired = request.header.recursion_desired;
+        let question = request.questions[0].clone();
+        let answer = zones.find(&question.name).unwrap().answer(&question.name, question.qtype);
+        packet.header.authoritative_answer = answer.authoritative;
+        packet.header.rescode = answer.rescode;
+        packet.answers = answer.answers;
+        packet.authorities = answer.authorities;
+        packet.resources = answer.resources;
+        packet.questions.push(question);
+        let res_buffer = transport::encode(&mut packet, transport::MAX_UDP_SIZE).unwrap();
+        let _ = socket.send_to(&res_buffer.buf[0..res_buffer.pos()], src);
+    }
+}
+
+#[test]
+fn trace_follows_each_referral() {
+    let dir = env::temp_dir().join(format!("mini-dns-client-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut port = 0;
+    for (i, (addr, content)) in ZONES.iter().enumerate() {
+        let path: PathBuf = dir.join(format!("{}.zone", i));
+        fs::write(&path, content).unwrap();
+        let zones = Zones::load(vec![path]).unwrap();
+        let socket = UdpSocket::bind((*addr, port)).unwrap();
+        port = socket.local_addr().unwrap().port();
+        thread::spawn(move || serve(socket, zones));
+    }
+    let root: SocketAddr = ("127.0.0.20".parse::<Ipv4Addr>().unwrap(), port).into();
+    let mut client = Client::new(root);
+    client.port = port;
+    client.timeout = Duration::from_millis(500);
+    let mut hops = Vec::new();
+    let response = client.trace("WWW.example.com", QueryType::A, |response, server, _| {
+        hops.push((server.ip().to_string(), response.header.recursion_desired));
+    }).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(response.answers.as_slice(), [Record::A { addr, .. }] if *addr == Ipv4Addr::new(192, 0, 2, 10)));
+    // the roots come from the server asked, with RD; every step after is
+    // asked without it
+    let hops: Vec<(&str, bool)> = hops.iter().map(|(ip, rd)| (ip.as_str(), *rd)).collect();
+    assert_eq!(hops, vec![("127.0.0.20", true), ("127.0.0.20", false), ("127.0.0.21", false), ("127.0.0.22", false)]);
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+use mini_dns::dnssec::{self, Status, Validator};
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::record::Record;
+use mini_dns::utils::{Result, ResultCode};
+
+const ED25519: u8 = 15;
+
+struct Key {
+    pair: Ed25519KeyPair,
+    dnskey: Record,
+}
+
+fn key(zone: &str) -> Key {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let dnskey = Record::DNSKEY {
+        domain: zone.to_string(),
+        flags: 257,
+        protocol: 3,
+        algorithm: ED25519,
+        public_key: pair.public_key().as_ref().to_vec(),
+        ttl: 3600,
+    };
+    Key { pair, dnskey }
+}
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32
+}
+
+fn sign_at(rrset: &[Record], key: &Key, inception: u32, expiration: u32) -> Record {
+    let owner = rrset[0].domain().to_string();
+    let mut sig = Record::RRSIG {
+        domain: owner.clone(),
+        type_covered: rrset[0].qtype().to_num(),
+        algorithm: ED25519,
+        // a wildcard's own asterisk isn't counted
+        labels: owner.split('.').filter(|label| !label.is_empty() && *label != "*").count() as u8,
+        original_ttl: rrset[0].ttl(),
+        expiration,
+        inception,
+        key_tag: dnssec::key_tag_of(&key.dnskey).unwrap(),
+        signer_name: key.dnskey.domain().to_string(),
+        signature: Vec::new(),
+        ttl: rrset[0].ttl(),
+    };
+    let signed = key.pair.sign(&dnssec::signed_data(&sig, rrset).unwrap());
+    if let Record::RRSIG { ref mut signature, .. } = sig {
+        *signature = signed.as_ref().to_vec();
+    }
+    sig
+}
+
+fn sign(rrset: &[Record], key: &Key) -> Record {
+    sign_at(rrset, key, now() - 3600, now() + 3600)
+}
+
+fn ds(key: &Key) -> Record {
+    let mut ds = Record::DS {
+        domain: key.dnskey.domain().to_string(),
+        key_tag: dnssec::key_tag_of(&key.dnskey).unwrap(),
+        algorithm: ED25519,
+        digest_type: 2,
+        digest: vec![0; 32],
+        ttl: 3600,
+    };
+    // the digest covers the owner name and the key's rdata
+    let mut data = Vec::new();
+    for label in key.dnskey.domain().split('.').filter(|label| !label.is_empty()) {
+        data.push(label.len() as u8);
+        data.extend_from_slice(label.as_bytes());
+    }
+    data.push(0);
+    if let Record::DNSKEY { flags, protocol, algorithm, ref public_key, .. } = key.dnskey {
+        data.extend_from_slice(&flags.to_be_bytes());
+        data.push(protocol);
+        data.push(algorithm);
+        data.extend_from_slice(public_key);
+    }
+    if let Record::DS { ref mut digest, .. } = ds {
+        *digest = ring::digest::digest(&ring::digest::SHA256, &data).as_ref().to_vec();
+    }
+    ds
+}
+
+fn soa(zone: &str) -> Record {
+    Record::SOA {
+        domain: zone.to_string(),
+        m_name: format!("ns.{}", zone).trim_end_matches('.').to_string(),
+        r_name: format!("hostmaster.{}", zone).trim_end_matches('.').to_string(),
+        serial: 1,
+        refresh: 7200,
+        retry: 900,
+        expire: 1209600,
+        minimum: 300,
+        ttl: 300,
+    }
+}
+
+fn nsec(owner: &str, next: &str, types: &[QueryType]) -> Record {
+    Record::NSEC {
+        domain: owner.to_string(),
+        next_domain: next.to_string(),
+        types: types.iter().map(|qtype| qtype.to_num()).collect(),
+        ttl: 300,
+    }
+}
+
+fn a(name: &str, last: u8) -> Record {
+    Record::A {
+        domain: name.to_string(),
+        addr: Ipv4Addr::new(192, 0, 2, last),
+        ttl: 300,
+    }
+}
+
+fn answer(answers: Vec<Record>) -> Packet {
+    let mut packet = Packet::new();
+    packet.header.response = true;
+    packet.answers = answers;
+    packet
+}
+
+fn negative(rescode: ResultCode, authorities: Vec<Record>) -> Packet {
+    let mut packet = answer(Vec::new());
+    packet.header.rescode = rescode;
+    packet.authorities = authorities;
+    packet
+}
+
+// a signed root, a signed `example` below it and an unsigned `insecure`
+struct World {
+    root: Key,
+    example: Key,
+    responses: HashMap<(String, u16), Packet>,
+}
+
+impl World {
+    fn new() -> World {
+        let root = key("");
+        let example = key("example");
+        let mut responses = HashMap::new();
+        let mut add = |name: &str, qtype: QueryType, packet: Packet| {
+            responses.insert((name.to_string(), qtype.to_num()), packet);
+        };
+        add("", QueryType::DNSKEY, answer(vec![root.dnskey.clone(), sign(std::slice::from_ref(&root.dnskey), &root)]));
+        add("example", QueryType::DS, answer(vec![ds(&example), sign(&[ds(&example)], &root)]));
+        add("example", QueryType::DNSKEY, answer(vec![example.dnskey.clone(), sign(std::slice::from_ref(&example.dnskey), &example)]));
+        let ns = [QueryType::NS, QueryType::SOA, QueryType::DNSKEY, QueryType::NSEC, QueryType::RRSIG];
+        let apex = nsec("example", "www.example", &ns);
+        let www = nsec("www.example", "example", &[QueryType::A, QueryType::NSEC, QueryType::RRSIG]);
+        add("www.example", QueryType::DS, negative(ResultCode::NOERROR, vec![soa("example"), sign(&[soa("example")], &example), www.clone(), sign(&[www], &example)]));
+        add("nope.example", QueryType::DS, negative(ResultCode::NXDOMAIN, vec![apex.clone(), sign(&[apex], &example)]));
+        let delegation = nsec("insecure", "zzz", &[QueryType::NS, QueryType::NSEC, QueryType::RRSIG]);
+        add("insecure", QueryType::DS, negative(ResultCode::NOERROR, vec![soa(""), sign(&[soa("")], &root), delegation.clone(), sign(&[delegation], &root)]));
+        World {
+            root,
+            example,
+            responses,
+        }
+    }
+
+    fn validator(&self) -> Validator {
+        Validator::new(vec![ds(&self.root)]).unwrap()
+    }
+
+    fn validate(&self, qname: &str, qtype: QueryType, response: &Packet) -> Status {
+        let fetch = |name: &str, qtype: QueryType| -> Result<Packet> {
+            self.responses.get(&(name.to_string(), qtype.to_num())).cloned().ok_or_else(|| format!("no response for {} {:?}", name, qtype).into())
+        };
+        self.validator().validate(qname, qtype, response, &fetch)
+    }
+}
+
+#[test]
+fn signed_answers_are_secure() {
+    let world = World::new();
+    let response = answer(vec![a("www.example", 1), a("www.example", 2), sign(&[a("www.example", 2), a("www.example", 1)], &world.example)]);
+    assert_eq!(world.validate("www.example", QueryType::A, &response), Status::Secure);
+}
+
+#[test]
+fn tampered_answers_are_bogus() {
+    let world = World::new();
+    let response = answer(vec![a("www.example", 66), sign(&[a("www.example", 1)], &world.example)]);
+    assert!(matches!(world.validate("www.example", QueryType::A, &response), Status::Bogus(_)));
+    // signed by a key that isn't in the chain
+    let response = answer(vec![a("www.example", 1), sign(&[a("www.example", 1)], &key("example"))]);
+    assert!(matches!(world.validate("www.example", QueryType::A, &response), Status::Bogus(_)));
+}
+
+#[test]
+fn missing_signatures_are_bogus() {
+    let world = World::new();
+    let response = answer(vec![a("www.example", 1)]);
+    assert!(matches!(world.validate("www.example", QueryType::A, &response), Status::Bogus(_)));
+}
+
+#[test]
+fn expired_signatures_are_bogus() {
+    let world = World::new();
+    let response = answer(vec![a("www.example", 1), sign_at(&[a("www.example", 1)], &world.example, now() - 7200, now() - 3600)]);
+    match world.validate("www.example", QueryType::A, &response) {
+        Status::Bogus(reason) => assert!(reason.contains("validity"), "{}", reason),
+        status => panic!("{:?}", status),
+    }
+}
+
+#[test]
+fn unsigned_delegations_are_insecure() {
+    let world = World::new();
+    let response = answer(vec![a("www.insecure", 1)]);
+    assert_eq!(world.validate("www.insecure", QueryType::A, &response), Status::Insecure);
+}
+
+#[test]
+fn denials_need_proof() {
+    let world = World::new();
+    let apex = nsec("example", "www.example", &[QueryType::NS, QueryType::SOA, QueryType::DNSKEY, QueryType::NSEC, QueryType::RRSIG]);
+    let proven = negative(ResultCode::NXDOMAIN, vec![
+        soa("example"),
+        sign(&[soa("example")], &world.example),
+        apex.clone(),
+        sign(&[apex], &world.example),
+    ]);
+    assert_eq!(world.validate("nope.example", QueryType::A, &proven), Status::Secure);
+    let unproven = negative(ResultCode::NXDOMAIN, vec![soa("example"), sign(&[soa("example")], &world.example)]);
+    assert!(matches!(world.validate("nope.example", QueryType::A, &unproven), Status::Bogus(_)));
+    // NODATA: www.example exists but has no MX
+    let www = nsec("www.example", "example", &[QueryType::A, QueryType::NSEC, QueryType::RRSIG]);
+    let nodata = negative(ResultCode::NOERROR, vec![soa("example"), sign(&[soa("example")], &world.example), www.clone(), sign(&[www], &world.example)]);
+    assert_eq!(world.validate("www.example", QueryType::MX, &nodata), Status::Secure);
+    assert!(matches!(world.validate("www.example", QueryType::A, &nodata), Status::Bogus(_)));
+}
+
+#[test]
+fn wildcard_answers_need_the_name_denied() {
+    let world = World::new();
+    let mut sig = sign(&[a("*.example", 1)], &world.example);
+    if let Record::RRSIG { ref mut domain, .. } = sig {
+        *domain = "host.example".to_string();
+    }
+    let mut response = answer(vec![a("host.example", 1), sig]);
+    assert!(matches!(world.validate("host.example", QueryType::A, &response), Status::Bogus(_)));
+    let apex = nsec("example", "www.example", &[QueryType::NS, QueryType::SOA, QueryType::DNSKEY, QueryType::NSEC, QueryType::RRSIG]);
+    response.authorities = vec![apex.clone(), sign(&[apex], &world.example)];
+    assert_eq!(world.validate("host.example", QueryType::A, &response), Status::Secure);
+}
+
+#[test]
+fn nsec3_hashes_match_rfc_5155() {
+    // RFC 5155 appendix A: H(example) with salt aabbccdd and 12 iterations
+    let world = World::new();
+    let nsec3 = Record::NSEC3 {
+        domain: "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example".to_string(),
+        hash_algorithm: 1,
+        flags: 0,
+        iterations: 12,
+        salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+        next_hashed: vec![0; 20],
+        types: vec![QueryType::NS.to_num(), QueryType::SOA.to_num(), QueryType::DNSKEY.to_num(), QueryType::RRSIG.to_num()],
+        ttl: 300,
+    };
+    let nodata = negative(ResultCode::NOERROR, vec![soa("example"), sign(&[soa("example")], &world.example), nsec3.clone(), sign(&[nsec3], &world.example)]);
+    assert_eq!(world.validate("example", QueryType::MX, &nodata), Status::Secure);
+}
+
+#[test]
+fn keys_and_digests_match_rfc_4034() {
+    // RFC 4034 5.4
+    let dnskey = Record::DNSKEY {
+        domain: "dskey.example.com".to_string(),
+        flags: 256,
+        protocol: 3,
+        algorithm: 5,
+        public_key: base64("AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw=="),
+        ttl: 86400,
+    };
+    assert_eq!(dnssec::key_tag_of(&dnskey).unwrap(), 60485);
+    let ds = Record::DS {
+        domain: "dskey.example.com".to_string(),
+        key_tag: 60485,
+        algorithm: 5,
+        digest_type: 1,
+        digest: hex("2BB183AF5F22588179A53B0A98631FAD1A292118"),
+        ttl: 86400,
+    };
+    assert!(dnssec::ds_matches(&ds, &dnskey));
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+}
+
+fn base64(text: &str) -> Vec<u8> {
+    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut bytes = Vec::new();
+    let mut bits: u32 = 0;
+    let mut count = 0;
+    for c in text.bytes().filter(|c| *c != b'=') {
+        bits = ((bits << 6) | alphabet.iter().position(|a| *a == c).unwrap() as u32) & 0xFFFF;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    bytes
+}
@@ -0,0 +1,135 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::edns::EdnsOption;
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::question::Question;
+use mini_dns::record::Record;
+use mini_dns::transport;
+use mini_dns::utils::ResultCode;
+
+fn round_trip(packet: &mut Packet) -> Packet {
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    buffer.buf.truncate(buffer.pos());
+    buffer.seek(0).unwrap();
+    Packet::from_buffer(&mut buffer).unwrap()
+}
+
+fn opt(options: Vec<EdnsOption>) -> Record {
+    Record::OPT {
+        packet_len: 1232,
+        ext_rcode: 0,
+        version: 0,
+        dnssec_ok: true,
+        options,
+    }
+}
+
+#[test]
+fn opt_records_round_trip() {
+    let mut packet = Packet::new();
+    packet.questions.push(Question::new("example.com".to_string(), QueryType::A));
+    packet.resources.push(opt(vec![
+        EdnsOption::ClientSubnet {
+            source_prefix: 24,
+            scope_prefix: 0,
+            addr: IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)),
+        },
+        EdnsOption::ClientSubnet {
+            source_prefix: 56,
+            scope_prefix: 48,
+            addr: IpAddr::V6("2001:db8:1:2300::".parse::<Ipv6Addr>().unwrap()),
+        },
+        EdnsOption::Cookie {
+            client: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            server: vec![9; 16],
+        },
+        EdnsOption::Unknown {
+            code: 65001,
+            data: vec![0xde, 0xad],
+        },
+    ]));
+    let parsed = round_trip(&mut packet.clone());
+    assert_eq!(parsed.resources, packet.resources);
+    assert_eq!(parsed.edns(), packet.edns());
+}
+
+#[test]
+fn subnet_bits_past_the_prefix_are_cleared() {
+    let mut packet = Packet::new();
+    packet.resources.push(opt(vec![EdnsOption::ClientSubnet {
+        source_prefix: 20,
+        scope_prefix: 0,
+        addr: IpAddr::V4(Ipv4Addr::new(198, 51, 255, 255)),
+    }]));
+    let parsed = round_trip(&mut packet);
+    assert_eq!(parsed.resources[0], opt(vec![EdnsOption::ClientSubnet {
+        source_prefix: 20,
+        scope_prefix: 0,
+        addr: IpAddr::V4(Ipv4Addr::new(198, 51, 240, 0)),
+    }]));
+}
+
+#[test]
+fn extended_rcodes_use_the_opt_record() {
+    let mut packet = Packet::new();
+    packet.header.response = true;
+    packet.header.rescode = ResultCode::BADVERS;
+    packet.resources.push(opt(Vec::new()));
+    let parsed = round_trip(&mut packet);
+    assert_eq!(parsed.header.rescode, ResultCode::BADVERS);
+    assert!(matches!(parsed.resources[0], Record::OPT { ext_rcode: 1, .. }));
+}
+
+#[test]
+fn unknown_rcodes_are_not_read_as_noerror() {
+    let mut packet = Packet::new();
+    packet.header.response = true;
+    packet.header.rescode = ResultCode::Other(11);
+    let parsed = round_trip(&mut packet);
+    assert_eq!(parsed.header.rescode, ResultCode::Other(11));
+
+    // BADCOOKIE, whose upper bits only the OPT record carries
+    packet.header.rescode = ResultCode::Other(23);
+    packet.resources.push(opt(Vec::new()));
+    let parsed = round_trip(&mut packet);
+    assert_eq!(parsed.header.rescode, ResultCode::Other(23));
+    assert!(matches!(parsed.resources[0], Record::OPT { ext_rcode: 1, .. }));
+    assert_eq!(parsed.header.rescode.to_string(), "23");
+}
+
+#[test]
+fn bad_cookies_are_rejected() {
+    let mut packet = Packet::new();
+    packet.resources.push(opt(vec![EdnsOption::Cookie {
+        client: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        server: vec![1, 2],
+    }]));
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    buffer.seek(0).unwrap();
+    assert!(Packet::from_buffer(&mut buffer).is_err());
+}
+
+#[test]
+fn buffers_grow_past_512_bytes() {
+    let mut packet = Packet::new();
+    packet.header.response = true;
+    packet.questions.push(Question::new("big.example.com".to_string(), QueryType::TXT));
+    packet.answers.push(Record::TXT {
+        domain: "big.example.com".to_string(),
+        data: vec![vec![b'x'; 250]; 12],
+        ttl: 60,
+    });
+    packet.resources.push(opt(Vec::new()));
+    let parsed = round_trip(&mut packet.clone());
+    assert_eq!(parsed.answers, packet.answers);
+    // still too big for the client's payload size
+    let mut buffer = transport::encode(&mut packet, 1232).unwrap();
+    buffer.seek(0).unwrap();
+    let truncated = Packet::from_buffer(&mut buffer).unwrap();
+    assert!(truncated.header.truncated_message);
+    assert!(truncated.edns().is_some());
+}
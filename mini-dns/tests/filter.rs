@@ -0,0 +1,118 @@
+use std::env;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+use mini_dns::filter::Filter;
+use mini_dns::query_type::QueryType;
+use mini_dns::record::Record;
+use mini_dns::utils::ResultCode;
+
+fn write(name: &str, content: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("mini-dns-filter-{}-{}", name, std::process::id()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+const HOSTS: &str = "# a hosts file
+127.0.0.1 localhost
+::1       localhost ip6-localhost
+0.0.0.0   ads.example.com tracker.example.net.   # two to a line
+0.0.0.0   Pixel.Example.org
+";
+
+const DOMAINS: &str = "
+telemetry.example.com
+*.doubleclick.example
+";
+
+#[test]
+fn both_list_formats_block() {
+    let hosts = write("hosts", HOSTS);
+    let domains = write("domains", DOMAINS);
+    let filter = Filter::load(vec![hosts.clone(), domains.clone()], Vec::new(), Vec::new()).unwrap();
+    fs::remove_file(hosts).unwrap();
+    fs::remove_file(domains).unwrap();
+    for name in ["ads.example.com", "tracker.example.net", "pixel.example.org", "telemetry.example.com"] {
+        assert!(filter.is_blocked(name), "{}", name);
+    }
+    // hosts entries are exact names, and the machine's own aren't blocked
+    assert!(!filter.is_blocked("www.ads.example.com"));
+    assert!(!filter.is_blocked("localhost"));
+    // a wildcard takes in every name below its domain, but not the domain
+    assert!(filter.is_blocked("ad.doubleclick.example"));
+    assert!(filter.is_blocked("a.b.doubleclick.example"));
+    assert!(!filter.is_blocked("doubleclick.example"));
+    assert!(!filter.is_blocked("notdoubleclick.example"));
+    assert_eq!(filter.len(), 5);
+}
+
+#[test]
+fn blocked_names_are_nxdomain_or_sinkholed() {
+    let domains = write("sinkhole", DOMAINS);
+    let filter = Filter::load(vec![domains.clone()], Vec::new(), Vec::new()).unwrap();
+    let answer = filter.answer("telemetry.example.com", QueryType::A).unwrap();
+    assert_eq!(answer.rescode, ResultCode::NXDOMAIN);
+    assert!(filter.answer("www.example.com", QueryType::A).is_none());
+    let sinkhole = vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)];
+    let filter = Filter::load(vec![domains.clone()], Vec::new(), sinkhole).unwrap();
+    fs::remove_file(domains).unwrap();
+    let answer = filter.answer("telemetry.example.com", QueryType::A).unwrap();
+    assert_eq!(answer.rescode, ResultCode::NOERROR);
+    assert_eq!(answer.answers, vec![Record::A { domain: "telemetry.example.com".to_string(), addr: Ipv4Addr::UNSPECIFIED, ttl: 60 }]);
+    let answer = filter.answer("ad.doubleclick.example", QueryType::AAAA).unwrap();
+    assert!(matches!(answer.answers.as_slice(), [Record::AAAA { addr, .. }] if *addr == Ipv6Addr::UNSPECIFIED));
+    // other types get no data rather than going upstream
+    let answer = filter.answer("telemetry.example.com", QueryType::MX).unwrap();
+    assert_eq!(answer.rescode, ResultCode::NOERROR);
+    assert!(answer.answers.is_empty());
+}
+
+#[test]
+fn overrides_answer_before_blocklists() {
+    let overrides = write("overrides", "$TTL 300
+nas.home.         A     192.168.1.10
+nas.home.         AAAA  fd00::10
+files.home.       CNAME nas.home.
+mail.home.        CNAME mail.example.com.
+ads.example.com.  A     192.168.1.1
+");
+    let hosts = write("hosts-overridden", HOSTS);
+    let filter = Filter::load(vec![hosts.clone()], vec![overrides.clone()], Vec::new()).unwrap();
+    fs::remove_file(overrides).unwrap();
+    fs::remove_file(hosts).unwrap();
+    let answer = filter.answer("nas.home", QueryType::AAAA).unwrap();
+    assert!(matches!(answer.answers.as_slice(), [Record::AAAA { .. }]));
+    let answer = filter.answer("files.home", QueryType::A).unwrap();
+    assert!(matches!(answer.answers.as_slice(), [Record::CNAME { .. }, Record::A { addr, .. }] if *addr == Ipv4Addr::new(192, 168, 1, 10)));
+    // a CNAME out of the overrides is left for the caller to follow
+    let answer = filter.answer("mail.home", QueryType::A).unwrap();
+    assert!(matches!(answer.answers.as_slice(), [Record::CNAME { host, .. }] if host == "mail.example.com"));
+    let answer = filter.answer("ads.example.com", QueryType::A).unwrap();
+    assert_eq!(answer.rescode, ResultCode::NOERROR);
+    assert!(matches!(answer.answers.as_slice(), [Record::A { addr, .. }] if *addr == Ipv4Addr::new(192, 168, 1, 1)));
+}
+
+#[test]
+fn overrides_take_only_address_records() {
+    let overrides = write("bad-overrides", "home. 300 MX 10 mail.home.\n");
+    let e = Filter::load(Vec::new(), vec![overrides.clone()], Vec::new()).err().unwrap();
+    fs::remove_file(overrides).unwrap();
+    assert!(e.to_string().contains("only take A, AAAA and CNAME"), "{}", e);
+    let list = write("bad-list", "ads.example.com tracker.example.com\n");
+    let e = Filter::load(vec![list.clone()], Vec::new(), Vec::new()).err().unwrap();
+    fs::remove_file(list).unwrap();
+    assert!(e.to_string().ends_with(":1: expected a name or a hosts file entry"), "{}", e);
+}
+
+#[test]
+fn reloading_picks_up_changes() {
+    let list = write("reload", "ads.example.com\n");
+    let filter = Filter::load(vec![list.clone()], Vec::new(), Vec::new()).unwrap();
+    assert!(filter.is_blocked("ads.example.com"));
+    fs::write(&list, "tracker.example.com\n").unwrap();
+    let filter = Filter::load(filter.blocklists.clone(), filter.overrides.clone(), filter.sinkhole.clone()).unwrap();
+    fs::remove_file(list).unwrap();
+    assert!(!filter.is_blocked("ads.example.com"));
+    assert!(filter.is_blocked("tracker.example.com"));
+}
@@ -0,0 +1,359 @@
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::question::Question;
+use mini_dns::record::Record;
+use mini_dns::utils::{Error, ResultCode};
+use mini_dns::zone;
+
+fn encode(packet: &mut Packet) -> BytePacketBuffer {
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    buffer.buf.truncate(buffer.pos());
+    buffer.seek(0).unwrap();
+    buffer
+}
+
+fn name(s: &str) -> String {
+    s.to_string()
+}
+
+fn text(s: &str) -> Vec<u8> {
+    s.as_bytes().to_vec()
+}
+
+fn sample() -> Packet {
+    let mut packet = Packet::new();
+    packet.header.id = 0xbeef;
+    packet.header.response = true;
+    packet.header.authoritative_answer = true;
+    packet.header.rescode = ResultCode::NXDOMAIN;
+    packet.questions.push(Question::new(name("www.example.com"), QueryType::A));
+    packet.answers = vec![
+        Record::CNAME { domain: name("www.example.com"), host: name("web.example.com"), ttl: 300 },
+        Record::A { domain: name("web.example.com"), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 60 },
+        Record::A { domain: name("web.example.com"), addr: Ipv4Addr::new(192, 0, 2, 2), ttl: 60 },
+        Record::AAAA { domain: name("web.example.com"), addr: "2001:db8::1".parse::<Ipv6Addr>().unwrap(), ttl: 60 },
+        Record::MX { domain: name("example.com"), priority: 10, host: name("mail.example.com"), ttl: 3600 },
+        Record::TXT { domain: name("example.com"), data: vec![text("v=spf1 -all"), text(""), text("second")], ttl: 3600 },
+        Record::PTR { domain: name("1.2.0.192.in-addr.arpa"), host: name("web.example.com"), ttl: 300 },
+        Record::HINFO { domain: name("web.example.com"), cpu: text("x86_64"), os: text("Linux"), ttl: 300 },
+        Record::SRV { domain: name("_sip._udp.example.com"), priority: 10, weight: 60, port: 5060, host: name("sip.example.com"), ttl: 300 },
+        Record::NAPTR {
+            domain: name("example.com"),
+            order: 100,
+            preference: 10,
+            flags: text("S"),
+            services: text("SIP+D2U"),
+            regexp: text(""),
+            replacement: name("_sip._udp.example.com"),
+            ttl: 300,
+        },
+        Record::CAA { domain: name("example.com"), flags: 128, tag: text("issue"), value: text("ca.example.net; account=230123"), ttl: 300 },
+        Record::DS { domain: name("example.com"), key_tag: 60485, algorithm: 8, digest_type: 2, digest: vec![0xab; 32], ttl: 86400 },
+        Record::DNSKEY { domain: name("example.com"), flags: 257, protocol: 3, algorithm: 8, public_key: vec![3, 1, 0, 1, 0xc3, 0x5d], ttl: 3600 },
+        Record::RRSIG {
+            domain: name("example.com"),
+            type_covered: 48,
+            algorithm: 8,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 1735689600,
+            inception: 1733097600,
+            key_tag: 60485,
+            signer_name: name("example.com"),
+            signature: vec![0x5a; 64],
+            ttl: 3600,
+        },
+        Record::NSEC { domain: name("example.com"), next_domain: name("www.example.com"), types: vec![1, 2, 6, 46, 47, 48, 257, 65280], ttl: 300 },
+        Record::NSEC3 {
+            domain: name("0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example.com"),
+            hash_algorithm: 1,
+            flags: 1,
+            iterations: 12,
+            salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+            next_hashed: vec![0x2d; 20],
+            types: vec![1, 46],
+            ttl: 300,
+        },
+        Record::UNKNOWN { domain: name("example.com"), qtype: 65280, class: 1, data: vec![0, 1, 2, 0xff], ttl: 300 },
+        Record::UNKNOWN { domain: name("empty.example.com"), qtype: 65281, class: 1, data: Vec::new(), ttl: 300 },
+    ];
+    packet.authorities = vec![
+        Record::SOA {
+            domain: name("example.com"),
+            m_name: name("ns1.example.com"),
+            r_name: name("hostmaster.example.com"),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+        },
+        Record::NS { domain: name("example.com"), host: name("ns1.example.com"), ttl: 3600 },
+        Record::NS { domain: name("example.com"), host: name("ns2.example.org"), ttl: 3600 },
+    ];
+    packet.resources = vec![
+        Record::A { domain: name("ns1.example.com"), addr: Ipv4Addr::new(192, 0, 2, 53), ttl: 3600 },
+        Record::OPT { packet_len: 1232, ext_rcode: 0, version: 0, dnssec_ok: false, options: Vec::new() },
+    ];
+    packet
+}
+
+#[test]
+fn every_record_survives_a_round_trip() {
+    let mut packet = sample();
+    let parsed = Packet::from_buffer(&mut encode(&mut packet)).unwrap();
+    assert_eq!(parsed.header.id, 0xbeef);
+    assert_eq!(parsed.header.rescode, ResultCode::NXDOMAIN);
+    assert!(parsed.header.authoritative_answer);
+    assert_eq!(parsed.questions, packet.questions);
+    assert_eq!(parsed.answers, packet.answers);
+    assert_eq!(parsed.authorities, packet.authorities);
+    assert_eq!(parsed.resources, packet.resources);
+}
+
+#[test]
+fn repeated_names_are_compressed() {
+    let mut many = Packet::new();
+    many.questions.push(Question::new(name("a-rather-long-label.example.com"), QueryType::A));
+    for i in 0..25 {
+        many.answers.push(Record::A { domain: name("a-rather-long-label.example.com"), addr: Ipv4Addr::new(192, 0, 2, i), ttl: 60 });
+    }
+    let buffer = encode(&mut many);
+    // header, question and 25 records of pointer, fixed fields and address;
+    // spelled out in full they would need over 1200 bytes
+    assert_eq!(buffer.buf.len(), 12 + 33 + 4 + 25 * (2 + 10 + 4));
+    assert!(buffer.buf.len() <= 512);
+    assert_eq!(Packet::from_buffer(&mut encode(&mut many)).unwrap().answers, many.answers);
+}
+
+#[test]
+fn deep_names_point_at_each_other() {
+    let mut packet = Packet::new();
+    let mut domain = name("example.com");
+    for i in 0..20 {
+        domain = format!("l{}.{}", i, domain);
+        packet.answers.push(Record::CNAME { domain: domain.clone(), host: format!("x.{}", domain), ttl: 60 });
+    }
+    let parsed = Packet::from_buffer(&mut encode(&mut packet)).unwrap();
+    assert_eq!(parsed.answers, packet.answers);
+}
+
+#[test]
+fn names_compare_without_case() {
+    let mut packet = Packet::new();
+    packet.answers.push(Record::NS { domain: name("Example.COM"), host: name("ns.example.com"), ttl: 60 });
+    let buffer = encode(&mut packet);
+    // the host's suffix points at the owner name written as is
+    assert_eq!(&buffer.buf[12..25], b"\x07Example\x03COM\x00");
+    let parsed = Packet::from_buffer(&mut encode(&mut packet)).unwrap();
+    assert!(matches!(&parsed.answers[0], Record::NS { domain, host, .. } if domain == "example.com" && host == "ns.example.com"));
+}
+
+#[test]
+fn pointers_must_point_backwards() {
+    let mut buffer = BytePacketBuffer::with_size(4);
+    buffer.buf.copy_from_slice(&[0xC0, 0x02, 0xC0, 0x00]);
+    assert!(buffer.read_qname(&mut String::new()).is_err());
+    let mut buffer = BytePacketBuffer::with_size(2);
+    buffer.buf.copy_from_slice(&[0xC0, 0x00]);
+    assert!(buffer.read_qname(&mut String::new()).is_err());
+}
+
+#[test]
+fn bad_labels_are_refused() {
+    let mut buffer = BytePacketBuffer::new();
+    assert!(buffer.write_qname(&"a".repeat(64)).is_err());
+    assert!(buffer.write_qname("empty..label").is_err());
+    assert!(buffer.write_qname(&["a"; 130].join(".")).is_err());
+    assert!(buffer.write_qname(&"a".repeat(63)).is_ok());
+}
+
+#[test]
+fn unknown_types_pass_through_verbatim() {
+    // a forwarded SVCB record (type 64), which has no variant of its own
+    let rdata = [0, 1, 0, 0, 1, 0, 3, 2, b'h', b'2'];
+    let mut wire = vec![0xbe, 0xef, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0];
+    wire.extend_from_slice(b"\x03svc\x07example\x03com\x00\x00\x40\x00\x01\x00\x00\x0e\x10");
+    wire.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    wire.extend_from_slice(&rdata);
+    let mut buffer = BytePacketBuffer::with_size(wire.len());
+    buffer.buf.copy_from_slice(&wire);
+    let mut packet = Packet::from_buffer(&mut buffer).unwrap();
+    assert_eq!(packet.answers, vec![Record::UNKNOWN { domain: name("svc.example.com"), qtype: 64, class: 1, data: rdata.to_vec(), ttl: 3600 }]);
+    assert_eq!(encode(&mut packet).buf, wire);
+}
+
+#[test]
+fn character_strings_keep_their_bytes() {
+    let mut packet = Packet::new();
+    packet.answers.push(Record::TXT { domain: name("example.com"), data: vec![vec![0xff, 0xfe, b'a'], vec![0xc3]], ttl: 60 });
+    packet.answers.push(Record::HINFO { domain: name("example.com"), cpu: vec![0x80], os: text("Linux"), ttl: 60 });
+    let mut buffer = encode(&mut packet);
+    buffer.seek(0).unwrap();
+    let parsed = Packet::from_buffer(&mut buffer).unwrap();
+    assert_eq!(parsed.answers, packet.answers);
+    assert_eq!(parsed.answers[0].to_string(), "example.com.\t60\tIN\tTXT\t\"\\255\\254a\" \"\\195\"");
+}
+
+#[test]
+fn other_classes_pass_through_verbatim() {
+    // a CHAOS version.bind answer
+    let mut wire = vec![0xbe, 0xef, 0x84, 0x00, 0, 0, 0, 1, 0, 0, 0, 0];
+    wire.extend_from_slice(b"\x07version\x04bind\x00\x00\x10\x00\x03\x00\x00\x00\x00\x00\x06\x05mini\xff");
+    let mut buffer = BytePacketBuffer::with_size(wire.len());
+    buffer.buf.copy_from_slice(&wire);
+    let mut packet = Packet::from_buffer(&mut buffer).unwrap();
+    assert_eq!(packet.answers, vec![Record::UNKNOWN { domain: name("version.bind"), qtype: 16, class: 3, data: b"\x05mini\xff".to_vec(), ttl: 0 }]);
+    assert_eq!(packet.answers[0].to_string(), "version.bind.\t0\tCH\tTXT\t\\# 6 056D696E69FF");
+    assert_eq!(encode(&mut packet).buf, wire);
+}
+
+#[test]
+fn zone_file_escapes_are_single_bytes() {
+    let path = env::temp_dir().join(format!("mini-dns-escapes-{}", std::process::id()));
+    fs::write(&path, "$ORIGIN example.com.\n$TTL 300\n@ IN SOA ns1 hostmaster 1 7200 900 1209600 300\n@ TXT \"\\255\\\"é\"\n").unwrap();
+    let records = zone::parse(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(records[1], Record::TXT { domain: name("example.com"), data: vec![vec![0xff, b'"', 0xc3, 0xa9]], ttl: 300 });
+}
+
+#[test]
+fn names_in_newer_types_are_not_compressed() {
+    let mut packet = Packet::new();
+    packet.answers.push(Record::SRV { domain: name("_sip._udp.example.com"), priority: 0, weight: 0, port: 5060, host: name("example.com"), ttl: 60 });
+    let buffer = encode(&mut packet);
+    assert!(buffer.buf.ends_with(b"\x13\xc4\x07example\x03com\x00"));
+}
+
+#[test]
+fn zone_files_take_the_new_types() {
+    let path = env::temp_dir().join(format!("mini-dns-types-{}", std::process::id()));
+    fs::write(&path, r#"$ORIGIN example.com.
+$TTL 300
+@      IN SOA   ns1 hostmaster 1 7200 900 1209600 300
+@         CAA   0 issue "ca.example.net"
+@         NAPTR 100 10 "S" "SIP+D2U" "" _sip._udp
+@         DS    60485 8 2 ABAB ABAB
+host      HINFO "x86_64" "Linux"
+_sip._udp SRV   10 60 5060 sip
+ptr       PTR   host
+a         TYPE1 \# 4 c0000201
+opaque    TYPE65280 \# 3 0102 03
+"#).unwrap();
+    let records = zone::parse(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(records[1..], [
+        Record::CAA { domain: name("example.com"), flags: 0, tag: text("issue"), value: text("ca.example.net"), ttl: 300 },
+        Record::NAPTR {
+            domain: name("example.com"),
+            order: 100,
+            preference: 10,
+            flags: text("S"),
+            services: text("SIP+D2U"),
+            regexp: text(""),
+            replacement: name("_sip._udp.example.com"),
+            ttl: 300,
+        },
+        Record::DS { domain: name("example.com"), key_tag: 60485, algorithm: 8, digest_type: 2, digest: vec![0xab; 4], ttl: 300 },
+        Record::HINFO { domain: name("host.example.com"), cpu: text("x86_64"), os: text("Linux"), ttl: 300 },
+        Record::SRV { domain: name("_sip._udp.example.com"), priority: 10, weight: 60, port: 5060, host: name("sip.example.com"), ttl: 300 },
+        Record::PTR { domain: name("ptr.example.com"), host: name("host.example.com"), ttl: 300 },
+        Record::A { domain: name("a.example.com"), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300 },
+        Record::UNKNOWN { domain: name("opaque.example.com"), qtype: 65280, class: 1, data: vec![1, 2, 3], ttl: 300 },
+    ]);
+}
+
+fn wire(bytes: &[u8]) -> BytePacketBuffer {
+    let mut buffer = BytePacketBuffer::with_size(bytes.len());
+    buffer.buf.copy_from_slice(bytes);
+    buffer
+}
+
+#[test]
+fn rdata_must_be_as_long_as_it_says() {
+    let mut good = vec![0xbe, 0xef, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0];
+    good.extend_from_slice(b"\x01a\x00\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\xc0\x00\x02\x01");
+    assert!(Packet::from_buffer(&mut wire(&good)).is_ok());
+    // an address with a byte to spare
+    let mut long = good.clone();
+    long[good.len() - 5] = 5;
+    long.push(0);
+    assert!(matches!(Packet::from_buffer(&mut wire(&long)), Err(Error::RdataLength { qtype: 1, declared: 5, used: 4 })));
+    // rdata running off the end of the message
+    let mut short = good.clone();
+    short.truncate(good.len() - 1);
+    assert!(matches!(Packet::from_buffer(&mut wire(&short)), Err(Error::EndOfBuffer)));
+}
+
+#[test]
+fn counts_must_fit_the_message() {
+    let header = [0xbe, 0xef, 0x81, 0x80, 0, 1, 0xff, 0xff, 0, 0, 0, 0];
+    assert!(matches!(Packet::from_buffer(&mut wire(&header)), Err(Error::BadCounts { records: 65536, len: 12 })));
+}
+
+#[test]
+fn extended_label_types_are_refused() {
+    assert!(matches!(wire(b"\x41a\x00").read_qname(&mut String::new()), Err(Error::LabelTooLong(65))));
+    let mut long = Vec::new();
+    for _ in 0..5 {
+        long.push(63);
+        long.extend_from_slice(&[b'a'; 63]);
+    }
+    long.push(0);
+    assert!(matches!(wire(&long).read_qname(&mut String::new()), Err(Error::NameTooLong(_))));
+}
+
+#[test]
+fn positions_stay_inside_the_buffer() {
+    let mut buffer = BytePacketBuffer::with_size(2);
+    assert!(buffer.set(1, 7).is_ok());
+    assert!(matches!(buffer.set(2, 7), Err(Error::EndOfBuffer)));
+    assert!(matches!(buffer.set_u16(1, 7), Err(Error::EndOfBuffer)));
+    assert!(buffer.seek(2).is_ok());
+    assert!(matches!(buffer.step(1), Err(Error::EndOfBuffer)));
+    assert!(matches!(buffer.get_range(1, usize::MAX), Err(Error::EndOfBuffer)));
+}
+
+#[test]
+fn malformed_queries_get_formerr() {
+    let query = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 3, b'w', b'w'];
+    let mut buffer = wire(&query);
+    assert!(Packet::from_buffer(&mut buffer).is_err());
+    let reply = Packet::format_error(&mut buffer).unwrap();
+    assert_eq!(reply.header.id, 0x1234);
+    assert!(reply.header.response && reply.header.recursion_desired);
+    assert_eq!(reply.header.rescode, ResultCode::FORMERR);
+    // nothing to answer without a whole header, and responses go unanswered
+    assert!(Packet::format_error(&mut wire(&query[..11])).is_none());
+    let mut response = query;
+    response[2] |= 0x80;
+    assert!(Packet::format_error(&mut wire(&response)).is_none());
+}
+
+// the fuzz target's check, run over mutations of a packet holding every type
+#[test]
+fn mangled_packets_never_panic() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let original = encode(&mut sample()).buf;
+    for _ in 0..20000 {
+        let mut bytes = original.clone();
+        for _ in 0..rng.gen_range(1..8) {
+            let i = rng.gen_range(0..bytes.len());
+            bytes[i] = rng.gen();
+        }
+        bytes.truncate(rng.gen_range(0..=bytes.len()));
+        if let Ok(mut packet) = Packet::from_buffer(&mut wire(&bytes)) {
+            let _ = packet.write(&mut BytePacketBuffer::new());
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::env;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::record::Record;
+use mini_dns::resolver::Resolver;
+use mini_dns::utils::ResultCode;
+use mini_dns::zone::Zones;
+
+// every stub server listens on its own loopback address, all on one port
+const ZONES: &[(&str, &str, &str)] = &[
+    ("127.0.0.2", "root", "
+. 3600 IN SOA a.root-servers.net. hostmaster.root. 1 1800 900 604800 300
+. 3600 IN NS a.root-servers.net.
+a.root-servers.net. 3600 IN A 127.0.0.2
+com. 3600 IN NS ns.com.
+ns.com. 3600 IN A 127.0.0.3
+net. 3600 IN NS ns.net.
+ns.net. 3600 IN A 127.0.0.5
+"),
+    ("127.0.0.3", "com", "
+$ORIGIN com.
+$TTL 3600
+@ SOA ns hostmaster 1 1800 900 604800 300
+@ NS ns
+ns A 127.0.0.3
+example NS ns1.example
+ns1.example A 127.0.0.4
+other NS ns.example.net.
+lame NS ns.lame
+ns.lame A 127.0.0.7
+"),
+    ("127.0.0.4", "example.com", "
+$ORIGIN example.com.
+$TTL 300
+@ SOA ns1 hostmaster 1 1800 900 604800 60
+@ NS ns1
+ns1 A 127.0.0.4
+www A 192.0.2.10
+alias CNAME www.example.net.
+loop CNAME loop2
+loop2 CNAME loop
+"),
+    ("127.0.0.5", "net", "
+$ORIGIN net.
+$TTL 3600
+@ SOA ns hostmaster 1 1800 900 604800 300
+@ NS ns
+ns A 127.0.0.5
+example NS ns.example
+ns.example A 127.0.0.6
+"),
+    ("127.0.0.6", "example.net", "
+$ORIGIN example.net.
+$TTL 300
+@ SOA ns hostmaster 1 1800 900 604800 60
+@ NS ns
+ns A 127.0.0.6
+www A 192.0.2.20
+"),
+    ("127.0.0.6", "other.com", "
+$ORIGIN other.com.
+$TTL 300
+@ SOA ns.example.net. hostmaster 1 1800 900 604800 60
+@ NS ns.example.net.
+host A 192.0.2.30
+"),
+];
+
+fn serve(socket: UdpSocket, zones: Zones) {
+    loop {
+        let mut req_buffer = BytePacketBuffer::new();
+        let (_, src) = match socket.recv_from(&mut req_buffer.buf) {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        let request = match Packet::from_buffer(&mut req_buffer) {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+        let mut packet = Packet::new();
+        packet.header.id = request.header.id;
+        packet.header.response = true;
+        packet.header.rescode = ResultCode::REFUSED;
+        if let Some(question) = request.questions.first() {
+            if let Some(zone) = zones.find(&question.name) {
+                let answer = zone.answer(&question.name, question.qtype);
+                packet.header.authoritative_answer = answer.authoritative;
+                packet.header.rescode = answer.rescode;
+                packet.answers = answer.answers;
+                packet.authorities = answer.authorities;
+                packet.resources = answer.resources;
+            }
+            packet.questions.push(question.clone());
+        }
+        let mut res_buffer = BytePacketBuffer::new();
+        if packet.write(&mut res_buffer).is_ok() {
+            let _ = socket.send_to(&res_buffer.buf[0..res_buffer.pos], src);
+        }
+    }
+}
+
+// starts the stub servers once per test binary and returns their port
+fn stubs() -> u16 {
+    static PORT: OnceLock<u16> = OnceLock::new();
+    *PORT.get_or_init(|| {
+        let dir = env::temp_dir().join(format!("mini-dns-resolver-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut port = 0;
+        let mut addrs: Vec<&str> = ZONES.iter().map(|(addr, _, _)| *addr).collect();
+        addrs.dedup();
+        for addr in addrs {
+            let paths: Vec<PathBuf> = ZONES.iter()
+                .filter(|(zone_addr, _, _)| *zone_addr == addr)
+                .map(|(_, name, content)| {
+                    let path = dir.join(format!("{}.zone", name));
+                    fs::write(&path, content).unwrap();
+                    path
+                })
+                .collect();
+            let zones = Zones::load(paths).unwrap();
+            let socket = UdpSocket::bind((addr, port)).unwrap();
+            port = socket.local_addr().unwrap().port();
+            thread::spawn(move || serve(socket, zones));
+        }
+        port
+    })
+}
+
+fn resolver() -> Resolver {
+    let mut resolver = Resolver::new(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))]);
+    resolver.port = stubs();
+    resolver.timeout = Duration::from_millis(200);
+    resolver
+}
+
+fn addresses(packet: &Packet) -> Vec<Ipv4Addr> {
+    packet.answers.iter()
+        .filter_map(|record| match *record {
+            Record::A { addr, .. } => Some(addr),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn follows_referrals_from_the_roots() {
+    let response = resolver().resolve("www.example.com", QueryType::A).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 10)]);
+}
+
+#[test]
+fn resolves_nameservers_without_glue() {
+    let response = resolver().resolve("host.other.com", QueryType::A).unwrap();
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 30)]);
+}
+
+#[test]
+fn chases_cnames_into_other_zones() {
+    let response = resolver().resolve("Alias.Example.COM", QueryType::A).unwrap();
+    assert!(matches!(&response.answers[0], Record::CNAME { domain, host, .. } if domain == "alias.example.com" && host == "www.example.net"));
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 20)]);
+}
+
+#[test]
+fn reports_missing_names() {
+    let response = resolver().resolve("missing.example.com", QueryType::A).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+    assert!(matches!(response.authorities[0], Record::SOA { .. }));
+}
+
+#[test]
+fn cname_loops_end() {
+    let response = resolver().resolve("loop.example.com", QueryType::A);
+    assert!(response.map(|response| addresses(&response).is_empty()).unwrap_or(true));
+}
+
+#[test]
+fn query_budget_is_enforced() {
+    let mut resolver = resolver();
+    resolver.max_queries = 2;
+    let e = resolver.resolve("www.example.com", QueryType::A).unwrap_err();
+    assert!(e.to_string().contains("budget"), "{}", e);
+    resolver.max_queries = 3;
+    assert!(resolver.resolve("www.example.com", QueryType::A).is_ok());
+}
+
+#[test]
+fn nesting_depth_is_enforced() {
+    let mut resolver = resolver();
+    resolver.max_depth = 0;
+    assert!(resolver.resolve("host.other.com", QueryType::A).is_err());
+    assert!(resolver.resolve("www.example.com", QueryType::A).is_ok());
+}
+
+#[test]
+fn unreachable_nameservers_fail() {
+    let e = resolver().resolve("www.lame.com", QueryType::A).unwrap_err();
+    assert!(e.to_string().contains("127.0.0.7"), "{}", e);
+}
+
+#[test]
+fn hints_come_from_a_master_file() {
+    let path = env::temp_dir().join(format!("mini-dns-hints-{}", std::process::id()));
+    fs::write(&path, ".  3600000  NS  A.ROOT-SERVERS.NET.\nA.ROOT-SERVERS.NET.  3600000  A  127.0.0.2\n").unwrap();
+    let resolver = Resolver::from_hints(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(resolver.roots, vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))]);
+    assert!(Resolver::from_hints(&path).is_err());
+}
@@ -0,0 +1,208 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConnection, StreamOwned};
+use rustls::crypto::ring;
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::doh;
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::question::Question;
+use mini_dns::record::Record;
+use mini_dns::tls;
+use mini_dns::transport;
+
+struct Pair {
+    cert: PathBuf,
+    key: PathBuf,
+    der: Vec<u8>,
+}
+
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.cert);
+        let _ = fs::remove_file(&self.key);
+    }
+}
+
+// a self-signed certificate for localhost, written out the way an operator
+// would hand one over
+fn pair(name: &str) -> Pair {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = env::temp_dir();
+    let pair = Pair {
+        cert: dir.join(format!("mini-dns-{}-{}.crt", name, std::process::id())),
+        key: dir.join(format!("mini-dns-{}-{}.key", name, std::process::id())),
+        der: generated.cert.der().to_vec(),
+    };
+    fs::write(&pair.cert, generated.cert.pem()).unwrap();
+    fs::write(&pair.key, generated.key_pair.serialize_pem()).unwrap();
+    pair
+}
+
+fn answer(mut request: Packet) -> Packet {
+    request.header.response = true;
+    request.resources.clear();
+    let domain = request.questions[0].name.clone();
+    request.answers.push(Record::A { domain, addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300 });
+    request
+}
+
+fn query(name: &str) -> Vec<u8> {
+    let mut packet = Packet::new();
+    packet.header.recursion_desired = true;
+    packet.questions.push(Question::new(name.to_string(), QueryType::A));
+    let buffer = transport::encode(&mut packet, transport::MAX_TCP_SIZE).unwrap();
+    buffer.buf[0..buffer.pos()].to_vec()
+}
+
+fn parse(wire: &[u8]) -> Packet {
+    let mut buffer = BytePacketBuffer::with_size(wire.len());
+    buffer.buf.copy_from_slice(wire);
+    Packet::from_buffer(&mut buffer).unwrap()
+}
+
+// a server taking one connection and serving it with `serve`
+fn listen<F>(pair: &Pair, alpn: &[u8], serve: F) -> SocketAddr
+where
+    F: FnOnce(StreamOwned<ServerConnection, TcpStream>) + Send + 'static,
+{
+    let config = tls::server_config(&pair.cert, &pair.key, alpn).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(StreamOwned::new(ServerConnection::new(config).unwrap(), stream));
+    });
+    addr
+}
+
+fn connect(pair: &Pair, addr: SocketAddr, alpn: &[u8]) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(pair.der.clone().into()).unwrap();
+    let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![alpn.to_vec()];
+    let connection = ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+    StreamOwned::new(connection, TcpStream::connect(addr).unwrap())
+}
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_response<R: BufRead>(stream: &mut R) -> HttpResponse {
+    let mut line = String::new();
+    stream.read_line(&mut line).unwrap();
+    let status = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line).unwrap();
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => headers.push((name.to_string(), value.trim().to_string())),
+            None => break,
+        }
+    }
+    let mut response = HttpResponse { status, headers, body: Vec::new() };
+    response.body = vec![0; response.header("content-length").unwrap().parse().unwrap()];
+    stream.read_exact(&mut response.body).unwrap();
+    response
+}
+
+#[test]
+fn dot_carries_framed_messages() {
+    let pair = pair("dot");
+    let addr = listen(&pair, tls::DOT_ALPN, |stream| transport::serve_messages(stream, answer).unwrap());
+    let mut stream = connect(&pair, addr, tls::DOT_ALPN);
+    for name in ["www.example.com", "mail.example.com"] {
+        let wire = query(name);
+        stream.write_all(&(wire.len() as u16).to_be_bytes()).unwrap();
+        stream.write_all(&wire).unwrap();
+        let mut buffer = transport::read_message(&mut stream).unwrap().unwrap();
+        let response = Packet::from_buffer(&mut buffer).unwrap();
+        assert_eq!(response.answers, vec![Record::A { domain: name.to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300 }]);
+    }
+    assert_eq!(stream.conn.alpn_protocol(), Some(tls::DOT_ALPN));
+}
+
+#[test]
+fn doh_takes_get_and_post() {
+    let pair = pair("doh");
+    let addr = listen(&pair, tls::DOH_ALPN, |stream| doh::serve(stream, answer).unwrap());
+    let mut stream = BufReader::new(connect(&pair, addr, tls::DOH_ALPN));
+    let encoded: String = {
+        // base64url by hand, padding dropped
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let wire = query("get.example.com");
+        let mut text = String::new();
+        for chunk in wire.chunks(3) {
+            let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+            for i in 0..=chunk.len() {
+                text.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        text
+    };
+    let get = format!("GET {}?dns={} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\n\r\n", doh::PATH, encoded, doh::MEDIA_TYPE);
+    stream.get_mut().write_all(get.as_bytes()).unwrap();
+    let response = read_response(&mut stream);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some(doh::MEDIA_TYPE));
+    assert_eq!(response.header("cache-control"), Some("max-age=300"));
+    assert_eq!(parse(&response.body).answers[0].domain(), "get.example.com");
+
+    let wire = query("post.example.com");
+    let post = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n", doh::PATH, doh::MEDIA_TYPE, wire.len());
+    stream.get_mut().write_all(post.as_bytes()).unwrap();
+    stream.get_mut().write_all(&wire).unwrap();
+    let response = read_response(&mut stream);
+    assert_eq!(response.status, 200);
+    assert_eq!(parse(&response.body).answers[0].domain(), "post.example.com");
+
+    // the connection stays usable after a refusal
+    let wrong_type = format!("POST {} HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi", doh::PATH);
+    stream.get_mut().write_all(wrong_type.as_bytes()).unwrap();
+    assert_eq!(read_response(&mut stream).status, 415);
+    stream.get_mut().write_all(b"GET /elsewhere HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut stream).status, 404);
+}
+
+#[test]
+fn rfc_8484_example_decodes() {
+    let wire = doh::base64url("AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB").unwrap();
+    let packet = parse(&wire);
+    assert_eq!(packet.header.id, 0);
+    assert!(packet.header.recursion_desired);
+    assert_eq!(packet.questions, vec![Question::new("www.example.com".to_string(), QueryType::A)]);
+    assert!(doh::base64url("AAAB=").is_none());
+    assert!(doh::base64url("A").is_none());
+}
+
+#[test]
+fn certificates_must_load() {
+    let pair = pair("missing");
+    let missing = env::temp_dir().join("mini-dns-no-such-key.pem");
+    let e = tls::server_config(&pair.cert, &missing, tls::DOT_ALPN).err().unwrap();
+    assert!(e.to_string().contains("mini-dns-no-such-key.pem"), "{}", e);
+    // a key in place of the certificate chain
+    let e = tls::server_config(&pair.key, &pair.key, tls::DOT_ALPN).err().unwrap();
+    assert!(e.to_string().contains("no certificates"), "{}", e);
+}
@@ -0,0 +1,144 @@
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::question::Question;
+use mini_dns::record::Record;
+use mini_dns::transport;
+
+fn reply(socket: &UdpSocket, mut packet: Packet, to: SocketAddr) {
+    packet.header.response = true;
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    socket.send_to(&buffer.buf[0..buffer.pos], to).unwrap();
+}
+
+#[test]
+fn replies_that_dont_match_are_ignored() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buffer = BytePacketBuffer::new();
+        let (_, src) = server.recv_from(&mut buffer.buf).unwrap();
+        let request = Packet::from_buffer(&mut buffer).unwrap();
+        let mut forged = request.clone();
+        forged.header.id = request.header.id.wrapping_add(1);
+        reply(&server, forged, src);
+        let mut other = request.clone();
+        other.questions[0].name = "elsewhere.example".to_string();
+        reply(&server, other, src);
+        // a different sender with the right id
+        let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        reply(&stranger, request.clone(), src);
+        let mut answer = request;
+        answer.header.recursion_available = true;
+        reply(&server, answer, src);
+    });
+    let question = Question::new("www.example.com".to_string(), QueryType::A);
+    let response = transport::exchange(addr, &question, true, false, Duration::from_secs(2)).unwrap();
+    assert!(response.header.recursion_available);
+    assert_eq!(response.questions, vec![question]);
+}
+
+#[test]
+fn silent_servers_time_out() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let question = Question::new("www.example.com".to_string(), QueryType::A);
+    let started = Instant::now();
+    let e = transport::exchange(server.local_addr().unwrap(), &question, true, false, Duration::from_millis(200)).unwrap_err();
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert!(e.to_string().contains("no reply"), "{}", e);
+}
+
+#[test]
+fn ids_differ_between_queries() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let question = Question::new("www.example.com".to_string(), QueryType::A);
+    let mut ids = Vec::new();
+    for _ in 0..8 {
+        let _ = transport::exchange(addr, &question, true, false, Duration::from_millis(1));
+        let mut buffer = BytePacketBuffer::new();
+        server.recv_from(&mut buffer.buf).unwrap();
+        ids.push(Packet::from_buffer(&mut buffer).unwrap().header.id);
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    assert!(ids.len() > 1);
+}
+
+fn big_answer(request: &Packet) -> Packet {
+    let mut packet = request.clone();
+    packet.header.response = true;
+    for i in 0..60 {
+        packet.answers.push(Record::A {
+            domain: request.questions[0].name.clone(),
+            addr: Ipv4Addr::new(192, 0, 2, i),
+            ttl: 300,
+        });
+    }
+    packet
+}
+
+#[test]
+fn large_responses_are_truncated_for_udp() {
+    let mut request = Packet::new();
+    request.questions.push(Question::new("big.example.com".to_string(), QueryType::A));
+    let mut packet = big_answer(&request);
+    let buffer = transport::encode(&mut packet.clone(), transport::MAX_TCP_SIZE).unwrap();
+    assert!(buffer.pos() > transport::MAX_UDP_SIZE);
+    let mut buffer = transport::encode(&mut packet, transport::MAX_UDP_SIZE).unwrap();
+    assert!(buffer.pos() <= transport::MAX_UDP_SIZE);
+    buffer.seek(0).unwrap();
+    let truncated = Packet::from_buffer(&mut buffer).unwrap();
+    assert!(truncated.header.truncated_message);
+    assert!(truncated.answers.is_empty());
+    assert_eq!(truncated.questions, request.questions);
+}
+
+#[test]
+fn truncated_replies_are_retried_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let udp = UdpSocket::bind(addr).unwrap();
+    thread::spawn(move || {
+        let mut buffer = BytePacketBuffer::new();
+        let (_, src) = udp.recv_from(&mut buffer.buf).unwrap();
+        let request = Packet::from_buffer(&mut buffer).unwrap();
+        let mut packet = big_answer(&request);
+        let buffer = transport::encode(&mut packet, transport::MAX_UDP_SIZE).unwrap();
+        udp.send_to(&buffer.buf[0..buffer.pos()], src).unwrap();
+    });
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buffer = transport::read_message(&mut stream).unwrap().unwrap();
+        let request = Packet::from_buffer(&mut buffer).unwrap();
+        let mut packet = big_answer(&request);
+        let buffer = transport::encode(&mut packet, transport::MAX_TCP_SIZE).unwrap();
+        transport::write_message(&mut stream, &buffer).unwrap();
+    });
+    let question = Question::new("big.example.com".to_string(), QueryType::A);
+    let response = transport::exchange(addr, &question, true, false, Duration::from_secs(2)).unwrap();
+    assert!(!response.header.truncated_message);
+    assert_eq!(response.answers.len(), 60);
+}
+
+#[test]
+fn messages_are_length_prefixed() {
+    let mut request = Packet::new();
+    request.questions.push(Question::new("example.com".to_string(), QueryType::A));
+    let buffer = transport::encode(&mut request, transport::MAX_TCP_SIZE).unwrap();
+    let mut wire = Vec::new();
+    transport::write_message(&mut wire, &buffer).unwrap();
+    transport::write_message(&mut wire, &buffer).unwrap();
+    assert_eq!(&wire[0..2], &(buffer.pos() as u16).to_be_bytes());
+    let mut reader = wire.as_slice();
+    for _ in 0..2 {
+        let mut message = transport::read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(Packet::from_buffer(&mut message).unwrap().questions, request.questions);
+    }
+    assert!(transport::read_message(&mut reader).unwrap().is_none());
+}
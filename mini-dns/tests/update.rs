@@ -0,0 +1,308 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::header::Header;
+use mini_dns::query_type::QueryType;
+use mini_dns::record::Record;
+use mini_dns::tsig::{self, Key, Tsig};
+use mini_dns::update::{self, Update};
+use mini_dns::utils::ResultCode;
+use mini_dns::zone::{Zone, Zones};
+
+const NONE: u16 = 254;
+const ANY: u16 = 255;
+
+const ZONE: &str = "$ORIGIN example.com.
+$TTL 300
+@    SOA ns hostmaster 2024010100 1800 900 604800 60
+@    NS  ns
+ns   A   192.0.2.53
+www  A   192.0.2.1
+www  A   192.0.2.2
+mail CNAME www
+";
+
+struct Files {
+    zone: PathBuf,
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.zone);
+        let _ = fs::remove_file(format!("{}.jnl", self.zone.display()));
+    }
+}
+
+fn zone(name: &str) -> (Files, Zone) {
+    let path = env::temp_dir().join(format!("mini-dns-update-{}-{}.zone", name, std::process::id()));
+    fs::write(&path, ZONE).unwrap();
+    let _ = fs::remove_file(format!("{}.jnl", path.display()));
+    let zone = Zone::load(&path).unwrap();
+    (Files { zone: path }, zone)
+}
+
+// a prerequisite or update record: with rdata it goes out as `record`
+// under `class`, without it as a bare name, type and class
+enum Entry {
+    Data(u16, Record),
+    Empty(&'static str, QueryType, u16),
+}
+
+fn a(name: &str, last: u8, ttl: u32) -> Record {
+    Record::A { domain: name.to_string(), addr: Ipv4Addr::new(192, 0, 2, last), ttl }
+}
+
+fn message(id: u16, zone: &str, prerequisites: &[Entry], updates: &[Entry]) -> BytePacketBuffer {
+    let mut buffer = BytePacketBuffer::uncompressed();
+    let mut header = Header::new();
+    header.id = id;
+    header.opcode = update::OPCODE_UPDATE;
+    header.questions = 1;
+    header.answers = prerequisites.len() as u16;
+    header.authoritative_entries = updates.len() as u16;
+    header.write(&mut buffer).unwrap();
+    buffer.write_qname(zone).unwrap();
+    buffer.write_u16(QueryType::SOA.to_num()).unwrap();
+    buffer.write_u16(1).unwrap();
+    for entry in prerequisites.iter().chain(updates) {
+        match entry {
+            Entry::Data(class, record) => {
+                let start = buffer.pos();
+                record.write(&mut buffer).unwrap();
+                buffer.set_u16(start + record.domain().len() + 2 + 2, *class).unwrap();
+            }
+            Entry::Empty(name, qtype, class) => {
+                buffer.write_qname(name).unwrap();
+                buffer.write_u16(qtype.to_num()).unwrap();
+                buffer.write_u16(*class).unwrap();
+                buffer.write_u32(0).unwrap();
+                buffer.write_u16(0).unwrap();
+            }
+        }
+    }
+    buffer.buf.truncate(buffer.pos());
+    buffer
+}
+
+fn parse(mut buffer: BytePacketBuffer) -> Update {
+    assert!(update::is_update(&buffer));
+    buffer.seek(0).unwrap();
+    Update::from_buffer(&mut buffer).unwrap()
+}
+
+fn serial(zone: &Zone) -> u32 {
+    match zone.soa {
+        Record::SOA { serial, .. } => serial,
+        _ => unreachable!(),
+    }
+}
+
+fn addresses(zone: &Zone, name: &str) -> Vec<Ipv4Addr> {
+    zone.records(name).iter()
+        .filter_map(|record| match *record {
+            Record::A { addr, .. } => Some(addr),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn updates_bump_the_serial_and_survive_a_reload() {
+    let (files, mut zone) = zone("journal");
+    let request = parse(message(1, "example.com", &[], &[
+        Entry::Data(1, a("WWW.example.com", 3, 300)),
+        Entry::Data(NONE, a("www.example.com", 1, 0)),
+        Entry::Data(1, a("new.host.example.com", 9, 60)),
+        Entry::Empty("mail.example.com", QueryType::CNAME, ANY),
+    ]));
+    assert_eq!(update::apply(&mut zone, &request).unwrap(), ResultCode::NOERROR);
+    assert_eq!(serial(&zone), 2024010101);
+    assert_eq!(addresses(&zone, "www.example.com"), vec![Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(192, 0, 2, 3)]);
+    assert_eq!(addresses(&zone, "new.host.example.com"), vec![Ipv4Addr::new(192, 0, 2, 9)]);
+    assert!(zone.records("mail.example.com").is_empty());
+    // `host` is an empty non-terminal now, so it has no data but exists
+    assert_eq!(zone.answer("host.example.com", QueryType::A).rescode, ResultCode::NOERROR);
+    assert_eq!(zone.answer("mail.example.com", QueryType::A).rescode, ResultCode::NXDOMAIN);
+
+    let journal = fs::read_to_string(&zone.journal).unwrap();
+    assert!(journal.starts_with("$SERIAL 2024010100\n"), "{}", journal);
+    assert_eq!(journal.lines().filter(|line| line.starts_with('+')).count(), 3);
+    assert_eq!(journal.lines().filter(|line| line.starts_with('-')).count(), 3);
+    assert!(journal.contains("- mail.example.com.\t300\tIN\tCNAME\twww.example.com.\n"), "{}", journal);
+
+    // a second update appends to the journal, and a reload replays it all
+    let request = parse(message(2, "example.com", &[], &[Entry::Data(1, a("www.example.com", 4, 300))]));
+    assert_eq!(update::apply(&mut zone, &request).unwrap(), ResultCode::NOERROR);
+    let reloaded = Zone::load(&files.zone).unwrap();
+    assert_eq!(serial(&reloaded), 2024010102);
+    assert_eq!(addresses(&reloaded, "www.example.com"), addresses(&zone, "www.example.com"));
+    assert_eq!(addresses(&reloaded, "new.host.example.com"), vec![Ipv4Addr::new(192, 0, 2, 9)]);
+    assert!(reloaded.records("mail.example.com").is_empty());
+
+    // a zone file edited under the journal doesn't silently lose updates
+    fs::write(&files.zone, ZONE.replace("2024010100", "2024020100")).unwrap();
+    let e = Zone::load(&files.zone).err().unwrap();
+    assert!(e.to_string().contains("journal starts from serial 2024010100 and doesn't follow serial 2024020100"), "{}", e);
+    // even when none of its lines touch the SOA
+    fs::write(&zone.journal, "$SERIAL 2024010100\n+ extra.example.com. 300 IN A 192.0.2.7\n").unwrap();
+    let e = Zone::load(&files.zone).err().unwrap();
+    assert!(e.to_string().contains("doesn't follow serial 2024020100"), "{}", e);
+    fs::write(&zone.journal, "+ extra.example.com. 300 IN A 192.0.2.7\n").unwrap();
+    let e = Zone::load(&files.zone).err().unwrap();
+    assert!(e.to_string().contains("expected `$SERIAL`"), "{}", e);
+    fs::write(&zone.journal, "$SERIAL 2024020100\n+ extra.example.com. 300 IN A 192.0.2.7\n").unwrap();
+    let reloaded = Zone::load(&files.zone).unwrap();
+    assert_eq!(addresses(&reloaded, "extra.example.com"), vec![Ipv4Addr::new(192, 0, 2, 7)]);
+}
+
+#[test]
+fn reloads_catch_up_with_updates_made_while_loading() {
+    let (files, _) = zone("reload");
+    let mut current = Zones::load(vec![files.zone.clone()]).unwrap();
+    let add = |id, last| parse(message(id, "example.com", &[], &[Entry::Data(1, a("www.example.com", last, 300))]));
+
+    // one update lands before the reload reads the journal, one after
+    let seen = current.generations();
+    assert_eq!(update::apply(current.get_mut("example.com").unwrap(), &add(1, 3)).unwrap(), ResultCode::NOERROR);
+    let mut reloaded = Zones::load(vec![files.zone.clone()]).unwrap();
+    assert_eq!(update::apply(current.get_mut("example.com").unwrap(), &add(2, 4)).unwrap(), ResultCode::NOERROR);
+    reloaded.catch_up(&current, &seen).unwrap();
+    let zone = reloaded.get_mut("example.com").unwrap();
+    assert_eq!(serial(zone), 2024010102);
+    assert_eq!(addresses(zone, "www.example.com"), addresses(current.get_mut("example.com").unwrap(), "www.example.com"));
+
+    // a line torn by a crash is left out, and cut off by the next update
+    let mut journal = OpenOptions::new().append(true).open(&zone.journal).unwrap();
+    journal.write_all(b"+ www.example.com. 300 IN A 192.0.2.").unwrap();
+    let mut zone = Zone::load(&files.zone).unwrap();
+    assert_eq!(serial(&zone), 2024010102);
+    assert_eq!(update::apply(&mut zone, &add(3, 5)).unwrap(), ResultCode::NOERROR);
+    let zone = Zone::load(&files.zone).unwrap();
+    assert_eq!(serial(&zone), 2024010103);
+    assert_eq!(addresses(&zone, "www.example.com").len(), 5);
+}
+
+#[test]
+fn prerequisites_must_hold() {
+    let (_files, mut zone) = zone("prerequisites");
+    let add = || Entry::Data(1, a("extra.example.com", 7, 300));
+    let cases = [
+        (Entry::Empty("nope.example.com", QueryType::UNKNOWN(255), ANY), ResultCode::NXDOMAIN),
+        (Entry::Empty("www.example.com", QueryType::UNKNOWN(255), NONE), ResultCode::YXDOMAIN),
+        (Entry::Empty("www.example.com", QueryType::AAAA, ANY), ResultCode::NXRRSET),
+        (Entry::Empty("www.example.com", QueryType::A, NONE), ResultCode::YXRRSET),
+        // a value dependent prerequisite has to name the whole RRset
+        (Entry::Data(1, a("www.example.com", 1, 0)), ResultCode::NXRRSET),
+        (Entry::Empty("www.example.org", QueryType::A, ANY), ResultCode::NOTZONE),
+        (Entry::Data(1, a("www.example.com", 1, 300)), ResultCode::FORMERR),
+    ];
+    for (prerequisite, rescode) in cases {
+        let request = parse(message(3, "example.com", &[prerequisite], &[add()]));
+        assert_eq!(update::apply(&mut zone, &request).unwrap(), rescode);
+    }
+    assert!(zone.records("extra.example.com").is_empty());
+    assert_eq!(serial(&zone), 2024010100);
+    assert!(!zone.journal.exists());
+
+    let request = parse(message(4, "example.com", &[
+        Entry::Empty("www.example.com", QueryType::A, ANY),
+        Entry::Empty("extra.example.com", QueryType::UNKNOWN(255), NONE),
+        Entry::Data(1, a("www.example.com", 2, 0)),
+        Entry::Data(1, a("www.example.com", 1, 0)),
+    ], &[add()]));
+    assert_eq!(update::apply(&mut zone, &request).unwrap(), ResultCode::NOERROR);
+    assert_eq!(addresses(&zone, "extra.example.com"), vec![Ipv4Addr::new(192, 0, 2, 7)]);
+}
+
+#[test]
+fn the_apex_and_cnames_are_protected() {
+    let (_files, mut zone) = zone("apex");
+    let request = parse(message(5, "example.com", &[], &[
+        Entry::Empty("example.com", QueryType::UNKNOWN(255), ANY),
+        Entry::Empty("example.com", QueryType::NS, ANY),
+        Entry::Data(NONE, Record::NS { domain: "example.com".to_string(), host: "ns.example.com".to_string(), ttl: 0 }),
+        // data next to a CNAME, and a CNAME next to data, are dropped
+        Entry::Data(1, a("mail.example.com", 5, 300)),
+        Entry::Data(1, Record::CNAME { domain: "www.example.com".to_string(), host: "ns.example.com".to_string(), ttl: 300 }),
+    ]));
+    assert_eq!(update::apply(&mut zone, &request).unwrap(), ResultCode::NOERROR);
+    // nothing changed, so the serial stays
+    assert_eq!(serial(&zone), 2024010100);
+    assert_eq!(zone.records("example.com").len(), 2);
+    assert!(matches!(zone.records("mail.example.com"), [Record::CNAME { .. }]));
+    assert_eq!(addresses(&zone, "www.example.com").len(), 2);
+
+    // one CNAME replaces another, and an explicit SOA sets the serial
+    let mut soa = zone.soa.clone();
+    if let Record::SOA { ref mut serial, .. } = soa {
+        *serial = 2024050100;
+    }
+    let request = parse(message(6, "example.com", &[], &[
+        Entry::Data(1, Record::CNAME { domain: "mail.example.com".to_string(), host: "ns.example.com".to_string(), ttl: 300 }),
+        Entry::Data(1, soa),
+    ]));
+    assert_eq!(update::apply(&mut zone, &request).unwrap(), ResultCode::NOERROR);
+    assert!(matches!(zone.records("mail.example.com"), [Record::CNAME { host, .. }] if host == "ns.example.com"));
+    assert_eq!(serial(&zone), 2024050100);
+
+    // updates can't name types that only live in messages
+    let request = parse(message(7, "example.com", &[], &[Entry::Empty("www.example.com", QueryType::UNKNOWN(252), ANY)]));
+    assert_eq!(update::apply(&mut zone, &request).unwrap(), ResultCode::FORMERR);
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[test]
+fn tsig_signs_and_checks() {
+    let key = Key::parse("hmac-sha256:update-key.:c2VjcmV0IHNoYXJlZCBieSBib3RoIGVuZHM=").unwrap();
+    assert_eq!(key.name, "update-key");
+    let keys = vec![key];
+    let mut request = message(7, "example.com", &[], &[Entry::Data(1, a("www.example.com", 3, 300))]);
+    request.seek(request.buf.len()).unwrap();
+    let mut tsig = Tsig::new(&keys[0], 7, now());
+    tsig.sign(Some(&keys[0]), &mut request, None).unwrap();
+    request.buf.truncate(request.pos());
+    let signed = tsig::check(&keys, &request.buf, None, now()).unwrap().unwrap();
+    assert_eq!(signed.error, 0);
+    assert_eq!(signed.tsig.mac.len(), 32);
+    // the TSIG record doesn't get in the way of reading the update
+    let mut copy = BytePacketBuffer::with_size(request.buf.len());
+    copy.buf.copy_from_slice(&request.buf);
+    assert_eq!(parse(copy).updates.len(), 1);
+
+    // a reply is signed over the request's MAC
+    let mut reply = BytePacketBuffer::uncompressed();
+    let mut header = Header::new();
+    header.id = 7;
+    header.response = true;
+    header.opcode = update::OPCODE_UPDATE;
+    header.write(&mut reply).unwrap();
+    let mut answer = Tsig::new(&keys[0], 7, now());
+    answer.sign(signed.key, &mut reply, Some(&signed.tsig.mac)).unwrap();
+    reply.buf.truncate(reply.pos());
+    assert_eq!(tsig::check(&keys, &reply.buf, Some(&signed.tsig.mac), now()).unwrap().unwrap().error, 0);
+    assert_eq!(tsig::check(&keys, &reply.buf, None, now()).unwrap().unwrap().error, tsig::BADSIG);
+
+    let mut tampered = request.buf.clone();
+    // `example` becomes `dxample`
+    tampered[13] ^= 1;
+    let checked = tsig::check(&keys, &tampered, None, now()).unwrap().unwrap();
+    assert_eq!(checked.error, tsig::BADSIG);
+    assert!(checked.key.is_none());
+    let other = vec![Key::new("other-key", "hmac-sha256", b"secret").unwrap()];
+    assert_eq!(tsig::check(&other, &request.buf, None, now()).unwrap().unwrap().error, tsig::BADKEY);
+    // outside the fudge the reply is still signed, to say so
+    let checked = tsig::check(&keys, &request.buf, None, now() + 3600).unwrap().unwrap();
+    assert_eq!(checked.error, tsig::BADTIME);
+    assert!(checked.key.is_some());
+
+    let unsigned = message(8, "example.com", &[], &[]);
+    assert!(tsig::check(&keys, &unsigned.buf, None, now()).unwrap().is_none());
+    assert!(Key::parse("hmac-md5:old-key:c2VjcmV0").is_err());
+}
@@ -0,0 +1,139 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mini_dns::byte_bucket_buffer::BytePacketBuffer;
+use mini_dns::packet::Packet;
+use mini_dns::query_type::QueryType;
+use mini_dns::record::Record;
+use mini_dns::upstream::{self, Upstreams};
+use mini_dns::utils::ResultCode;
+
+// answers every query with `addr` after `delay`, counting what it's asked
+fn server(addr: Ipv4Addr, delay: Duration, rescode: ResultCode) -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let local = socket.local_addr().unwrap();
+    let asked = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&asked);
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::new();
+        let (_, src) = socket.recv_from(&mut buffer.buf).unwrap();
+        counter.fetch_add(1, Ordering::SeqCst);
+        let mut packet = Packet::from_buffer(&mut buffer).unwrap();
+        thread::sleep(delay);
+        packet.header.response = true;
+        packet.header.rescode = rescode;
+        packet.resources.clear();
+        let domain = packet.questions[0].name.clone();
+        packet.answers.push(Record::A { domain, addr, ttl: 60 });
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        socket.send_to(&buffer.buf[0..buffer.pos()], src).unwrap();
+    });
+    (local, asked)
+}
+
+fn answer(packet: &Packet) -> Ipv4Addr {
+    match packet.answers.first() {
+        Some(Record::A { addr, .. }) => *addr,
+        other => panic!("expected an A record, got {:?}", other),
+    }
+}
+
+#[test]
+fn names_follow_the_longest_route() {
+    let (public, _) = server(Ipv4Addr::new(192, 0, 2, 1), Duration::ZERO, ResultCode::NOERROR);
+    let (corp, _) = server(Ipv4Addr::new(10, 0, 0, 1), Duration::ZERO, ResultCode::NOERROR);
+    let (lab, _) = server(Ipv4Addr::new(10, 9, 0, 1), Duration::ZERO, ResultCode::NOERROR);
+    let mut upstreams = Upstreams::new(vec![public]);
+    upstreams.route("corp.internal.", vec![corp]);
+    upstreams.route("lab.corp.internal", vec![lab]);
+    let ask = |name| answer(&upstreams.query(name, QueryType::A, false).unwrap());
+    assert_eq!(ask("www.example.com"), Ipv4Addr::new(192, 0, 2, 1));
+    assert_eq!(ask("corp.internal"), Ipv4Addr::new(10, 0, 0, 1));
+    assert_eq!(ask("wiki.corp.internal"), Ipv4Addr::new(10, 0, 0, 1));
+    assert_eq!(ask("host.lab.corp.internal"), Ipv4Addr::new(10, 9, 0, 1));
+    // a label boundary, not just a string suffix
+    assert_eq!(ask("notcorp.internal"), Ipv4Addr::new(192, 0, 2, 1));
+}
+
+#[test]
+fn silent_servers_fail_over() {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let (live, _) = server(Ipv4Addr::new(192, 0, 2, 1), Duration::ZERO, ResultCode::NOERROR);
+    let mut upstreams = Upstreams::new(vec![silent.local_addr().unwrap(), live]);
+    upstreams.timeout = Duration::from_millis(100);
+    // the silent server is untried, so it's asked first; after that its
+    // timeout counts against it
+    let mut waits = 0;
+    for _ in 0..4 {
+        let started = Instant::now();
+        assert_eq!(answer(&upstreams.query("www.example.com", QueryType::A, false).unwrap()), Ipv4Addr::new(192, 0, 2, 1));
+        if started.elapsed() >= upstreams.timeout {
+            waits += 1;
+        }
+    }
+    assert_eq!(waits, 1);
+}
+
+#[test]
+fn failing_servers_drop_out_until_they_answer() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let mut upstreams = Upstreams::new(vec![addr]);
+    upstreams.timeout = Duration::from_millis(50);
+    assert!(upstreams.query("www.example.com", QueryType::A, false).is_err());
+    assert!(upstreams.query("www.example.com", QueryType::A, false).is_err());
+    assert!(upstreams.servers_for("www.example.com")[0].is_down(Instant::now()));
+    // the health check puts it back once it answers again
+    thread::spawn(move || loop {
+        let mut buffer = BytePacketBuffer::new();
+        let (_, src) = socket.recv_from(&mut buffer.buf).unwrap();
+        let mut packet = Packet::from_buffer(&mut buffer).unwrap();
+        // the queries that went unanswered are still queued
+        if packet.questions[0].qtype != QueryType::NS {
+            continue;
+        }
+        packet.header.response = true;
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        socket.send_to(&buffer.buf[0..buffer.pos()], src).unwrap();
+    });
+    upstreams.check();
+    assert!(!upstreams.servers_for("www.example.com")[0].is_down(Instant::now()));
+}
+
+#[test]
+fn faster_servers_are_preferred() {
+    let (slow, slow_asked) = server(Ipv4Addr::new(192, 0, 2, 1), Duration::from_millis(50), ResultCode::NOERROR);
+    let (fast, fast_asked) = server(Ipv4Addr::new(192, 0, 2, 2), Duration::ZERO, ResultCode::NOERROR);
+    let upstreams = Upstreams::new(vec![slow, fast]);
+    for _ in 0..10 {
+        upstreams.query("www.example.com", QueryType::A, false).unwrap();
+    }
+    // each is tried once to learn how fast it is
+    assert_eq!(slow_asked.load(Ordering::SeqCst), 1);
+    assert_eq!(fast_asked.load(Ordering::SeqCst), 9);
+}
+
+#[test]
+fn refusals_are_passed_over() {
+    let (refusing, _) = server(Ipv4Addr::new(192, 0, 2, 1), Duration::ZERO, ResultCode::REFUSED);
+    let (willing, _) = server(Ipv4Addr::new(192, 0, 2, 2), Duration::ZERO, ResultCode::NOERROR);
+    let upstreams = Upstreams::new(vec![refusing, willing]);
+    let response = upstreams.query("www.example.com", QueryType::A, false).unwrap();
+    assert_eq!(answer(&response), Ipv4Addr::new(192, 0, 2, 2));
+    // with nobody better, the refusal is the answer
+    let alone = Upstreams::new(vec![refusing]);
+    assert_eq!(alone.query("www.example.com", QueryType::A, false).unwrap().header.rescode, ResultCode::REFUSED);
+}
+
+#[test]
+fn servers_default_to_port_53() {
+    assert_eq!(upstream::parse_server("192.0.2.1").unwrap(), "192.0.2.1:53".parse().unwrap());
+    assert_eq!(upstream::parse_server("192.0.2.1:5353").unwrap(), "192.0.2.1:5353".parse().unwrap());
+    assert_eq!(upstream::parse_server("2001:db8::1").unwrap(), "[2001:db8::1]:53".parse().unwrap());
+    assert!(upstream::parse_server("resolver.example").is_err());
+}